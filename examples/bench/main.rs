@@ -0,0 +1,93 @@
+use std::env;
+use std::error::Error;
+use std::f32;
+use std::time::{Duration, Instant};
+
+use glam::{Mat4, Vec3, Vec4};
+use rusterizer::image::Image;
+use rusterizer::mesh::{self, Vertex};
+use rusterizer::shader::ShaderProgram;
+use rusterizer::{CullFace, Framebuffer, Pipeline, PipelineOptions};
+
+const WIDTH: u32 = 640;
+const HEIGHT: u32 = 480;
+const FRAMES: u32 = 100;
+
+struct NormalProgram {
+    u_proj_view: Mat4,
+}
+
+impl ShaderProgram for NormalProgram {
+    type Attribute = Vertex;
+    type Varying = Vec3;
+    type Fragment = Vec4;
+
+    fn vertex(&self, attr: &Self::Attribute) -> (Vec4, Self::Varying) {
+        (self.u_proj_view * attr.pos.extend(1.0), attr.norm)
+    }
+
+    fn fragment(&self, _pos: Vec4, var: &Self::Varying) -> Vec4 {
+        let color = var.normalize() * 0.5 + Vec3::splat(0.5);
+        Vec4::new(color.x, color.y, color.z, 1.0)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let model_path = args.next().expect("USAGE: prog modelpath");
+
+    let (vertices, indices) = mesh::load_obj(&model_path)?;
+    let attributes: Vec<Vertex> = indices.iter().map(|&i| vertices[i as usize]).collect();
+    println!("{} triangles, {} frames", attributes.len() / 3, FRAMES);
+
+    let proj = Mat4::perspective_rh_gl(
+        WIDTH as f32 / HEIGHT as f32,
+        f32::consts::PI / 4.0,
+        0.1,
+        10.0,
+    );
+
+    let mut color_image = Image::new(WIDTH, HEIGHT);
+    let mut depth_image = Image::new(WIDTH, HEIGHT);
+
+    let pipeline = Pipeline::with_options(PipelineOptions {
+        cull_face: CullFace::Back,
+        ..PipelineOptions::default()
+    });
+
+    let mut total = Duration::ZERO;
+    let mut fastest = Duration::MAX;
+
+    for frame in 0..FRAMES {
+        // Orbit the camera so triangles land in different tiles every frame
+        let t = frame as f32 / FRAMES as f32 * 2.0 * f32::consts::PI;
+        let view = Mat4::look_at_rh(
+            Vec3::new(3.0 * t.sin(), 0.0, 3.0 * t.cos()),
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+        );
+        let shader = NormalProgram {
+            u_proj_view: proj * view,
+        };
+
+        color_image.clear_rgba([0, 0, 0, 255]);
+        depth_image.clear_depth(1.0);
+
+        let frame_start_time = Instant::now();
+        let mut images_color = [&mut color_image];
+        let mut framebuffer = Framebuffer::new(&mut images_color, &mut depth_image);
+        #[cfg(feature = "rayon")]
+        pipeline.draw_parallel(&shader, &attributes, &mut framebuffer);
+        #[cfg(not(feature = "rayon"))]
+        pipeline.draw(&shader, &attributes, &mut framebuffer);
+
+        let draw_duration = frame_start_time.elapsed();
+        total += draw_duration;
+        fastest = fastest.min(draw_duration);
+    }
+
+    println!("mean frame time: {:?}", total / FRAMES);
+    println!("fastest frame time: {:?}", fastest);
+
+    Ok(())
+}
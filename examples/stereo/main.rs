@@ -0,0 +1,94 @@
+use std::env;
+use std::error::Error;
+use std::f32;
+
+use glam::{Mat4, Vec3, Vec4};
+use rusterizer::image::Image;
+use rusterizer::mesh::{self, Vertex};
+use rusterizer::shader::ShaderProgram;
+use rusterizer::{CullFace, Framebuffer, Pipeline, PipelineOptions, Viewport};
+
+// Dimensions of one eye, the image is twice as wide
+const WIDTH: u32 = 480;
+const HEIGHT: u32 = 480;
+
+// Distance between the eyes, in the units of the model
+const EYE_SEPARATION: f32 = 0.12;
+
+/// Outputs world space positions, which `Pipeline::draw_multiview` then
+/// transforms for each eye, so the vertices are only shaded once.
+struct WorldNormalProgram;
+
+impl ShaderProgram for WorldNormalProgram {
+    type Attribute = Vertex;
+    type Varying = Vec3;
+    type Fragment = Vec4;
+
+    fn vertex(&self, attr: &Self::Attribute) -> (Vec4, Self::Varying) {
+        (attr.pos.extend(1.0), attr.norm)
+    }
+
+    fn fragment(&self, _pos: Vec4, var: &Self::Varying) -> Vec4 {
+        let color = var.normalize() * 0.5 + Vec3::splat(0.5);
+        Vec4::new(color.x, color.y, color.z, 1.0)
+    }
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let mut args = env::args().skip(1);
+    let model_path = args.next().expect("USAGE: prog modelpath outpath");
+    let out_path = args.next().expect("USAGE: prog modelpath outpath");
+
+    let (vertices, indices) = mesh::load_obj(&model_path)?;
+    let attributes: Vec<Vertex> = indices.iter().map(|&i| vertices[i as usize]).collect();
+
+    let proj = Mat4::perspective_rh_gl(
+        f32::consts::PI / 4.0,
+        WIDTH as f32 / HEIGHT as f32,
+        0.1,
+        10.0,
+    );
+
+    // Parallel eyes, shifted sideways from the center, left eye on the left
+    let eye = |offset: f32| {
+        let shift = Vec3::new(offset, 0.0, 0.0);
+        proj * Mat4::look_at_rh(Vec3::new(0.0, 0.0, 3.0) + shift, shift, Vec3::Y)
+    };
+    let views = [
+        (
+            Viewport {
+                x: 0,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT,
+            },
+            eye(-EYE_SEPARATION / 2.0),
+        ),
+        (
+            Viewport {
+                x: WIDTH,
+                y: 0,
+                width: WIDTH,
+                height: HEIGHT,
+            },
+            eye(EYE_SEPARATION / 2.0),
+        ),
+    ];
+
+    let mut color_image = Image::from_pixel_rgba(2 * WIDTH, HEIGHT, [0, 0, 0, 255]);
+    let mut depth_image = Image::from_pixel_depth(2 * WIDTH, HEIGHT, 1.0);
+
+    let pipeline = Pipeline::with_options(PipelineOptions {
+        cull_face: CullFace::Back,
+        ..PipelineOptions::default()
+    });
+
+    let mut images_color = [&mut color_image];
+    let mut framebuffer = Framebuffer::new(&mut images_color, &mut depth_image);
+    let stats = pipeline.draw_multiview(&WorldNormalProgram, &attributes, &views, &mut framebuffer);
+    println!("{:?}", stats);
+
+    color_image.save_png(&out_path)?;
+
+    Ok(())
+}
@@ -6,14 +6,12 @@ use std::time::{Duration, Instant};
 
 use glam::{Mat4, Vec2, Vec3, Vec4};
 use rusterizer::image::Image;
+use rusterizer::mesh::{self, Vertex};
 use rusterizer::shader::{ShaderProgram, Smooth};
 use rusterizer::{CullFace, Pipeline, PipelineOptions};
 
 // TODO(yan): Rustfmt doesn't like these paths in 1.50.0
 #[rustfmt::skip]
-#[path = "../attr.rs"]
-mod attr;
-#[rustfmt::skip]
 #[path = "../loader.rs"]
 mod loader;
 
@@ -35,16 +33,6 @@ struct Varying {
     pub light_intensity: f32,
 }
 
-impl Default for Varying {
-    fn default() -> Varying {
-        Varying {
-            norm: Vec3::ZERO,
-            uv: Vec2::ZERO,
-            light_intensity: 0.0,
-        }
-    }
-}
-
 impl Smooth for Varying {
     fn interpolate(a: &Varying, b: &Varying, c: &Varying, bc: Vec3) -> Varying {
         Varying {
@@ -61,6 +49,8 @@ impl Smooth for Varying {
 }
 
 struct SimpleProgram {
+    vertices: Vec<Vertex>,
+    indices: Vec<u32>,
     u_proj: Mat4,
     u_view: Mat4,
     u_light_dir: Vec3,
@@ -68,8 +58,17 @@ struct SimpleProgram {
 }
 
 impl SimpleProgram {
-    pub fn with_uniforms(proj: Mat4, view: Mat4, light_dir: Vec3, tex: Image) -> SimpleProgram {
+    pub fn with_uniforms(
+        vertices: Vec<Vertex>,
+        indices: Vec<u32>,
+        proj: Mat4,
+        view: Mat4,
+        light_dir: Vec3,
+        tex: Image,
+    ) -> SimpleProgram {
         SimpleProgram {
+            vertices,
+            indices,
             u_proj: proj,
             u_view: view,
             u_light_dir: light_dir,
@@ -83,20 +82,25 @@ impl SimpleProgram {
 }
 
 impl ShaderProgram for SimpleProgram {
-    type Attribute = attr::Attribute;
+    // Vertices are pulled from the mesh through its index buffer
+    type Attribute = u32;
     type Varying = Varying;
+    type Fragment = Vec4;
 
-    fn vertex(&self, attr: &Self::Attribute, var: &mut Self::Varying) -> Vec4 {
-        let normal = attr.norm.normalize();
+    fn vertex(&self, index: &u32) -> (Vec4, Self::Varying) {
+        let vertex = &self.vertices[self.indices[*index as usize] as usize];
+        let normal = vertex.norm.normalize();
         let light_intensity = normal.dot(self.u_light_dir);
 
-        var.norm = normal;
-        var.uv = attr.uv;
-        var.light_intensity = light_intensity;
+        let var = Varying {
+            norm: normal,
+            uv: vertex.uv,
+            light_intensity,
+        };
 
         let m = self.u_proj * self.u_view;
 
-        m * attr.pos
+        (m * vertex.pos.extend(1.0), var)
     }
 
     fn fragment(&self, _pos: Vec4, var: &Self::Varying) -> Vec4 {
@@ -116,7 +120,8 @@ fn main() -> Result<(), Box<dyn Error>> {
     let mut depth_image = Image::from_pixel_depth(WIDTH, HEIGHT, depth());
 
     let texture = loader::load_image(&tex_path)?;
-    let attributes = loader::load_model(&model_path)?;
+    let (vertices, indices) = mesh::load_obj(&model_path)?;
+    let vertex_count = indices.len() as u32;
 
     let proj = Mat4::perspective_rh_gl(
         WIDTH as f32 / HEIGHT as f32,
@@ -131,7 +136,14 @@ fn main() -> Result<(), Box<dyn Error>> {
         Vec3::new(0.0, 1.0, 0.0),
     );
 
-    let mut shader = SimpleProgram::with_uniforms(proj, view, Vec3::new(0.0, 0.0, 1.0), texture);
+    let mut shader = SimpleProgram::with_uniforms(
+        vertices,
+        indices,
+        proj,
+        view,
+        Vec3::new(0.0, 0.0, 1.0),
+        texture,
+    );
 
     let pipeline = Pipeline::with_options(PipelineOptions {
         cull_face: CullFace::Back,
@@ -157,7 +169,7 @@ fn main() -> Result<(), Box<dyn Error>> {
 
         color_image.clear_rgba(black());
         depth_image.clear_depth(depth());
-        pipeline.triangles(&shader, &attributes, &mut color_image, &mut depth_image);
+        pipeline.triangles_by_index(&shader, vertex_count, &mut color_image, &mut depth_image);
 
         let output = render(&color_image);
 
@@ -0,0 +1,100 @@
+//! Renders a spinning cube into an image that `index.html` draws into a
+//! canvas. Uses no JS bindings crate, the page calls the exported functions
+//! and reads the pixels straight out of the module memory.
+
+use std::cell::RefCell;
+use std::f32;
+
+use glam::{Mat4, Vec3, Vec4};
+use rusterizer::image::Image;
+use rusterizer::shaders::{UnlitColor, UnlitColorAttribute};
+use rusterizer::{CullFace, Pipeline, PipelineOptions};
+
+struct State {
+    color_image: Image,
+    depth_image: Image,
+    pipeline: Pipeline,
+    attributes: Vec<UnlitColorAttribute>,
+}
+
+thread_local! {
+    static STATE: RefCell<Option<State>> = const { RefCell::new(None) };
+}
+
+/// Allocates the images for a WIDTH x HEIGHT canvas. Call before `render`.
+#[no_mangle]
+pub extern "C" fn init(width: u32, height: u32) {
+    let state = State {
+        color_image: Image::new(width, height),
+        depth_image: Image::new(width, height),
+        pipeline: Pipeline::with_options(PipelineOptions {
+            cull_face: CullFace::Back,
+            ..PipelineOptions::default()
+        }),
+        attributes: cube(),
+    };
+
+    STATE.with(|s| *s.borrow_mut() = Some(state));
+}
+
+/// Renders the cube at TIME seconds and returns a pointer to its RGBA
+/// bytes, row by row from the top, valid until the next call.
+#[no_mangle]
+pub extern "C" fn render(time: f32) -> *const u8 {
+    STATE.with(|s| {
+        let mut s = s.borrow_mut();
+        let state = s.as_mut().expect("init must be called before render");
+        let (width, height) = state.color_image.dimensions();
+
+        let proj = Mat4::perspective_rh_gl(
+            f32::consts::PI / 4.0,
+            width as f32 / height as f32,
+            0.1,
+            10.0,
+        );
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 1.5, 3.0), Vec3::ZERO, Vec3::Y);
+        let model = Mat4::from_rotation_y(time) * Mat4::from_rotation_x(time * 0.7);
+        let shader = UnlitColor::new(proj * view * model);
+
+        state.color_image.clear_rgba([0, 0, 0, 255]);
+        state.depth_image.clear_depth(1.0);
+        state.pipeline.triangles(
+            &shader,
+            &state.attributes,
+            &mut state.color_image,
+            &mut state.depth_image,
+        );
+
+        state.color_image.as_rgba8_bytes().as_ptr()
+    })
+}
+
+/// Returns the triangles of a unit cube with a different color per face,
+/// counter-clockwise from the outside.
+fn cube() -> Vec<UnlitColorAttribute> {
+    let faces = [
+        (Vec3::X, Vec3::Y, Vec4::new(1.0, 0.2, 0.2, 1.0)),
+        (-Vec3::X, Vec3::Y, Vec4::new(0.2, 1.0, 1.0, 1.0)),
+        (Vec3::Y, Vec3::Z, Vec4::new(0.2, 1.0, 0.2, 1.0)),
+        (-Vec3::Y, Vec3::Z, Vec4::new(1.0, 0.2, 1.0, 1.0)),
+        (Vec3::Z, Vec3::X, Vec4::new(0.2, 0.2, 1.0, 1.0)),
+        (-Vec3::Z, Vec3::X, Vec4::new(1.0, 1.0, 0.2, 1.0)),
+    ];
+
+    let mut attributes = Vec::new();
+    for &(normal, up, color) in &faces {
+        let right = up.cross(normal);
+        let corner = |u: f32, v: f32| (normal + right * u + up * v) * 0.5;
+        let positions = [
+            corner(-1.0, -1.0),
+            corner(1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, -1.0),
+            corner(1.0, 1.0),
+            corner(-1.0, 1.0),
+        ];
+        attributes.extend(UnlitColorAttribute::solid(&positions, color));
+    }
+
+    attributes
+}
@@ -0,0 +1,311 @@
+use glam::{const_vec3, Mat4, Vec3, Vec4};
+
+use crate::DepthConvention;
+
+/// Sphere bounding an object, e.g. to reject it with `Frustum`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sphere {
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+/// Axis aligned box bounding an object, with inclusive MIN and MAX corners.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vec3,
+    pub max: Vec3,
+}
+
+impl Aabb {
+    /// Box containing no points, with MIN above MAX. It is the identity of
+    /// `union` and intersects nothing.
+    pub const EMPTY: Aabb = Aabb {
+        min: const_vec3!([f32::INFINITY; 3]),
+        max: const_vec3!([f32::NEG_INFINITY; 3]),
+    };
+
+    /// Returns the smallest box containing POINTS, or `Aabb::EMPTY` if
+    /// there are none.
+    pub fn from_points(points: &[Vec3]) -> Aabb {
+        points.iter().fold(Aabb::EMPTY, |aabb, &point| Aabb {
+            min: aabb.min.min(point),
+            max: aabb.max.max(point),
+        })
+    }
+
+    /// Returns true if the box contains no points, i.e. MIN is above MAX on
+    /// some axis.
+    pub fn is_empty(&self) -> bool {
+        !(self.min.x <= self.max.x && self.min.y <= self.max.y && self.min.z <= self.max.z)
+    }
+
+    /// Returns the smallest box containing both boxes.
+    pub fn union(&self, other: &Aabb) -> Aabb {
+        if self.is_empty() {
+            return *other;
+        }
+        if other.is_empty() {
+            return *self;
+        }
+
+        Aabb {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+        }
+    }
+
+    /// Returns the smallest box containing the box transformed by the affine
+    /// TRANSFORM, e.g. to move bounds of a mesh to world space.
+    pub fn transform(&self, transform: Mat4) -> Aabb {
+        if self.is_empty() {
+            return Aabb::EMPTY;
+        }
+
+        // Each axis of the transform moves the extent along all of the
+        // axes, by the absolute value of its components
+        let center = transform.transform_point3((self.min + self.max) / 2.0);
+        let extent = (self.max - self.min) / 2.0;
+        let extent = transform.x_axis.truncate().abs() * extent.x
+            + transform.y_axis.truncate().abs() * extent.y
+            + transform.z_axis.truncate().abs() * extent.z;
+
+        Aabb {
+            min: center - extent,
+            max: center + extent,
+        }
+    }
+
+    /// Returns the eight corners of the box, with the bits of the index
+    /// selecting MAX over MIN for X, Y and Z, in that order from the lowest.
+    pub fn corners(&self) -> [Vec3; 8] {
+        let mut corners = [self.min; 8];
+        for (i, corner) in corners.iter_mut().enumerate() {
+            if i & 1 != 0 {
+                corner.x = self.max.x;
+            }
+            if i & 2 != 0 {
+                corner.y = self.max.y;
+            }
+            if i & 4 != 0 {
+                corner.z = self.max.z;
+            }
+        }
+
+        corners
+    }
+}
+
+/// Planes of the view volume of a transform, pointing inwards.
+///
+/// Tests are conservative: they can report an intersection for a volume
+/// just outside of a corner of the frustum, but never miss one.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Frustum {
+    /// Left, right, bottom, top, near and far planes. Points P inside the
+    /// frustum have `plane.truncate().dot(P) + plane.w >= 0` for all planes.
+    pub planes: [Vec4; 6],
+}
+
+impl Frustum {
+    /// Extracts the planes of the view volume of TRANSFORM, which maps to
+    /// clip space, e.g. a model-view-projection matrix. The planes are then
+    /// in the space TRANSFORM maps from.
+    ///
+    /// Planes are normalized so that tests measure distances. Planes that
+    /// degenerate transforms collapse to no normal are kept as they are, and
+    /// then pass or reject everything by the sign of their W.
+    pub fn from_matrix(transform: Mat4) -> Frustum {
+        Frustum::with_depth_convention(transform, DepthConvention::NegativeOneToOne)
+    }
+
+    /// Like `from_matrix`, but for TRANSFORM producing NDC depth in
+    /// CONVENTION, which moves the near plane.
+    pub fn with_depth_convention(transform: Mat4, convention: DepthConvention) -> Frustum {
+        // Clip space points are inside if -w <= x, y <= w and -w <= z <= w,
+        // or 0 <= z <= w
+        let (x, y, z, w) = (
+            transform.row(0),
+            transform.row(1),
+            transform.row(2),
+            transform.row(3),
+        );
+
+        let near = match convention {
+            DepthConvention::NegativeOneToOne => w + z,
+            DepthConvention::ZeroToOne => z,
+        };
+
+        let mut planes = [w + x, w - x, w + y, w - y, near, w - z];
+        for plane in &mut planes {
+            let length = plane.truncate().length();
+            if length > 0.0 {
+                *plane /= length;
+            }
+        }
+
+        Frustum { planes }
+    }
+
+    /// Returns false if SPHERE is entirely outside of the frustum.
+    pub fn intersects_sphere(&self, sphere: &Sphere) -> bool {
+        self.planes
+            .iter()
+            .all(|plane| plane.truncate().dot(sphere.center) + plane.w >= -sphere.radius)
+    }
+
+    /// Returns false if AABB is entirely outside of the frustum, or empty.
+    pub fn intersects_aabb(&self, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        let center = (aabb.min + aabb.max) / 2.0;
+        let extent = (aabb.max - aabb.min) / 2.0;
+
+        self.planes.iter().all(|plane| {
+            // Distance from the center to the corner furthest along the
+            // plane normal
+            let radius = extent.dot(plane.truncate().abs());
+            plane.truncate().dot(center) + plane.w >= -radius
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Frustum of the box from -1 to 1 on X and Y, and from -3 to -1 on Z,
+    /// whose planes are exact in floats.
+    fn box_frustum() -> Frustum {
+        Frustum::from_matrix(Mat4::orthographic_rh_gl(-1.0, 1.0, -1.0, 1.0, 1.0, 3.0))
+    }
+
+    /// Returns points just outside of each plane of `box_frustum` by
+    /// DISTANCE.
+    fn outside_each_plane(distance: f32) -> [Vec3; 6] {
+        [
+            Vec3::new(-1.0 - distance, 0.0, -2.0),
+            Vec3::new(1.0 + distance, 0.0, -2.0),
+            Vec3::new(0.0, -1.0 - distance, -2.0),
+            Vec3::new(0.0, 1.0 + distance, -2.0),
+            Vec3::new(0.0, 0.0, -1.0 + distance),
+            Vec3::new(0.0, 0.0, -3.0 - distance),
+        ]
+    }
+
+    #[test]
+    fn empty_aabb() {
+        assert_eq!(Aabb::from_points(&[]), Aabb::EMPTY);
+        assert!(Aabb::EMPTY.is_empty());
+        assert_eq!(Aabb::EMPTY.transform(Mat4::IDENTITY), Aabb::EMPTY);
+        assert!(!box_frustum().intersects_aabb(&Aabb::EMPTY));
+
+        let aabb = Aabb::from_points(&[Vec3::new(1.0, -2.0, 3.0), Vec3::new(-1.0, 2.0, 0.0)]);
+        assert_eq!(aabb.min, Vec3::new(-1.0, -2.0, 0.0));
+        assert_eq!(aabb.max, Vec3::new(1.0, 2.0, 3.0));
+        assert_eq!(aabb.union(&Aabb::EMPTY), aabb);
+        assert_eq!(Aabb::EMPTY.union(&aabb), aabb);
+
+        // A single point is a box, not an empty one
+        let point = Aabb::from_points(&[Vec3::ONE]);
+        assert!(!point.is_empty());
+        assert_eq!(point.union(&Aabb::EMPTY), point);
+    }
+
+    #[test]
+    fn transform_bounds_rotated_corners() {
+        let aabb = Aabb {
+            min: Vec3::new(-1.0, 0.0, 2.0),
+            max: Vec3::new(3.0, 1.0, 2.5),
+        };
+        let transform = Mat4::from_translation(Vec3::new(5.0, -1.0, 0.5))
+            * Mat4::from_rotation_z(0.5)
+            * Mat4::from_rotation_x(-1.2)
+            * Mat4::from_scale(Vec3::new(1.0, 2.0, 0.5));
+
+        let corners: Vec<Vec3> = aabb
+            .corners()
+            .iter()
+            .map(|&corner| transform.transform_point3(corner))
+            .collect();
+        let expected = Aabb::from_points(&corners);
+        let transformed = aabb.transform(transform);
+        assert!(transformed.min.abs_diff_eq(expected.min, 1e-5));
+        assert!(transformed.max.abs_diff_eq(expected.max, 1e-5));
+    }
+
+    #[test]
+    fn volumes_touching_planes_are_kept() {
+        let frustum = box_frustum();
+        let (touching, outside) = (outside_each_plane(0.5), outside_each_plane(0.501));
+        for (&center, &outside) in touching.iter().zip(&outside) {
+            let sphere = Sphere {
+                center,
+                radius: 0.5,
+            };
+            let aabb = Aabb {
+                min: center - Vec3::splat(0.5),
+                max: center + Vec3::splat(0.5),
+            };
+            assert!(frustum.intersects_sphere(&sphere), "sphere at {}", center);
+            assert!(frustum.intersects_aabb(&aabb), "box at {}", center);
+
+            let sphere = Sphere {
+                center: outside,
+                ..sphere
+            };
+            let aabb = Aabb {
+                min: outside - Vec3::splat(0.5),
+                max: outside + Vec3::splat(0.5),
+            };
+            assert!(!frustum.intersects_sphere(&sphere), "sphere at {}", outside);
+            assert!(!frustum.intersects_aabb(&aabb), "box at {}", outside);
+        }
+
+        // Points are spheres of zero radius and empty extent
+        for &point in &outside_each_plane(0.0) {
+            let sphere = Sphere {
+                center: point,
+                radius: 0.0,
+            };
+            assert!(frustum.intersects_sphere(&sphere), "point {}", point);
+            assert!(
+                frustum.intersects_aabb(&Aabb::from_points(&[point])),
+                "point {}",
+                point
+            );
+        }
+    }
+
+    #[test]
+    fn degenerate_matrix_is_conservative() {
+        let frustum = Frustum::from_matrix(Mat4::ZERO);
+        let sphere = Sphere {
+            center: Vec3::new(100.0, -5.0, 3.0),
+            radius: 0.0,
+        };
+        assert!(frustum.intersects_sphere(&sphere));
+        assert!(frustum.intersects_aabb(&Aabb::from_points(&[sphere.center])));
+        assert!(!frustum.intersects_aabb(&Aabb::EMPTY));
+
+        // Planes are not normalized into NaN
+        assert!(frustum.planes.iter().all(|plane| *plane == Vec4::ZERO));
+    }
+
+    #[test]
+    fn depth_convention_moves_near_plane() {
+        let proj = Mat4::perspective_rh(std::f32::consts::FRAC_PI_2, 1.0, 1.0, 10.0);
+        let frustum = Frustum::with_depth_convention(proj, DepthConvention::ZeroToOne);
+        assert!(frustum.planes[4].abs_diff_eq(Vec4::new(0.0, 0.0, -1.0, -1.0), 1e-6));
+
+        // In front of the near plane at Z = -1, but behind the one of the
+        // matrix read as producing NDC depth in [-1..1]
+        let sphere = Sphere {
+            center: Vec3::new(0.0, 0.0, -0.9),
+            radius: 0.05,
+        };
+        assert!(!frustum.intersects_sphere(&sphere));
+        assert!(Frustum::from_matrix(proj).intersects_sphere(&sphere));
+    }
+}
@@ -0,0 +1,169 @@
+use glam::{Vec3, Vec4};
+
+use crate::convert;
+
+/// Decodes sRGB encoded COLOR with components in [0..1] to linear.
+pub fn srgb_to_linear(color: Vec3) -> Vec3 {
+    Vec3::new(
+        convert::srgb_to_linear(color.x),
+        convert::srgb_to_linear(color.y),
+        convert::srgb_to_linear(color.z),
+    )
+}
+
+/// Encodes linear COLOR with components in [0..1] to sRGB.
+pub fn linear_to_srgb(color: Vec3) -> Vec3 {
+    Vec3::new(
+        convert::linear_to_srgb(color.x),
+        convert::linear_to_srgb(color.y),
+        convert::linear_to_srgb(color.z),
+    )
+}
+
+/// Converts hue, saturation and value to RGB. The hue is in turns, so that
+/// 0.0 and 1.0 are both red, and wraps around outside of [0..1).
+///
+/// HSV and HSL are defined on the RGB values given, which are usually sRGB
+/// encoded, like colors picked in image editors.
+pub fn hsv_to_rgb(hsv: Vec3) -> Vec3 {
+    let (hue, saturation, value) = (hsv.x, hsv.y, hsv.z);
+    let channel = |n: f32| {
+        let k = (n + hue * 6.0).rem_euclid(6.0);
+        value - value * saturation * k.min(4.0 - k).clamp(0.0, 1.0)
+    };
+
+    Vec3::new(channel(5.0), channel(3.0), channel(1.0))
+}
+
+/// Converts RGB to hue, saturation and value, see `hsv_to_rgb`. The hue is
+/// in [0..1), grays have hue and saturation zero.
+pub fn rgb_to_hsv(rgb: Vec3) -> Vec3 {
+    let (max, chroma) = max_chroma(rgb);
+    let saturation = if max > 0.0 { chroma / max } else { 0.0 };
+
+    Vec3::new(hue(rgb, max, chroma), saturation, max)
+}
+
+/// Converts hue, saturation and lightness to RGB, see `hsv_to_rgb`.
+pub fn hsl_to_rgb(hsl: Vec3) -> Vec3 {
+    let (hue, saturation, lightness) = (hsl.x, hsl.y, hsl.z);
+    let a = saturation * lightness.min(1.0 - lightness);
+    let channel = |n: f32| {
+        let k = (n + hue * 12.0).rem_euclid(12.0);
+        lightness - a * (k - 3.0).min(9.0 - k).clamp(-1.0, 1.0)
+    };
+
+    Vec3::new(channel(0.0), channel(8.0), channel(4.0))
+}
+
+/// Converts RGB to hue, saturation and lightness, see `hsv_to_rgb`. The hue
+/// is in [0..1), grays have hue and saturation zero.
+pub fn rgb_to_hsl(rgb: Vec3) -> Vec3 {
+    let (max, chroma) = max_chroma(rgb);
+    let lightness = max - chroma / 2.0;
+    let saturation = if lightness > 0.0 && lightness < 1.0 {
+        chroma / (1.0 - (2.0 * lightness - 1.0).abs())
+    } else {
+        0.0
+    };
+
+    Vec3::new(hue(rgb, max, chroma), saturation, lightness)
+}
+
+/// Returns the fully saturated, brightest RGB color of HUE, e.g. for a
+/// rainbow.
+pub fn hue_to_rgb(hue: f32) -> Vec3 {
+    hsv_to_rgb(Vec3::new(hue, 1.0, 1.0))
+}
+
+/// Returns the opaque RGBA color of HUE, SATURATION and VALUE.
+pub fn hsv(hue: f32, saturation: f32, value: f32) -> Vec4 {
+    hsv_to_rgb(Vec3::new(hue, saturation, value)).extend(1.0)
+}
+
+fn max_chroma(rgb: Vec3) -> (f32, f32) {
+    let max = rgb.max_element();
+    (max, max - rgb.min_element())
+}
+
+fn hue(rgb: Vec3, max: f32, chroma: f32) -> f32 {
+    if chroma <= 0.0 {
+        return 0.0;
+    }
+
+    let sector = if max == rgb.x {
+        ((rgb.y - rgb.z) / chroma).rem_euclid(6.0)
+    } else if max == rgb.y {
+        (rgb.z - rgb.x) / chroma + 2.0
+    } else {
+        (rgb.x - rgb.y) / chroma + 4.0
+    };
+
+    // Tiny negative sectors wrap to 6.0 in floats
+    let hue = sector / 6.0;
+    if hue >= 1.0 {
+        0.0
+    } else {
+        hue
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hue_wraps_around() {
+        let red = Vec3::new(1.0, 0.0, 0.0);
+        for &hue in &[0.0, 1.0, -1.0, 2.0] {
+            assert_eq!(hue_to_rgb(hue), red, "hue {}", hue);
+            assert_eq!(hsl_to_rgb(Vec3::new(hue, 1.0, 0.5)), red, "hue {}", hue);
+        }
+        assert!(hue_to_rgb(-1.0 / 6.0).abs_diff_eq(Vec3::new(1.0, 0.0, 1.0), 1e-6));
+        assert!(hue_to_rgb(7.0 / 6.0).abs_diff_eq(Vec3::new(1.0, 1.0, 0.0), 1e-6));
+
+        // Reds leaning towards magenta are just below 1.0, not negative
+        let hue = rgb_to_hsv(Vec3::new(1.0, 0.0, 0.01)).x;
+        assert!(hue > 0.99 && hue < 1.0, "hue {}", hue);
+        let hue = rgb_to_hsl(Vec3::new(1.0, 0.0, 1e-7)).x;
+        assert!((0.0..1.0).contains(&hue), "hue {}", hue);
+        assert_eq!(rgb_to_hsv(red).x, 0.0);
+    }
+
+    #[test]
+    fn grays_have_no_hue() {
+        for &value in &[0.0, 0.25, 0.5, 1.0] {
+            let gray = Vec3::splat(value);
+            assert_eq!(rgb_to_hsv(gray), Vec3::new(0.0, 0.0, value));
+            assert_eq!(rgb_to_hsl(gray), Vec3::new(0.0, 0.0, value));
+            for &hue in &[0.0, 0.3, 0.9] {
+                assert_eq!(hsv_to_rgb(Vec3::new(hue, 0.0, value)), gray);
+                assert_eq!(hsl_to_rgb(Vec3::new(hue, 0.0, value)), gray);
+            }
+        }
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let mut seed = 0x2545_f491_u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        for _ in 0..1000 {
+            let rgb = Vec3::new(random(), random(), random());
+            let hsv = hsv_to_rgb(rgb_to_hsv(rgb));
+            let hsl = hsl_to_rgb(rgb_to_hsl(rgb));
+            let srgb = srgb_to_linear(linear_to_srgb(rgb));
+            assert!(hsv.abs_diff_eq(rgb, 1e-5), "{} through HSV is {}", rgb, hsv);
+            assert!(hsl.abs_diff_eq(rgb, 1e-5), "{} through HSL is {}", rgb, hsl);
+            assert!(
+                srgb.abs_diff_eq(rgb, 1e-5),
+                "{} through sRGB is {}",
+                rgb,
+                srgb
+            );
+        }
+    }
+}
@@ -1,6 +1,8 @@
 use std::convert::TryInto;
 use std::fmt::Debug;
 
+use glam::Vec4;
+
 /// Losslessly converts `n` to `usize` using `TryFrom` or panics.
 ///
 /// # Panics
@@ -13,3 +15,67 @@ where
 {
     n.try_into().expect("Expected N to fit in usize")
 }
+
+/// Converts 8-bit RGBA to a color with components in [0..1].
+pub fn rgba_to_vec(pixel: [u8; 4]) -> Vec4 {
+    Vec4::new(
+        f32::from(pixel[0]) / 255.0,
+        f32::from(pixel[1]) / 255.0,
+        f32::from(pixel[2]) / 255.0,
+        f32::from(pixel[3]) / 255.0,
+    )
+}
+
+/// Quantizes a color with components in [0..1] to 8-bit RGBA, rounding to
+/// the nearest value, so that `rgba_to_vec` roundtrips exactly.
+pub fn vec_to_rgba(color: Vec4) -> [u8; 4] {
+    [
+        (color.x.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color.y.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color.z.clamp(0.0, 1.0) * 255.0).round() as u8,
+        (color.w.clamp(0.0, 1.0) * 255.0).round() as u8,
+    ]
+}
+
+/// Decodes an sRGB encoded value in [0..1] to linear.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encodes a linear value in [0..1] to sRGB.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.003_130_8 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rgba_roundtrip() {
+        for v in 0..=255 {
+            let pixel = [v, 255 - v, v, 255 - v];
+            assert_eq!(vec_to_rgba(rgba_to_vec(pixel)), pixel);
+        }
+    }
+
+    #[test]
+    fn vec_to_rgba_rounds_to_nearest() {
+        assert_eq!(
+            vec_to_rgba(Vec4::new(0.5 / 255.0, 0.49 / 255.0, 1.0, 2.0)),
+            [1, 0, 255, 255]
+        );
+        assert_eq!(
+            vec_to_rgba(Vec4::new(-1.0, 254.6 / 255.0, 0.5, 0.0)),
+            [0, 255, 128, 0]
+        );
+    }
+}
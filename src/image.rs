@@ -1,143 +1,553 @@
+pub mod filter;
+
+mod diff;
+mod draw;
+mod pattern;
+
 use std::fmt::Debug;
+use std::iter;
+use std::marker::PhantomData;
+use std::ops::{Deref, Index, IndexMut, Range};
 use std::slice;
 
-use glam::{Vec2, Vec4};
+use glam::{Vec2, Vec3, Vec4};
 
-use crate::convert::cast_usize;
+use crate::convert::{cast_usize, linear_to_srgb, rgba_to_vec, srgb_to_linear, vec_to_rgba};
+use crate::CompareFunc;
 
-#[derive(Debug, PartialEq, Clone)]
-pub struct Image {
-    width: usize,
-    height: usize,
-    buffer: Vec<u32>,
+pub use self::diff::{assert_images_match, DiffStats};
+pub use self::draw::DrawMode;
+
+/// Format of the pixels of an `Image`, defining how they are stored.
+pub trait Pixel: Copy + Debug + PartialEq {
+    /// Word stored in the image buffer for each pixel. Formats with the same
+    /// storage can be reinterpreted as each other, see `Image::reinterpret`.
+    type Storage: Copy + Debug + PartialEq;
+
+    /// Value of the pixels of a newly created image.
+    const CLEAR: Self;
+
+    fn encode(self) -> Self::Storage;
+    fn decode(storage: Self::Storage) -> Self;
 }
 
-impl Image {
-    pub fn new(width: u32, height: u32) -> Image {
-        let w = cast_usize(width);
-        let h = cast_usize(height);
+/// Raw storage word. Used by the default `Image`, whose pixels are
+/// interpreted as RGBA, depth or stencil by the accessor used.
+impl Pixel for u32 {
+    type Storage = u32;
+    const CLEAR: u32 = 0;
 
-        Image {
-            width: w,
-            height: h,
-            buffer: vec![0; w * h],
-        }
+    fn encode(self) -> u32 {
+        self
     }
 
-    pub fn from_pixel_rgba(width: u32, height: u32, pixel: [u8; 4]) -> Image {
-        let mut image = Image::new(width, height);
+    fn decode(storage: u32) -> u32 {
+        storage
+    }
+}
 
-        let pixel_u32 = u32::from_le_bytes(pixel);
-        for p in image.buffer.iter_mut() {
-            *p = pixel_u32;
-        }
+/// 8-bit RGBA color.
+impl Pixel for [u8; 4] {
+    type Storage = u32;
+    const CLEAR: [u8; 4] = [0, 0, 0, 0];
 
-        image
+    fn encode(self) -> u32 {
+        u32::from_le_bytes(self)
     }
 
-    pub fn from_pixel_depth(width: u32, height: u32, pixel: f32) -> Image {
-        let mut image = Image::new(width, height);
+    fn decode(storage: u32) -> [u8; 4] {
+        storage.to_le_bytes()
+    }
+}
 
-        let pixel_u32 = pixel.to_bits();
-        for p in image.buffer.iter_mut() {
-            *p = pixel_u32;
-        }
+/// Depth, cleared to the far plane.
+impl Pixel for f32 {
+    type Storage = u32;
+    const CLEAR: f32 = 1.0;
 
-        image
+    fn encode(self) -> u32 {
+        self.to_bits()
     }
 
-    pub fn from_raw(buffer: Vec<u32>, width: u32, height: u32) -> Option<Image> {
+    fn decode(storage: u32) -> f32 {
+        f32::from_bits(storage)
+    }
+}
+
+/// Floating-point RGBA color, see `ImageF32`.
+impl Pixel for Vec4 {
+    type Storage = Vec4;
+    const CLEAR: Vec4 = Vec4::ZERO;
+
+    fn encode(self) -> Vec4 {
+        self
+    }
+
+    fn decode(storage: Vec4) -> Vec4 {
+        storage
+    }
+}
+
+/// Single 8-bit channel, see `ImageLuma`.
+impl Pixel for u8 {
+    type Storage = u8;
+    const CLEAR: u8 = 0;
+
+    fn encode(self) -> u8 {
+        self
+    }
+
+    fn decode(storage: u8) -> u8 {
+        storage
+    }
+}
+
+/// 16-bit normalized depth, see `ImageDepth16`, cleared to the far plane.
+impl Pixel for u16 {
+    type Storage = u16;
+    const CLEAR: u16 = u16::MAX;
+
+    fn encode(self) -> u16 {
+        self
+    }
+
+    fn decode(storage: u16) -> u16 {
+        storage
+    }
+}
+
+/// Pixel formats holding depth in the [0..1] range, which the pipeline can
+/// test and write fragment depth to.
+///
+/// The pipeline quantizes the depth of fragments to the format before the
+/// depth test, so that they compare against stored depth as they would be
+/// stored.
+pub trait DepthPixel: Pixel {
+    fn to_depth(self) -> f32;
+    fn from_depth(depth: f32) -> Self;
+}
+
+/// Raw storage word holding the bits of an `f32`, see `Image::pixel_depth`.
+impl DepthPixel for u32 {
+    fn to_depth(self) -> f32 {
+        f32::from_bits(self)
+    }
+
+    fn from_depth(depth: f32) -> u32 {
+        depth.to_bits()
+    }
+}
+
+impl DepthPixel for f32 {
+    fn to_depth(self) -> f32 {
+        self
+    }
+
+    fn from_depth(depth: f32) -> f32 {
+        depth
+    }
+}
+
+/// Depth is clamped to [0..1] and rounded to the nearest of 65536 evenly
+/// spaced steps.
+impl DepthPixel for u16 {
+    fn to_depth(self) -> f32 {
+        f32::from(self) / 65535.0
+    }
+
+    fn from_depth(depth: f32) -> u16 {
+        (depth.clamp(0.0, 1.0) * 65535.0).round() as u16
+    }
+}
+
+/// Pixel formats holding colors, which can be rendered to and sampled.
+pub trait ColorPixel: Pixel {
+    /// Converts to a float RGBA color.
+    fn to_color(self) -> Vec4;
+
+    /// Converts COLOR written to pixel X, Y, quantizing it with ENCODING if
+    /// the format has limited precision.
+    fn from_color(color: Vec4, x: u32, y: u32, encoding: &ColorEncoding) -> Self;
+
+    /// Converts a raw storage word, interpreted as RGBA8 by formats that
+    /// don't store words.
+    fn from_u32(value: u32) -> Self;
+
+    /// Converts to a float RGBA color written with ENCODING, decoding sRGB,
+    /// e.g. to blend with it.
+    fn to_linear_color(self, encoding: &ColorEncoding) -> Vec4 {
+        encoding.decode(self.to_color())
+    }
+}
+
+impl ColorPixel for u32 {
+    fn to_color(self) -> Vec4 {
+        rgba_to_vec(self.to_le_bytes())
+    }
+
+    fn from_color(color: Vec4, x: u32, y: u32, encoding: &ColorEncoding) -> u32 {
+        u32::from_le_bytes(encoding.encode(color, x, y))
+    }
+
+    fn from_u32(value: u32) -> u32 {
+        value
+    }
+}
+
+impl ColorPixel for [u8; 4] {
+    fn to_color(self) -> Vec4 {
+        rgba_to_vec(self)
+    }
+
+    fn from_color(color: Vec4, x: u32, y: u32, encoding: &ColorEncoding) -> [u8; 4] {
+        encoding.encode(color, x, y)
+    }
+
+    fn from_u32(value: u32) -> [u8; 4] {
+        value.to_le_bytes()
+    }
+}
+
+/// Colors are stored unclamped and the encoding is ignored.
+impl ColorPixel for Vec4 {
+    fn to_color(self) -> Vec4 {
+        self
+    }
+
+    fn from_color(color: Vec4, _x: u32, _y: u32, _encoding: &ColorEncoding) -> Vec4 {
+        color
+    }
+
+    fn from_u32(value: u32) -> Vec4 {
+        rgba_to_vec(value.to_le_bytes())
+    }
+
+    fn to_linear_color(self, _encoding: &ColorEncoding) -> Vec4 {
+        self
+    }
+}
+
+/// Reads as opaque gray, but writes take the red channel, so that shaders
+/// can output a single value in any channel layout they like.
+impl ColorPixel for u8 {
+    fn to_color(self) -> Vec4 {
+        let v = f32::from(self) / 255.0;
+        Vec4::new(v, v, v, 1.0)
+    }
+
+    fn from_color(color: Vec4, x: u32, y: u32, encoding: &ColorEncoding) -> u8 {
+        encoding.encode(color, x, y)[0]
+    }
+
+    fn from_u32(value: u32) -> u8 {
+        value as u8
+    }
+}
+
+/// Image with pixels of format P. The default format is the raw storage
+/// word, whose accessors interpret it as RGBA, depth or stencil, e.g.
+/// `pixel_rgba` and `pixel_depth`. Other formats are accessed with `pixel`
+/// and `set_pixel`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Image<P: Pixel = u32> {
+    width: usize,
+    height: usize,
+    buffer: Vec<P::Storage>,
+    _pixel: PhantomData<P>,
+}
+
+/// Single-channel 8-bit image, e.g. for height maps, ambient occlusion or
+/// font atlases. Samples expand to opaque gray, `Vec4::new(v, v, v, 1.0)`.
+///
+/// `ImageLuma::new` and `ImageLuma::from_raw` need `ImageConstructors` in
+/// scope, e.g. `use rusterizer::image::ImageConstructors;`.
+pub type ImageLuma = Image<u8>;
+
+/// Depth image storing 16-bit normalized depth, half the memory of a default
+/// `Image`, e.g. `ImageDepth16::from_pixel(width, height, u16::CLEAR)`.
+///
+/// Depth is stored in steps of 1/65535 regardless of its value, unlike the
+/// floats of a default `Image`, which are finer towards 0. Perspective
+/// projections spend most of the [0..1] range near the near plane, so far
+/// from it surfaces closer together than a few steps may z-fight. Keep the
+/// near plane as far out as possible.
+pub type ImageDepth16 = Image<u16>;
+
+/// Floating-point RGBA image for HDR rendering. Values are stored as
+/// written, without clamping or quantization.
+///
+/// Create one with `Image::from_pixel`, e.g.
+/// `ImageF32::from_pixel(width, height, Vec4::ZERO)`. `ImageF32::new` and
+/// `ImageF32::from_raw` need `ImageConstructors` in scope, e.g.
+/// `use rusterizer::image::ImageConstructors;`.
+pub type ImageF32 = Image<Vec4>;
+
+// Constructors of the default format are in its own impl block, as generic
+// ones would make `Image::new` ambiguous.
+impl<P: Pixel> Image<P> {
+    /// Creates an image filled with PIXEL, e.g. `Pixel::CLEAR`.
+    pub fn from_pixel(width: u32, height: u32, pixel: P) -> Image<P> {
+        let w = cast_usize(width);
+        let h = cast_usize(height);
+
+        Image::from_buffer(w, h, vec![pixel.encode(); w * h])
+    }
+
+    /// Like `Image::from_raw`, for any pixel format.
+    pub fn from_storage(buffer: Vec<P::Storage>, width: u32, height: u32) -> Option<Image<P>> {
         let w = cast_usize(width);
         let h = cast_usize(height);
         if w * h <= buffer.len() {
-            Some(Image {
-                width: w,
-                height: h,
-                buffer,
-            })
+            Some(Image::from_buffer(w, h, buffer))
         } else {
             None
         }
     }
 
-    pub fn into_raw(self) -> Vec<u32> {
+    pub fn into_raw(self) -> Vec<P::Storage> {
         self.buffer
     }
 
-    pub fn pixels_mut_rgba(&mut self) -> PixelsMutRgba<'_> {
-        PixelsMutRgba {
-            iter: self.buffer.iter_mut(),
+    /// Reinterprets the stored words as pixel format Q without converting
+    /// them, e.g. to access a default `Image` as `Image<[u8; 4]>`.
+    pub fn reinterpret<Q: Pixel<Storage = P::Storage>>(self) -> Image<Q> {
+        Image::from_buffer(self.width, self.height, self.buffer)
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> P {
+        let index = self.pixel_index(x, y);
+        P::decode(self.buffer[index])
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        let index = self.pixel_index(x, y);
+        self.buffer[index] = pixel.encode();
+    }
+
+    /// Like `pixel`, but returns None if X, Y is out of bounds.
+    pub fn try_pixel(&self, x: u32, y: u32) -> Option<P> {
+        let index = self.try_pixel_index(x, y)?;
+        Some(P::decode(self.buffer[index]))
+    }
+
+    /// Like `set_pixel`, but returns false instead of writing if X, Y is out
+    /// of bounds.
+    pub fn try_set_pixel(&mut self, x: u32, y: u32, pixel: P) -> bool {
+        match self.try_pixel_index(x, y) {
+            Some(index) => {
+                self.buffer[index] = pixel.encode();
+                true
+            }
+            None => false,
         }
     }
 
-    pub fn pixels_mut_depth(&mut self) -> PixelsMutDepth<'_> {
-        PixelsMutDepth {
-            iter: self.buffer.iter_mut(),
+    pub fn clear(&mut self, pixel: P) {
+        let storage = pixel.encode();
+        for p in self.buffer.iter_mut() {
+            *p = storage;
         }
     }
 
-    pub fn pixel_rgba(&self, x: u32, y: u32) -> [u8; 4] {
-        let index = cast_usize(y) * self.width + cast_usize(x);
-        let pixel_u32 = self.buffer[index];
+    /// Fills the WIDTH x HEIGHT rectangle at X, Y with PIXEL, clipped to the
+    /// image.
+    pub fn fill_rect(&mut self, x: u32, y: u32, width: u32, height: u32, pixel: P) {
+        let (x, y) = (cast_usize(x), cast_usize(y));
+        let width = cast_usize(width).min(self.width.saturating_sub(x));
+        let height = cast_usize(height).min(self.height.saturating_sub(y));
+        if width == 0 {
+            return;
+        }
 
-        pixel_u32.to_le_bytes()
+        let storage = pixel.encode();
+        for row in y..y + height {
+            let start = row * self.width + x;
+            self.buffer[start..start + width].fill(storage);
+        }
     }
 
-    pub fn pixel_depth(&self, x: u32, y: u32) -> f32 {
-        let index = cast_usize(y) * self.width + cast_usize(x);
-        let pixel_u32 = self.buffer[index];
+    /// Iterates over rows of the buffer, top to bottom in memory.
+    pub fn rows(&self) -> slice::ChunksExact<'_, P::Storage> {
+        let len = self.width * self.height;
+        self.buffer[..len].chunks_exact(self.width.max(1))
+    }
 
-        f32::from_bits(pixel_u32)
+    pub fn rows_mut(&mut self) -> slice::ChunksExactMut<'_, P::Storage> {
+        let len = self.width * self.height;
+        self.buffer[..len].chunks_exact_mut(self.width.max(1))
     }
 
-    pub fn pixel_mut_rgba(&mut self, x: u32, y: u32) -> &mut [u8; 4] {
-        let index = cast_usize(y) * self.width + cast_usize(x);
-        let pixel_u32 = &mut self.buffer[index];
+    /// Iterates over pixels with their coordinates, in the same order as
+    /// `rows`.
+    pub fn enumerate_pixels(&self) -> EnumeratePixels<'_, P> {
+        EnumeratePixels {
+            rows: self.rows().enumerate(),
+            row: [].iter().enumerate(),
+            y: 0,
+            _pixel: PhantomData,
+        }
+    }
 
-        unsafe { &mut *(pixel_u32 as *mut u32 as *mut [u8; 4]) }
+    /// Iterates over stored words with their coordinates, see
+    /// `enumerate_pixels`.
+    pub fn enumerate_pixels_mut(&mut self) -> EnumeratePixelsMut<'_, P::Storage> {
+        EnumeratePixelsMut {
+            rows: self.rows_mut().enumerate(),
+            row: [].iter_mut().enumerate(),
+            y: 0,
+        }
     }
 
-    pub fn pixel_mut_depth(&mut self, x: u32, y: u32) -> &mut f32 {
-        let index = cast_usize(y) * self.width + cast_usize(x);
-        let pixel_u32 = &mut self.buffer[index];
+    /// Borrows the WIDTH x HEIGHT rectangle at X, Y as an image of its own.
+    /// Panics if the rectangle doesn't fit in the image.
+    pub fn view(&self, x: u32, y: u32, width: u32, height: u32) -> ImageView<'_, P> {
+        let range = self.view_range(x, y, width, height);
+        ImageView {
+            width: cast_usize(width),
+            height: cast_usize(height),
+            stride: self.width,
+            buffer: &self.buffer[range],
+            _pixel: PhantomData,
+        }
+    }
 
-        unsafe { &mut *(pixel_u32 as *mut u32 as *mut f32) }
+    /// Mutably borrows the WIDTH x HEIGHT rectangle at X, Y as an image of
+    /// its own, e.g. to render into a part of the image. Panics if the
+    /// rectangle doesn't fit in the image.
+    pub fn view_mut(&mut self, x: u32, y: u32, width: u32, height: u32) -> ImageViewMut<'_, P> {
+        let range = self.view_range(x, y, width, height);
+        ImageViewMut {
+            width: cast_usize(width),
+            height: cast_usize(height),
+            stride: self.width,
+            buffer: &mut self.buffer[range],
+            _pixel: PhantomData,
+        }
     }
 
-    pub fn sample_nearest_rgba(&self, uv: Vec2) -> Vec4 {
-        let u = uv.x.clamp(0.0, 1.0);
-        let v = uv.y.clamp(0.0, 1.0);
+    /// Returns a copy of the WIDTH x HEIGHT rectangle at X, Y. The rectangle
+    /// is clamped to the image, so the result shrinks, possibly to 0x0, if
+    /// it extends past the bounds.
+    pub fn crop(&self, x: u32, y: u32, width: u32, height: u32) -> Image<P> {
+        let mut target = Image::from_buffer(0, 0, Vec::new());
+        self.crop_into((x, y, width, height), &mut target);
+        target
+    }
 
-        let x = u * self.width.saturating_sub(1) as f32;
-        let y = v * self.height.saturating_sub(1) as f32;
+    /// Like `crop`, but writes the result to TARGET, reusing its allocation.
+    /// TARGET takes the dimensions of the clamped rectangle.
+    pub fn crop_into(&self, (x, y, width, height): (u32, u32, u32, u32), target: &mut Image<P>) {
+        let (x, y) = (cast_usize(x), cast_usize(y));
+        let width = cast_usize(width).min(self.width.saturating_sub(x));
+        let height = cast_usize(height).min(self.height.saturating_sub(y));
+        let (width, height) = if width == 0 || height == 0 {
+            (0, 0)
+        } else {
+            (width, height)
+        };
 
-        let pixel = self.pixel_rgba(x as u32, y as u32);
+        target.buffer.clear();
+        for row in y..y + height {
+            let start = row * self.width + x;
+            target
+                .buffer
+                .extend_from_slice(&self.buffer[start..start + width]);
+        }
 
-        Vec4::new(
-            pixel[0] as f32 / 255.0,
-            pixel[1] as f32 / 255.0,
-            pixel[2] as f32 / 255.0,
-            pixel[3] as f32 / 255.0,
-        )
+        target.width = width;
+        target.height = height;
     }
 
-    pub fn set_pixel_rgba(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
-        *self.pixel_mut_rgba(x, y) = pixel;
+    /// Returns the image rotated by 90 degrees clockwise, with row 0 at the
+    /// top. Width and height are swapped.
+    pub fn rotate90(&self) -> Image<P> {
+        let height = self.height;
+        self.rotate_quarter(|x, y| (height - 1 - y, x))
     }
 
-    pub fn set_pixel_depth(&mut self, x: u32, y: u32, pixel: f32) {
-        *self.pixel_mut_depth(x, y) = pixel;
+    /// Returns the image rotated by 180 degrees.
+    pub fn rotate180(&self) -> Image<P> {
+        let mut image = self.clone();
+        image.rotate180_in_place();
+        image
     }
 
-    pub fn clear_rgba(&mut self, pixel: [u8; 4]) {
-        for p in self.pixels_mut_rgba() {
-            *p = pixel;
+    /// Returns the image rotated by 270 degrees clockwise, with row 0 at the
+    /// top. Width and height are swapped.
+    pub fn rotate270(&self) -> Image<P> {
+        let width = self.width;
+        self.rotate_quarter(|x, y| (y, width - 1 - x))
+    }
+
+    pub fn rotate180_in_place(&mut self) {
+        let len = self.width * self.height;
+        self.buffer[..len].reverse();
+    }
+
+    /// Copies each source pixel X, Y to the coordinates returned by
+    /// TRANSFORM in an image with swapped dimensions. Walks the source in
+    /// tiles, so that both reads and writes stay within a few cache lines.
+    fn rotate_quarter<F>(&self, transform: F) -> Image<P>
+    where
+        F: Fn(usize, usize) -> (usize, usize),
+    {
+        const TILE: usize = 16;
+
+        let (width, height) = (self.height, self.width);
+        let mut buffer = self.buffer[..width * height].to_vec();
+        for tile_y in (0..self.height).step_by(TILE) {
+            for tile_x in (0..self.width).step_by(TILE) {
+                for y in tile_y..usize::min(tile_y + TILE, self.height) {
+                    for x in tile_x..usize::min(tile_x + TILE, self.width) {
+                        let (dst_x, dst_y) = transform(x, y);
+                        buffer[dst_y * width + dst_x] = self.buffer[y * self.width + x];
+                    }
+                }
+            }
         }
+
+        Image::from_buffer(width, height, buffer)
     }
 
-    pub fn clear_depth(&mut self, pixel: f32) {
-        for p in self.pixels_mut_depth() {
-            *p = pixel;
+    /// Copies all of SRC into the image with its top left corner at DST_X,
+    /// DST_Y. Parts falling outside of the image are clipped.
+    pub fn copy_from(&mut self, src: &Image<P>, dst_x: u32, dst_y: u32) {
+        let (width, height) = src.dimensions();
+        self.copy_from_rect(src, 0, 0, width, height, dst_x, dst_y);
+    }
+
+    /// Copies the WIDTH x HEIGHT rectangle of SRC at SRC_X, SRC_Y into the
+    /// image with its top left corner at DST_X, DST_Y. The rectangle is
+    /// clipped to the bounds of both images.
+    #[allow(clippy::too_many_arguments)]
+    pub fn copy_from_rect(
+        &mut self,
+        src: &Image<P>,
+        src_x: u32,
+        src_y: u32,
+        width: u32,
+        height: u32,
+        dst_x: u32,
+        dst_y: u32,
+    ) {
+        let (src_x, src_y) = (cast_usize(src_x), cast_usize(src_y));
+        let (dst_x, dst_y) = (cast_usize(dst_x), cast_usize(dst_y));
+
+        let width = cast_usize(width)
+            .min(src.width.saturating_sub(src_x))
+            .min(self.width.saturating_sub(dst_x));
+        let height = cast_usize(height)
+            .min(src.height.saturating_sub(src_y))
+            .min(self.height.saturating_sub(dst_y));
+        if width == 0 {
+            return;
+        }
+
+        for row in 0..height {
+            let src_start = (src_y + row) * src.width + src_x;
+            let dst_start = (dst_y + row) * self.width + dst_x;
+            self.buffer[dst_start..dst_start + width]
+                .copy_from_slice(&src.buffer[src_start..src_start + width]);
         }
     }
 
@@ -152,38 +562,2364 @@ impl Image {
     pub fn height(&self) -> u32 {
         self.height as u32
     }
-}
 
-impl AsRef<[u32]> for Image {
-    fn as_ref(&self) -> &[u32] {
-        &self.buffer
+    /// Returns the buffer index of pixel X, Y, panicking with the coordinates
+    /// if it is out of bounds.
+    fn pixel_index(&self, x: u32, y: u32) -> usize {
+        match self.try_pixel_index(x, y) {
+            Some(index) => index,
+            None => panic!(
+                "pixel ({}, {}) out of bounds for {}x{} image",
+                x, y, self.width, self.height,
+            ),
+        }
+    }
+
+    fn try_pixel_index(&self, x: u32, y: u32) -> Option<usize> {
+        let (x, y) = (cast_usize(x), cast_usize(y));
+        if x < self.width && y < self.height {
+            Some(y * self.width + x)
+        } else {
+            None
+        }
+    }
+
+    /// Returns the range of the buffer spanned by the rows of a view.
+    fn view_range(&self, x: u32, y: u32, width: u32, height: u32) -> Range<usize> {
+        let (x, y) = (cast_usize(x), cast_usize(y));
+        let (width, height) = (cast_usize(width), cast_usize(height));
+        assert!(
+            x + width <= self.width && y + height <= self.height,
+            "view must fit in the image",
+        );
+
+        if width == 0 || height == 0 {
+            return 0..0;
+        }
+
+        let start = y * self.width + x;
+        let end = (y + height - 1) * self.width + x + width;
+        start..end
+    }
+
+    fn from_buffer(width: usize, height: usize, buffer: Vec<P::Storage>) -> Image<P> {
+        Image {
+            width,
+            height,
+            buffer,
+            _pixel: PhantomData,
+        }
     }
 }
 
-pub struct PixelsMutRgba<'a> {
-    iter: slice::IterMut<'a, u32>,
+impl<P: Pixel> AsRef<[P::Storage]> for Image<P> {
+    fn as_ref(&self) -> &[P::Storage] {
+        &self.buffer
+    }
 }
 
-impl<'a> Iterator for PixelsMutRgba<'a> {
-    type Item = &'a mut [u8; 4];
+/// Indexes the stored word of pixel X, Y, i.e. the packed `u32` of the
+/// default `Image`. Use the typed accessors, e.g. `pixel_rgba`, to read it as
+/// a particular format. Panics if the pixel is out of bounds.
+impl<P: Pixel> Index<(u32, u32)> for Image<P> {
+    type Output = P::Storage;
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter
-            .next()
-            .map(|v| unsafe { &mut *(v as *mut u32 as *mut [u8; 4]) })
+    fn index(&self, (x, y): (u32, u32)) -> &P::Storage {
+        &self.buffer[self.pixel_index(x, y)]
     }
 }
 
-pub struct PixelsMutDepth<'a> {
-    iter: slice::IterMut<'a, u32>,
+impl<P: Pixel> IndexMut<(u32, u32)> for Image<P> {
+    fn index_mut(&mut self, (x, y): (u32, u32)) -> &mut P::Storage {
+        let index = self.pixel_index(x, y);
+        &mut self.buffer[index]
+    }
 }
 
-impl<'a> Iterator for PixelsMutDepth<'a> {
-    type Item = &'a mut f32;
+impl Image {
+    pub fn new(width: u32, height: u32) -> Image {
+        Image::from_pixel(width, height, u32::CLEAR)
+    }
 
-    fn next(&mut self) -> Option<Self::Item> {
-        self.iter
-            .next()
-            .map(|v| unsafe { &mut *(v as *mut u32 as *mut f32) })
+    pub fn from_raw(buffer: Vec<u32>, width: u32, height: u32) -> Option<Image> {
+        Image::from_storage(buffer, width, height)
+    }
+
+    pub fn from_pixel_rgba(width: u32, height: u32, pixel: [u8; 4]) -> Image {
+        let mut image = Image::new(width, height);
+
+        let pixel_u32 = u32::from_le_bytes(pixel);
+        for p in image.buffer.iter_mut() {
+            *p = pixel_u32;
+        }
+
+        image
+    }
+
+    pub fn from_pixel_depth(width: u32, height: u32, pixel: f32) -> Image {
+        let mut image = Image::new(width, height);
+
+        let pixel_u32 = pixel.to_bits();
+        for p in image.buffer.iter_mut() {
+            *p = pixel_u32;
+        }
+
+        image
+    }
+
+    pub fn from_pixel_stencil(width: u32, height: u32, pixel: u8) -> Image {
+        let mut image = Image::new(width, height);
+        image.clear_stencil(pixel);
+
+        image
+    }
+
+    /// Creates an image from RGBA bytes, row by row. Returns None unless
+    /// BYTES holds exactly WIDTH x HEIGHT pixels.
+    pub fn from_rgba8(bytes: &[u8], width: u32, height: u32) -> Option<Image> {
+        if bytes.len() != cast_usize(width) * cast_usize(height) * 4 {
+            return None;
+        }
+
+        let buffer = bytes
+            .chunks_exact(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+
+        Image::from_raw(buffer, width, height)
+    }
+
+    /// Returns the pixels as RGBA bytes, row by row.
+    pub fn to_rgba8_vec(&self) -> Vec<u8> {
+        self.rows()
+            .flatten()
+            .flat_map(|p| p.to_le_bytes())
+            .collect()
+    }
+
+    /// Borrows the pixels as RGBA bytes, row by row, without copying. Only
+    /// available on little-endian targets, where the stored words have this
+    /// byte order in memory, see `to_rgba8_vec` for the others.
+    ///
+    /// Rows start at the top of the rendered image and bytes are ordered R,
+    /// G, B, A, as in a browser `ImageData`. This holds on wasm32, which is
+    /// little-endian.
+    #[cfg(target_endian = "little")]
+    pub fn as_rgba8_bytes(&self) -> &[u8] {
+        let len = self.width * self.height;
+        let words = &self.buffer[..len];
+
+        unsafe { slice::from_raw_parts(words.as_ptr() as *const u8, len * 4) }
+    }
+
+    #[cfg(target_endian = "little")]
+    pub fn as_rgba8_bytes_mut(&mut self) -> &mut [u8] {
+        let len = self.width * self.height;
+        let words = &mut self.buffer[..len];
+
+        unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, len * 4) }
+    }
+
+    /// Converts the pixels to words holding BGRA bytes in memory on
+    /// little-endian targets, i.e. `0xAARRGGBB`, as expected by minifb and
+    /// softbuffer. Replaces the contents of OUT, reusing its allocation.
+    pub fn to_bgra_u32(&self, out: &mut Vec<u32>) {
+        out.clear();
+        out.extend(self.rows().flatten().map(|&p| rgba_to_bgra(p)));
+    }
+
+    /// Like `to_bgra_u32`, but writes into OUT, which must hold exactly
+    /// width x height words.
+    pub fn copy_to_bgra_u32(&self, out: &mut [u32]) {
+        assert!(
+            out.len() == self.width * self.height,
+            "output must match the image size"
+        );
+        for (dst, &src) in out.iter_mut().zip(self.rows().flatten()) {
+            *dst = rgba_to_bgra(src);
+        }
+    }
+
+    /// Converts the pixels to words holding ARGB bytes in memory on
+    /// little-endian targets, i.e. `0xBBGGRRAA`. Replaces the contents of
+    /// OUT, reusing its allocation.
+    pub fn to_argb_u32(&self, out: &mut Vec<u32>) {
+        out.clear();
+        out.extend(self.rows().flatten().map(|&p| p.rotate_left(8)));
+    }
+
+    /// Multiplies RGB by alpha, converting straight alpha to premultiplied,
+    /// e.g. for textures sampled with filtering, see `Sampler`. Rounds to
+    /// nearest.
+    pub fn premultiply_alpha(&mut self) {
+        for [r, g, b, a] in self.pixels_mut_rgba() {
+            let alpha = u32::from(*a);
+            for channel in [r, g, b] {
+                *channel = draw::div_255(u32::from(*channel) * alpha) as u8;
+            }
+        }
+    }
+
+    /// Divides RGB by alpha, converting premultiplied alpha back to straight.
+    /// Fully transparent pixels become transparent black, as their color is
+    /// lost by `premultiply_alpha`.
+    pub fn unpremultiply_alpha(&mut self) {
+        for [r, g, b, a] in self.pixels_mut_rgba() {
+            let alpha = u32::from(*a);
+            for channel in [r, g, b] {
+                *channel = match alpha {
+                    0 => 0,
+                    _ => ((u32::from(*channel) * 255 + alpha / 2) / alpha).min(255) as u8,
+                };
+            }
+        }
+    }
+
+    pub fn pixels_mut_rgba(&mut self) -> PixelsMutRgba<'_> {
+        PixelsMutRgba {
+            iter: self.buffer.iter_mut(),
+        }
+    }
+
+    pub fn pixels_mut_depth(&mut self) -> PixelsMutDepth<'_> {
+        PixelsMutDepth {
+            iter: self.buffer.iter_mut(),
+        }
+    }
+
+    pub fn enumerate_pixels_rgba(&self) -> impl Iterator<Item = (u32, u32, [u8; 4])> + '_ {
+        self.enumerate_pixels()
+            .map(|(x, y, p)| (x, y, p.to_le_bytes()))
+    }
+
+    pub fn enumerate_pixels_rgba_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (u32, u32, &mut [u8; 4])> + '_ {
+        self.enumerate_pixels_mut()
+            .map(|(x, y, p)| (x, y, unsafe { &mut *(p as *mut u32 as *mut [u8; 4]) }))
+    }
+
+    /// Returns the pixels as depth values, row by row.
+    pub fn depth_to_vec(&self) -> Vec<f32> {
+        self.rows().flatten().map(|&p| f32::from_bits(p)).collect()
+    }
+
+    /// Converts the pixels as depth values to gray RGBA for viewing. Depth
+    /// from NEAR to FAR maps from black to white, raised to GAMMA, e.g. 1.0
+    /// for a linear ramp or above to tell apart the distant depths that
+    /// perspective projections crowd towards 1.0. Depths outside of the
+    /// range are clamped.
+    ///
+    /// NEAR and FAR are stored depths in [0..1], e.g. the extremes of the
+    /// rendered depths, see `PipelineOptions::linearize_depth` to get
+    /// distances instead. Pixels at 1.0 or beyond, i.e. with nothing
+    /// rendered, are dark blue to stand out from the far end of the range.
+    pub fn depth_to_rgba(&self, near: f32, far: f32, gamma: f32) -> Image {
+        let mut image = Image::new(self.width(), self.height());
+        self.depth_to_rgba_into(near, far, gamma, &mut image);
+        image
+    }
+
+    /// Like `depth_to_rgba`, but writes into IMAGE, which must have equal
+    /// dimensions, reusing its allocation.
+    pub fn depth_to_rgba_into(&self, near: f32, far: f32, gamma: f32, image: &mut Image) {
+        assert!(
+            self.dimensions() == image.dimensions(),
+            "images must have equal dims"
+        );
+
+        for (dst, &src) in image.buffer.iter_mut().zip(&self.buffer) {
+            let depth = f32::from_bits(src);
+            let rgba = if depth >= 1.0 {
+                DEPTH_CLEARED_RGBA
+            } else {
+                let t = ((depth - near) / (far - near)).clamp(0.0, 1.0).powf(gamma);
+                let gray = (t * 255.0).round() as u8;
+                [gray, gray, gray, 255]
+            };
+            *dst = u32::from_le_bytes(rgba);
+        }
+    }
+
+    pub fn enumerate_pixels_depth(&self) -> impl Iterator<Item = (u32, u32, f32)> + '_ {
+        self.enumerate_pixels()
+            .map(|(x, y, p)| (x, y, f32::from_bits(p)))
+    }
+
+    pub fn enumerate_pixels_depth_mut(
+        &mut self,
+    ) -> impl Iterator<Item = (u32, u32, &mut f32)> + '_ {
+        self.enumerate_pixels_mut()
+            .map(|(x, y, p)| (x, y, unsafe { &mut *(p as *mut u32 as *mut f32) }))
+    }
+
+    pub fn pixel_rgba(&self, x: u32, y: u32) -> [u8; 4] {
+        let index = self.pixel_index(x, y);
+        let pixel_u32 = self.buffer[index];
+
+        pixel_u32.to_le_bytes()
+    }
+
+    pub fn pixel_depth(&self, x: u32, y: u32) -> f32 {
+        let index = self.pixel_index(x, y);
+        let pixel_u32 = self.buffer[index];
+
+        f32::from_bits(pixel_u32)
+    }
+
+    pub fn try_pixel_rgba(&self, x: u32, y: u32) -> Option<[u8; 4]> {
+        self.try_pixel(x, y).map(u32::to_le_bytes)
+    }
+
+    pub fn try_pixel_depth(&self, x: u32, y: u32) -> Option<f32> {
+        self.try_pixel(x, y).map(f32::from_bits)
+    }
+
+    pub fn pixel_stencil(&self, x: u32, y: u32) -> u8 {
+        let index = self.pixel_index(x, y);
+        self.buffer[index] as u8
+    }
+
+    pub fn pixel_u32(&self, x: u32, y: u32) -> u32 {
+        let index = self.pixel_index(x, y);
+        self.buffer[index]
+    }
+
+    pub fn pixel_mut_rgba(&mut self, x: u32, y: u32) -> &mut [u8; 4] {
+        let index = self.pixel_index(x, y);
+        let pixel_u32 = &mut self.buffer[index];
+
+        unsafe { &mut *(pixel_u32 as *mut u32 as *mut [u8; 4]) }
+    }
+
+    pub fn pixel_mut_depth(&mut self, x: u32, y: u32) -> &mut f32 {
+        let index = self.pixel_index(x, y);
+        let pixel_u32 = &mut self.buffer[index];
+
+        unsafe { &mut *(pixel_u32 as *mut u32 as *mut f32) }
+    }
+
+    /// Samples the texel containing UV, clamping to the edge. Shorthand for
+    /// sampling with the default `Sampler`.
+    pub fn sample_nearest_rgba(&self, uv: Vec2) -> Vec4 {
+        Sampler::default().sample(self, uv)
+    }
+
+    /// Samples the texel containing UV, wrapping texel coordinates outside
+    /// the image according to WRAP_U and WRAP_V. BORDER is returned for
+    /// texels outside of a `Wrap::ClampToBorder` axis.
+    pub fn sample_nearest_rgba_wrapped(
+        &self,
+        uv: Vec2,
+        wrap_u: Wrap,
+        wrap_v: Wrap,
+        border: Vec4,
+    ) -> Vec4 {
+        let sampler = Sampler {
+            filter: Filter::Nearest,
+            wrap_u,
+            wrap_v,
+            border_color: border,
+            ..Sampler::default()
+        };
+
+        sampler.sample(self, uv)
+    }
+
+    /// Samples with bilinear filtering, clamping to the edge. Texel centers
+    /// are at half-integer coordinates, i.e. UV 0.0 and 1.0 are the outer
+    /// edges of the image.
+    pub fn sample_bilinear_rgba(&self, uv: Vec2) -> Vec4 {
+        let clamp = Wrap::ClampToEdge;
+        self.sample_bilinear_rgba_wrapped(uv, clamp, clamp, Vec4::ZERO)
+    }
+
+    /// Samples with bilinear filtering, wrapping texel coordinates outside
+    /// the image according to WRAP_U and WRAP_V. BORDER is used for texels
+    /// outside of a `Wrap::ClampToBorder` axis, so samples near such an edge
+    /// blend towards it.
+    pub fn sample_bilinear_rgba_wrapped(
+        &self,
+        uv: Vec2,
+        wrap_u: Wrap,
+        wrap_v: Wrap,
+        border: Vec4,
+    ) -> Vec4 {
+        let sampler = Sampler {
+            filter: Filter::Bilinear,
+            wrap_u,
+            wrap_v,
+            border_color: border,
+            ..Sampler::default()
+        };
+
+        sampler.sample(self, uv)
+    }
+
+    /// Returns the image resampled to WIDTH x HEIGHT. `Filter::Nearest`
+    /// picks the texel containing each destination pixel center, the other
+    /// filters interpolate bilinearly, or average the covered texels when
+    /// downscaling by more than 2x, so that thumbnails don't alias.
+    pub fn resized(&self, width: u32, height: u32, filter: Filter) -> Image {
+        let (src_width, src_height) = self.dimensions();
+        if src_width == 0 || src_height == 0 {
+            return Image::new(width, height);
+        }
+
+        let downscale = width < src_width / 2 || height < src_height / 2;
+        if filter != Filter::Nearest && downscale {
+            return resample_area(self, width, height, false);
+        }
+
+        let sampler = Sampler {
+            filter: if filter == Filter::Nearest {
+                Filter::Nearest
+            } else {
+                Filter::Bilinear
+            },
+            ..Sampler::default()
+        };
+
+        let mut image = Image::new(width, height);
+        for y in 0..height {
+            for x in 0..width {
+                let uv = Vec2::new(
+                    (x as f32 + 0.5) / width as f32,
+                    (y as f32 + 0.5) / height as f32,
+                );
+                let color = sampler.sample(self, uv);
+                image.set_pixel_rgba(x, y, vec_to_rgba(color));
+            }
+        }
+
+        image
+    }
+
+    /// Draws the SRC_W x SRC_H rectangle of SRC at SRC_X, SRC_Y scaled into
+    /// the DST_W x DST_H rectangle at DST_X, DST_Y, see `resized`. The
+    /// source rectangle is clipped to SRC, the destination to the image.
+    pub fn blit_scaled(
+        &mut self,
+        src: &Image,
+        (src_x, src_y, src_w, src_h): (u32, u32, u32, u32),
+        (dst_x, dst_y, dst_w, dst_h): (u32, u32, u32, u32),
+        filter: Filter,
+    ) {
+        let src_w = u32::min(src_w, src.width().saturating_sub(src_x));
+        let src_h = u32::min(src_h, src.height().saturating_sub(src_y));
+        if src_w == 0 || src_h == 0 {
+            return;
+        }
+
+        let mut region = Image::new(src_w, src_h);
+        region.copy_from_rect(src, src_x, src_y, src_w, src_h, 0, 0);
+
+        self.copy_from(&region.resized(dst_w, dst_h, filter), dst_x, dst_y);
+    }
+
+    pub fn set_pixel_rgba(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        *self.pixel_mut_rgba(x, y) = pixel;
+    }
+
+    pub fn set_pixel_depth(&mut self, x: u32, y: u32, pixel: f32) {
+        *self.pixel_mut_depth(x, y) = pixel;
+    }
+
+    pub fn try_set_pixel_rgba(&mut self, x: u32, y: u32, pixel: [u8; 4]) -> bool {
+        self.try_set_pixel(x, y, u32::from_le_bytes(pixel))
+    }
+
+    pub fn try_set_pixel_depth(&mut self, x: u32, y: u32, pixel: f32) -> bool {
+        self.try_set_pixel(x, y, pixel.to_bits())
+    }
+
+    pub fn set_pixel_stencil(&mut self, x: u32, y: u32, pixel: u8) {
+        let index = self.pixel_index(x, y);
+        self.buffer[index] = u32::from(pixel);
+    }
+
+    pub fn set_pixel_u32(&mut self, x: u32, y: u32, pixel: u32) {
+        let index = self.pixel_index(x, y);
+        self.buffer[index] = pixel;
+    }
+
+    /// Fills a rectangle with an RGBA color, see `fill_rect`.
+    pub fn fill_rect_rgba(&mut self, x: u32, y: u32, width: u32, height: u32, pixel: [u8; 4]) {
+        self.fill_rect(x, y, width, height, pixel.encode());
+    }
+
+    /// Fills a rectangle with a depth value, see `fill_rect`.
+    pub fn fill_rect_depth(&mut self, x: u32, y: u32, width: u32, height: u32, pixel: f32) {
+        self.fill_rect(x, y, width, height, pixel.encode());
+    }
+
+    pub fn clear_rgba(&mut self, pixel: [u8; 4]) {
+        for p in self.pixels_mut_rgba() {
+            *p = pixel;
+        }
+    }
+
+    /// Clears to float COLOR, quantized once like fragment colors written by
+    /// the pipeline: clamped, optionally encoded to sRGB if SRGB is set, see
+    /// `PipelineOptions::srgb_framebuffer`, and rounded to nearest.
+    pub fn clear_color(&mut self, color: Vec4, srgb: bool) {
+        let encoding = ColorEncoding {
+            srgb,
+            ..ColorEncoding::default()
+        };
+
+        // Without dithering, the encoding doesn't depend on the pixel
+        self.clear_rgba(encoding.encode(color, 0, 0));
+    }
+
+    pub fn clear_depth(&mut self, pixel: f32) {
+        for p in self.pixels_mut_depth() {
+            *p = pixel;
+        }
+    }
+
+    pub fn clear_stencil(&mut self, pixel: u8) {
+        for p in self.buffer.iter_mut() {
+            *p = u32::from(pixel);
+        }
+    }
+}
+
+pub struct PixelsMutRgba<'a> {
+    iter: slice::IterMut<'a, u32>,
+}
+
+impl<'a> Iterator for PixelsMutRgba<'a> {
+    type Item = &'a mut [u8; 4];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .next()
+            .map(|v| unsafe { &mut *(v as *mut u32 as *mut [u8; 4]) })
+    }
+}
+
+pub struct PixelsMutDepth<'a> {
+    iter: slice::IterMut<'a, u32>,
+}
+
+impl<'a> Iterator for PixelsMutDepth<'a> {
+    type Item = &'a mut f32;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.iter
+            .next()
+            .map(|v| unsafe { &mut *(v as *mut u32 as *mut f32) })
+    }
+}
+
+/// Color of cleared pixels in `Image::depth_to_rgba`.
+const DEPTH_CLEARED_RGBA: [u8; 4] = [0, 0, 96, 255];
+
+/// Swaps the R and B bytes of a packed RGBA word.
+pub(crate) fn rgba_to_bgra(pixel: u32) -> u32 {
+    (pixel & 0xff00_ff00) | ((pixel & 0xff) << 16) | ((pixel >> 16) & 0xff)
+}
+
+/// Iterator over the pixels of an image and their coordinates, see
+/// `Image::enumerate_pixels`.
+pub struct EnumeratePixels<'a, P: Pixel> {
+    rows: iter::Enumerate<slice::ChunksExact<'a, P::Storage>>,
+    row: iter::Enumerate<slice::Iter<'a, P::Storage>>,
+    y: u32,
+    _pixel: PhantomData<P>,
+}
+
+impl<'a, P: Pixel> Iterator for EnumeratePixels<'a, P> {
+    type Item = (u32, u32, P);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((x, p)) = self.row.next() {
+                return Some((x as u32, self.y, P::decode(*p)));
+            }
+
+            let (y, row) = self.rows.next()?;
+            self.y = y as u32;
+            self.row = row.iter().enumerate();
+        }
+    }
+}
+
+/// Iterator over the stored words of an image and their coordinates, see
+/// `Image::enumerate_pixels_mut`.
+pub struct EnumeratePixelsMut<'a, S> {
+    rows: iter::Enumerate<slice::ChunksExactMut<'a, S>>,
+    row: iter::Enumerate<slice::IterMut<'a, S>>,
+    y: u32,
+}
+
+impl<'a, S> Iterator for EnumeratePixelsMut<'a, S> {
+    type Item = (u32, u32, &'a mut S);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((x, p)) = self.row.next() {
+                return Some((x as u32, self.y, p));
+            }
+
+            let (y, row) = self.rows.next()?;
+            self.y = y as u32;
+            self.row = row.iter_mut().enumerate();
+        }
+    }
+}
+
+/// `new` and `from_raw` of the formats other than the default, e.g.
+/// `ImageLuma::new(width, height)`. They are trait functions, as inherent
+/// ones would make `Image::new` ambiguous.
+pub trait ImageConstructors: Sized {
+    type Storage;
+
+    /// Creates an image filled with `Pixel::CLEAR`.
+    fn new(width: u32, height: u32) -> Self;
+
+    /// Like `Image::from_storage`.
+    fn from_raw(buffer: Vec<Self::Storage>, width: u32, height: u32) -> Option<Self>;
+}
+
+impl<P: Pixel> ImageConstructors for Image<P> {
+    type Storage = P::Storage;
+
+    fn new(width: u32, height: u32) -> Image<P> {
+        Image::from_pixel(width, height, P::CLEAR)
+    }
+
+    fn from_raw(buffer: Vec<P::Storage>, width: u32, height: u32) -> Option<Image<P>> {
+        Image::from_storage(buffer, width, height)
+    }
+}
+
+impl ImageLuma {
+    pub fn from_pixel_luma(width: u32, height: u32, pixel: u8) -> ImageLuma {
+        Image::from_pixel(width, height, pixel)
+    }
+
+    pub fn pixel_luma(&self, x: u32, y: u32) -> u8 {
+        self.pixel(x, y)
+    }
+
+    pub fn set_pixel_luma(&mut self, x: u32, y: u32, pixel: u8) {
+        self.set_pixel(x, y, pixel);
+    }
+
+    pub fn clear_luma(&mut self, pixel: u8) {
+        self.clear(pixel);
+    }
+
+    /// Converts IMAGE to grayscale, taking the Rec. 709 luminance of the
+    /// stored (not linearized) RGB. Alpha is dropped.
+    pub fn from_rgba_luminance(image: &Image) -> ImageLuma {
+        let buffer = image
+            .buffer
+            .iter()
+            .map(|&p| {
+                let [r, g, b, _] = p.to_le_bytes();
+                let luminance =
+                    0.2126 * f32::from(r) + 0.7152 * f32::from(g) + 0.0722 * f32::from(b);
+                luminance.round() as u8
+            })
+            .collect();
+
+        Image::from_buffer(image.width, image.height, buffer)
+    }
+
+    /// Converts to an opaque RGBA image, splatting the value to RGB.
+    pub fn to_rgba(&self) -> Image {
+        let buffer = self
+            .buffer
+            .iter()
+            .map(|&v| u32::from_le_bytes([v, v, v, 255]))
+            .collect();
+
+        Image::from_buffer(self.width, self.height, buffer)
+    }
+
+    /// Samples the texel containing UV, clamping to the edge.
+    pub fn sample_nearest_luma(&self, uv: Vec2) -> Vec4 {
+        Sampler::default().sample(self, uv)
+    }
+
+    /// Samples with bilinear filtering, clamping to the edge.
+    pub fn sample_bilinear_luma(&self, uv: Vec2) -> Vec4 {
+        let sampler = Sampler {
+            filter: Filter::Bilinear,
+            ..Sampler::default()
+        };
+
+        sampler.sample(self, uv)
+    }
+}
+
+impl ImageF32 {
+    pub fn pixel_rgba(&self, x: u32, y: u32) -> Vec4 {
+        self.pixel(x, y)
+    }
+
+    pub fn set_pixel_rgba(&mut self, x: u32, y: u32, pixel: Vec4) {
+        self.set_pixel(x, y, pixel);
+    }
+
+    pub fn clear_rgba(&mut self, pixel: Vec4) {
+        self.clear(pixel);
+    }
+
+    /// Converts to an 8-bit image, scaling colors by EXPOSURE and quantizing
+    /// them with ENCODING, which clamps to [0..1]. Shorthand for `tonemap`
+    /// with `Tonemap::Clamp`.
+    pub fn to_rgba(&self, exposure: f32, encoding: &ColorEncoding) -> Image {
+        tonemap(self, Tonemap::Clamp, exposure, encoding)
+    }
+}
+
+/// Operators mapping HDR colors to [0..1], applied per RGB channel.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Tonemap {
+    /// Clamp, losing all detail above 1.0.
+    #[default]
+    Clamp,
+    /// `x / (1 + x)`, compressing highlights without ever reaching 1.0.
+    Reinhard,
+    /// Krzysztof Narkowicz's fit of the ACES filmic curve, with more
+    /// contrast than Reinhard and a shoulder reaching 1.0.
+    AcesApprox,
+}
+
+impl Tonemap {
+    fn apply(self, x: f32) -> f32 {
+        // Negative colors would hit the poles of the curves
+        let x = x.max(0.0);
+        match self {
+            Tonemap::Clamp => x,
+            Tonemap::Reinhard => x / (1.0 + x),
+            Tonemap::AcesApprox => {
+                let (a, b, c, d, e) = (2.51, 0.03, 2.43, 0.59, 0.14);
+                (x * (a * x + b)) / (x * (c * x + d) + e)
+            }
+        }
+    }
+}
+
+/// Converts IMAGE to an 8-bit image in a single pass: scales RGB by
+/// EXPOSURE, applies OPERATOR and quantizes with ENCODING, so sRGB encoding
+/// and dithering happen in the same traversal. Alpha is only clamped.
+pub fn tonemap(
+    image: &ImageF32,
+    operator: Tonemap,
+    exposure: f32,
+    encoding: &ColorEncoding,
+) -> Image {
+    let mut target = Image::new(image.width as u32, image.height as u32);
+    if image.width == 0 {
+        return target;
+    }
+
+    let src_rows = image.buffer.chunks_exact(image.width);
+    let dst_rows = target.buffer.chunks_exact_mut(image.width);
+    for (y, (src_row, dst_row)) in src_rows.zip(dst_rows).enumerate() {
+        for (x, (src, dst)) in src_row.iter().zip(dst_row.iter_mut()).enumerate() {
+            let color = Vec4::new(
+                operator.apply(src.x * exposure),
+                operator.apply(src.y * exposure),
+                operator.apply(src.z * exposure),
+                src.w,
+            );
+
+            *dst = u32::from_le_bytes(encoding.encode(color, x as u32, y as u32));
+        }
+    }
+
+    target
+}
+
+/// Borrowed rectangular region of an `Image`, see `Image::view`.
+#[derive(Debug, Clone, Copy)]
+pub struct ImageView<'a, P: Pixel = u32> {
+    width: usize,
+    height: usize,
+    /// Row pitch of the parent image.
+    stride: usize,
+    buffer: &'a [P::Storage],
+    _pixel: PhantomData<P>,
+}
+
+impl<'a, P: Pixel> ImageView<'a, P> {
+    pub fn pixel(&self, x: u32, y: u32) -> P {
+        P::decode(self.buffer[self.index(x, y)])
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn index(&self, x: u32, y: u32) -> usize {
+        let (ux, uy) = (cast_usize(x), cast_usize(y));
+        assert!(
+            ux < self.width && uy < self.height,
+            "pixel ({}, {}) out of bounds for {}x{} view",
+            x,
+            y,
+            self.width,
+            self.height,
+        );
+
+        uy * self.stride + ux
+    }
+}
+
+impl<'a> ImageView<'a> {
+    pub fn pixel_rgba(&self, x: u32, y: u32) -> [u8; 4] {
+        self.pixel(x, y).to_le_bytes()
+    }
+
+    pub fn pixel_depth(&self, x: u32, y: u32) -> f32 {
+        f32::from_bits(self.pixel(x, y))
+    }
+
+    pub fn pixel_stencil(&self, x: u32, y: u32) -> u8 {
+        self.pixel(x, y) as u8
+    }
+
+    /// Samples the texel containing UV, clamping to the edge of the view.
+    pub fn sample_nearest_rgba(&self, uv: Vec2) -> Vec4 {
+        Sampler::default().sample(self, uv)
+    }
+
+    /// Samples with bilinear filtering, clamping to the edge of the view.
+    pub fn sample_bilinear_rgba(&self, uv: Vec2) -> Vec4 {
+        let sampler = Sampler {
+            filter: Filter::Bilinear,
+            ..Sampler::default()
+        };
+
+        sampler.sample(self, uv)
+    }
+}
+
+/// Mutably borrowed rectangular region of an `Image`, see
+/// `Image::view_mut`. Can be rendered into like an image.
+#[derive(Debug)]
+pub struct ImageViewMut<'a, P: Pixel = u32> {
+    width: usize,
+    height: usize,
+    /// Row pitch of the parent image.
+    stride: usize,
+    buffer: &'a mut [P::Storage],
+    _pixel: PhantomData<P>,
+}
+
+impl<'a, P: Pixel> ImageViewMut<'a, P> {
+    /// Reborrows as a read-only view.
+    pub fn as_view(&self) -> ImageView<'_, P> {
+        ImageView {
+            width: self.width,
+            height: self.height,
+            stride: self.stride,
+            buffer: self.buffer,
+            _pixel: PhantomData,
+        }
+    }
+
+    pub fn pixel(&self, x: u32, y: u32) -> P {
+        self.as_view().pixel(x, y)
+    }
+
+    pub fn set_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        let index = self.as_view().index(x, y);
+        self.buffer[index] = pixel.encode();
+    }
+
+    pub fn clear(&mut self, pixel: P) {
+        let storage = pixel.encode();
+        for row in 0..self.height {
+            let start = row * self.stride;
+            self.buffer[start..start + self.width].fill(storage);
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+}
+
+impl<'a> ImageViewMut<'a> {
+    pub fn pixel_rgba(&self, x: u32, y: u32) -> [u8; 4] {
+        self.as_view().pixel_rgba(x, y)
+    }
+
+    pub fn pixel_depth(&self, x: u32, y: u32) -> f32 {
+        self.as_view().pixel_depth(x, y)
+    }
+
+    pub fn pixel_stencil(&self, x: u32, y: u32) -> u8 {
+        self.as_view().pixel_stencil(x, y)
+    }
+
+    pub fn sample_nearest_rgba(&self, uv: Vec2) -> Vec4 {
+        self.as_view().sample_nearest_rgba(uv)
+    }
+
+    pub fn sample_bilinear_rgba(&self, uv: Vec2) -> Vec4 {
+        self.as_view().sample_bilinear_rgba(uv)
+    }
+
+    pub fn set_pixel_rgba(&mut self, x: u32, y: u32, pixel: [u8; 4]) {
+        self.set_pixel(x, y, u32::from_le_bytes(pixel));
+    }
+
+    pub fn set_pixel_depth(&mut self, x: u32, y: u32, pixel: f32) {
+        self.set_pixel(x, y, pixel.to_bits());
+    }
+
+    pub fn set_pixel_stencil(&mut self, x: u32, y: u32, pixel: u8) {
+        self.set_pixel(x, y, u32::from(pixel));
+    }
+
+    pub fn clear_rgba(&mut self, pixel: [u8; 4]) {
+        self.clear(u32::from_le_bytes(pixel));
+    }
+
+    pub fn clear_depth(&mut self, pixel: f32) {
+        self.clear(pixel.to_bits());
+    }
+
+    pub fn clear_stencil(&mut self, pixel: u8) {
+        self.clear(u32::from(pixel));
+    }
+}
+
+/// Images the pipeline can render into, i.e. `Image` and `ImageViewMut`.
+/// Color attachments need pixels implementing `ColorPixel`, depth
+/// attachments `DepthPixel` and stencil attachments the raw storage word of
+/// the default `Image`.
+pub trait RenderTarget {
+    type Pixel: Pixel;
+
+    fn dimensions(&self) -> (u32, u32);
+    fn pixel(&self, x: u32, y: u32) -> Self::Pixel;
+    fn set_pixel(&mut self, x: u32, y: u32, pixel: Self::Pixel);
+}
+
+impl<P: Pixel> RenderTarget for Image<P> {
+    type Pixel = P;
+
+    fn dimensions(&self) -> (u32, u32) {
+        Image::dimensions(self)
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> P {
+        Image::pixel(self, x, y)
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        Image::set_pixel(self, x, y, pixel);
+    }
+}
+
+impl<'a, P: Pixel> RenderTarget for ImageViewMut<'a, P> {
+    type Pixel = P;
+
+    fn dimensions(&self) -> (u32, u32) {
+        ImageViewMut::dimensions(self)
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> P {
+        ImageViewMut::pixel(self, x, y)
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        ImageViewMut::set_pixel(self, x, y, pixel);
+    }
+}
+
+/// Images the pipeline can render fragment outputs into, see
+/// `FragmentOutputs`.
+pub trait ColorTarget {
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Writes COLOR to pixel X, Y, blending it with the stored color if
+    /// ENCODING has a `blend` mode. Targets with limited precision quantize
+    /// it with ENCODING.
+    fn write_color(&mut self, x: u32, y: u32, color: Vec4, encoding: &ColorEncoding);
+
+    /// Writes a raw storage word to pixel X, Y, interpreted as RGBA8 by
+    /// targets that don't store words.
+    fn write_u32(&mut self, x: u32, y: u32, value: u32);
+}
+
+impl<T> ColorTarget for T
+where
+    T: RenderTarget,
+    T::Pixel: ColorPixel,
+{
+    fn dimensions(&self) -> (u32, u32) {
+        RenderTarget::dimensions(self)
+    }
+
+    fn write_color(&mut self, x: u32, y: u32, color: Vec4, encoding: &ColorEncoding) {
+        let color = match encoding.blend {
+            Some(blend) => blend.apply(color, self.pixel(x, y).to_linear_color(encoding)),
+            None => color,
+        };
+        self.set_pixel(x, y, T::Pixel::from_color(color, x, y, encoding));
+    }
+
+    fn write_u32(&mut self, x: u32, y: u32, value: u32) {
+        self.set_pixel(x, y, T::Pixel::from_u32(value));
+    }
+}
+
+/// How texel coordinates outside of an image are mapped back into it.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Wrap {
+    /// Tile the image.
+    Repeat,
+    /// Use the nearest edge texel.
+    #[default]
+    ClampToEdge,
+    /// Tile the image, mirroring every other tile.
+    MirroredRepeat,
+    /// Use a constant border color, typically transparent black.
+    ClampToBorder,
+}
+
+impl Wrap {
+    /// Maps texel coordinate COORD into [0..SIZE). Returns None if the
+    /// border should be used instead.
+    fn wrap(self, coord: i64, size: i64) -> Option<i64> {
+        match self {
+            Wrap::Repeat => Some(coord.rem_euclid(size)),
+            Wrap::ClampToEdge => Some(coord.clamp(0, size - 1)),
+            Wrap::MirroredRepeat => {
+                let period = coord.rem_euclid(2 * size);
+                if period < size {
+                    Some(period)
+                } else {
+                    Some(2 * size - 1 - period)
+                }
+            }
+            Wrap::ClampToBorder => {
+                if coord >= 0 && coord < size {
+                    Some(coord)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+/// Texture with a chain of mipmap levels, each half the size of the previous
+/// one (rounded down, but at least 1) down to any size. Dereferences to
+/// level 0, so it can be used in place of a plain `Image`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Texture {
+    levels: Vec<Image>,
+}
+
+impl Texture {
+    /// Creates a texture from mipmap levels. Returns None if there are no
+    /// levels or their dimensions don't form a mipmap chain.
+    pub fn from_levels(levels: Vec<Image>) -> Option<Texture> {
+        if levels.is_empty() {
+            return None;
+        }
+
+        for pair in levels.windows(2) {
+            let (width, height) = pair[0].dimensions();
+            let expected = (u32::max(width / 2, 1), u32::max(height / 2, 1));
+            if (width, height) == (1, 1) || pair[1].dimensions() != expected {
+                return None;
+            }
+        }
+
+        Some(Texture { levels })
+    }
+
+    pub fn into_levels(self) -> Vec<Image> {
+        self.levels
+    }
+
+    pub fn levels(&self) -> &[Image] {
+        &self.levels
+    }
+
+    pub fn level(&self, level: usize) -> &Image {
+        &self.levels[level]
+    }
+
+    pub fn level_count(&self) -> usize {
+        self.levels.len()
+    }
+
+    /// Samples with SAMPLER at an explicit LOD, e.g. computed from the
+    /// distance to the camera. LOD is clamped to the available levels, so
+    /// negative values select level 0 and values past the last level select
+    /// the smallest one. Fractional LODs blend between adjacent levels if
+    /// the sampler filter is `Filter::Trilinear`, otherwise they select the
+    /// nearest level.
+    pub fn sample_lod(&self, sampler: &Sampler, uv: Vec2, lod: f32) -> Vec4 {
+        sampler.sample_lod(self, uv, lod)
+    }
+
+    /// Samples with bilinear filtering in the two levels nearest to LOD and
+    /// blends between them. LOD is clamped to the available levels.
+    pub fn sample_trilinear(&self, uv: Vec2, lod: f32) -> Vec4 {
+        let sampler = Sampler {
+            filter: Filter::Trilinear,
+            ..Sampler::default()
+        };
+
+        sampler.sample_lod(self, uv, lod)
+    }
+
+    /// Creates a texture from IMAGE, generating the rest of the mipmap chain
+    /// with `generate_mipmaps`.
+    pub fn from_image(image: Image, srgb: bool) -> Texture {
+        let (mut width, mut height) = image.dimensions();
+        let mut levels = vec![image];
+        while (width, height) != (1, 1) {
+            width = u32::max(width / 2, 1);
+            height = u32::max(height / 2, 1);
+
+            // Filter from level 0, so that rounding errors don't accumulate
+            levels.push(resample_area(&levels[0], width, height, srgb));
+        }
+
+        Texture { levels }
+    }
+}
+
+/// Returns the full mipmap chain for IMAGE, from a copy of the image itself
+/// down to 1x1.
+///
+/// Each level is half the size of the previous one and box filtered from the
+/// image. For odd dimensions, each texel of the smaller level covers one and
+/// a half texels of the larger one, weighted by area, so no row or column is
+/// dropped. If SRGB is set, RGB is averaged in linear space.
+pub fn generate_mipmaps(image: &Image, srgb: bool) -> Vec<Image> {
+    Texture::from_image(image.clone(), srgb).into_levels()
+}
+
+/// Resamples IMAGE to the given dimensions, averaging all source texels
+/// covered by each destination texel weighted by their coverage.
+fn resample_area(image: &Image, dst_width: u32, dst_height: u32, srgb: bool) -> Image {
+    let weights_x = area_weights(image.width, cast_usize(dst_width));
+    let weights_y = area_weights(image.height, cast_usize(dst_height));
+
+    let mut dst = Image::new(dst_width, dst_height);
+    for (dy, wy) in weights_y.iter().enumerate() {
+        for (dx, wx) in weights_x.iter().enumerate() {
+            let mut color = Vec4::ZERO;
+            for &(sy, weight_y) in wy {
+                for &(sx, weight_x) in wx {
+                    let texel = rgba_to_vec(image.pixel_rgba(sx as u32, sy as u32));
+                    let texel = if srgb {
+                        Vec4::new(
+                            srgb_to_linear(texel.x),
+                            srgb_to_linear(texel.y),
+                            srgb_to_linear(texel.z),
+                            texel.w,
+                        )
+                    } else {
+                        texel
+                    };
+
+                    color += texel * (weight_x * weight_y);
+                }
+            }
+
+            if srgb {
+                color = Vec4::new(
+                    linear_to_srgb(color.x),
+                    linear_to_srgb(color.y),
+                    linear_to_srgb(color.z),
+                    color.w,
+                );
+            }
+
+            dst.set_pixel_rgba(dx as u32, dy as u32, vec_to_rgba(color));
+        }
+    }
+
+    dst
+}
+
+/// For each of DST texels along an axis, returns the SRC texels it covers
+/// and their normalized coverage weights.
+fn area_weights(src: usize, dst: usize) -> Vec<Vec<(usize, f32)>> {
+    let scale = src as f32 / dst as f32;
+
+    (0..dst)
+        .map(|d| {
+            let start = d as f32 * scale;
+            let end = (d + 1) as f32 * scale;
+
+            let first = start.floor() as usize;
+            let last = usize::min(end.ceil() as usize, src);
+
+            (first..last)
+                .map(|s| {
+                    let overlap = f32::min(end, (s + 1) as f32) - f32::max(start, s as f32);
+                    (s, overlap / scale)
+                })
+                .filter(|&(_, weight)| weight > 0.0)
+                .collect()
+        })
+        .collect()
+}
+
+impl From<Image> for Texture {
+    fn from(image: Image) -> Texture {
+        Texture {
+            levels: vec![image],
+        }
+    }
+}
+
+impl AsRef<Image> for Texture {
+    fn as_ref(&self) -> &Image {
+        &self.levels[0]
+    }
+}
+
+impl Deref for Texture {
+    type Target = Image;
+
+    fn deref(&self) -> &Image {
+        &self.levels[0]
+    }
+}
+
+/// Face of a `CubeMap`, in the order of GL's cube map targets.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum CubeFace {
+    PositiveX,
+    NegativeX,
+    PositiveY,
+    NegativeY,
+    PositiveZ,
+    NegativeZ,
+}
+
+impl CubeFace {
+    pub const ALL: [CubeFace; 6] = [
+        CubeFace::PositiveX,
+        CubeFace::NegativeX,
+        CubeFace::PositiveY,
+        CubeFace::NegativeY,
+        CubeFace::PositiveZ,
+        CubeFace::NegativeZ,
+    ];
+
+    /// Selects the face for DIRECTION by its dominant axis and returns it
+    /// with the face UV, following GL's cube map convention.
+    pub fn from_direction(direction: Vec3) -> (CubeFace, Vec2) {
+        let abs = direction.abs();
+
+        let (face, sc, tc, ma) = if abs.x >= abs.y && abs.x >= abs.z {
+            if direction.x >= 0.0 {
+                (CubeFace::PositiveX, -direction.z, -direction.y, abs.x)
+            } else {
+                (CubeFace::NegativeX, direction.z, -direction.y, abs.x)
+            }
+        } else if abs.y >= abs.z {
+            if direction.y >= 0.0 {
+                (CubeFace::PositiveY, direction.x, direction.z, abs.y)
+            } else {
+                (CubeFace::NegativeY, direction.x, -direction.z, abs.y)
+            }
+        } else if direction.z >= 0.0 {
+            (CubeFace::PositiveZ, direction.x, -direction.y, abs.z)
+        } else {
+            (CubeFace::NegativeZ, -direction.x, -direction.y, abs.z)
+        };
+
+        let uv = Vec2::new((sc / ma + 1.0) / 2.0, (tc / ma + 1.0) / 2.0);
+        (face, uv)
+    }
+}
+
+/// Six square faces of equal size, sampled by direction.
+///
+/// Like in GL, face UV (0, 0) is row 0 of the face image, so faces are
+/// expected in the row order they are usually stored in files, top row
+/// first, and should not be flipped vertically like 2D textures.
+#[derive(Debug, PartialEq, Clone)]
+pub struct CubeMap {
+    faces: Vec<Image>,
+}
+
+impl CubeMap {
+    /// Creates a cube map from faces in `CubeFace::ALL` order. Returns None
+    /// if the faces are not square or of different sizes.
+    pub fn from_faces(faces: [Image; 6]) -> Option<CubeMap> {
+        let size = faces[0].width();
+        if size == 0 || faces.iter().any(|f| f.dimensions() != (size, size)) {
+            return None;
+        }
+
+        Some(CubeMap {
+            faces: faces.to_vec(),
+        })
+    }
+
+    /// Creates a cube map from a horizontal cross layout, 4 faces wide and 3
+    /// faces high, top row first:
+    ///
+    /// ```text
+    ///       +Y
+    ///   -X  +Z  +X  -Z
+    ///       -Y
+    /// ```
+    ///
+    /// Returns None if the dimensions don't match the layout.
+    pub fn from_cross(image: &Image) -> Option<CubeMap> {
+        let (width, height) = image.dimensions();
+        let size = width / 4;
+        if size == 0 || width != size * 4 || height != size * 3 {
+            return None;
+        }
+
+        let face_at = |col: u32, row: u32| {
+            let mut face = Image::new(size, size);
+            for y in 0..size {
+                for x in 0..size {
+                    let pixel = image.pixel_u32(col * size + x, row * size + y);
+                    face.set_pixel_u32(x, y, pixel);
+                }
+            }
+            face
+        };
+
+        CubeMap::from_faces([
+            face_at(2, 1),
+            face_at(0, 1),
+            face_at(1, 0),
+            face_at(1, 2),
+            face_at(1, 1),
+            face_at(3, 1),
+        ])
+    }
+
+    pub fn face(&self, face: CubeFace) -> &Image {
+        &self.faces[face as usize]
+    }
+
+    pub fn size(&self) -> u32 {
+        self.faces[0].width()
+    }
+
+    /// Samples the texel hit by DIRECTION, which doesn't need to be
+    /// normalized.
+    pub fn sample(&self, direction: Vec3) -> Vec4 {
+        let (face, uv) = CubeFace::from_direction(direction);
+        let size = self.size() as f32;
+        let max = self.size() - 1;
+
+        let x = ((uv.x * size) as u32).min(max);
+        let y = ((uv.y * size) as u32).min(max);
+
+        rgba_to_vec(self.face(face).pixel_rgba(x, y))
+    }
+}
+
+/// Images whose pixels can be read as float RGBA texels.
+pub trait Texels {
+    fn dimensions(&self) -> (u32, u32);
+
+    /// Reads texel X, Y, which must be inside the image.
+    fn texel(&self, x: u32, y: u32) -> Vec4;
+}
+
+impl<P: ColorPixel> Texels for Image<P> {
+    fn dimensions(&self) -> (u32, u32) {
+        Image::dimensions(self)
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Vec4 {
+        self.pixel(x, y).to_color()
+    }
+}
+
+impl<'a, P: ColorPixel> Texels for ImageView<'a, P> {
+    fn dimensions(&self) -> (u32, u32) {
+        ImageView::dimensions(self)
+    }
+
+    fn texel(&self, x: u32, y: u32) -> Vec4 {
+        self.pixel(x, y).to_color()
+    }
+}
+
+/// Types that can be sampled with a `Sampler`.
+pub trait MipLevels {
+    type Level: Texels;
+
+    /// Mipmap levels, starting with the full resolution level 0.
+    fn mip_levels(&self) -> &[Self::Level];
+}
+
+impl<P: ColorPixel> MipLevels for Image<P> {
+    type Level = Image<P>;
+
+    fn mip_levels(&self) -> &[Image<P>] {
+        slice::from_ref(self)
+    }
+}
+
+impl<'a, P: ColorPixel> MipLevels for ImageView<'a, P> {
+    type Level = ImageView<'a, P>;
+
+    fn mip_levels(&self) -> &[ImageView<'a, P>] {
+        slice::from_ref(self)
+    }
+}
+
+impl MipLevels for Texture {
+    type Level = Image;
+
+    fn mip_levels(&self) -> &[Image] {
+        &self.levels
+    }
+}
+
+/// Texture filtering.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Filter {
+    /// Use the texel containing the sample, in the mipmap level nearest to
+    /// the LOD.
+    #[default]
+    Nearest,
+    /// Blend the four nearest texels, in the mipmap level nearest to the
+    /// LOD.
+    Bilinear,
+    /// Filter bilinearly in the two mipmap levels nearest to the LOD and
+    /// blend between them.
+    Trilinear,
+}
+
+/// Source of a channel of a sampled color, see `Sampler::swizzle`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Swizzle {
+    R,
+    G,
+    B,
+    A,
+    Zero,
+    One,
+}
+
+impl Swizzle {
+    pub const IDENTITY: [Swizzle; 4] = [Swizzle::R, Swizzle::G, Swizzle::B, Swizzle::A];
+
+    fn select(self, color: Vec4) -> f32 {
+        match self {
+            Swizzle::R => color.x,
+            Swizzle::G => color.y,
+            Swizzle::B => color.z,
+            Swizzle::A => color.w,
+            Swizzle::Zero => 0.0,
+            Swizzle::One => 1.0,
+        }
+    }
+}
+
+/// Sampling state, kept separate from the sampled image or texture data.
+///
+/// Texel centers are at half-integer coordinates, i.e. UV 0.0 and 1.0 are
+/// the outer edges of the image.
+///
+/// Filtering averages texels as stored, without regard to alpha. Textures
+/// with straight alpha bleed the color of transparent texels into the edges
+/// of cutouts, so convert them with `Image::premultiply_alpha` and render
+/// them with `BlendMode::Premultiplied`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Sampler {
+    pub filter: Filter,
+    pub wrap_u: Wrap,
+    pub wrap_v: Wrap,
+    /// Color of texels outside of a `Wrap::ClampToBorder` axis.
+    pub border_color: Vec4,
+    /// Decode texel RGB from sRGB to linear before filtering. The border
+    /// color is already linear.
+    pub srgb: bool,
+    /// Sources of the R, G, B and A channels of the sampled color, applied
+    /// after filtering. E.g. `[R, R, R, One]` samples a grayscale image
+    /// stored in the red channel as opaque gray.
+    pub swizzle: [Swizzle; 4],
+}
+
+impl Default for Sampler {
+    fn default() -> Self {
+        Sampler {
+            filter: Filter::Nearest,
+            wrap_u: Wrap::ClampToEdge,
+            wrap_v: Wrap::ClampToEdge,
+            border_color: Vec4::ZERO,
+            srgb: false,
+            swizzle: Swizzle::IDENTITY,
+        }
+    }
+}
+
+impl Sampler {
+    /// Samples level 0 of TEXTURE at UV.
+    pub fn sample<T: MipLevels + ?Sized>(&self, texture: &T, uv: Vec2) -> Vec4 {
+        self.sample_lod(texture, uv, 0.0)
+    }
+
+    /// Samples TEXTURE at UV from the mipmap levels selected by LOD, which is
+    /// clamped to the available levels.
+    pub fn sample_lod<T: MipLevels + ?Sized>(&self, texture: &T, uv: Vec2, lod: f32) -> Vec4 {
+        let levels = texture.mip_levels();
+        let max_lod = (levels.len() - 1) as f32;
+        let lod = if lod.is_nan() {
+            0.0
+        } else {
+            lod.clamp(0.0, max_lod)
+        };
+
+        let color = match self.filter {
+            Filter::Nearest => self.sample_nearest(&levels[lod.round() as usize], uv),
+            Filter::Bilinear => self.sample_bilinear(&levels[lod.round() as usize], uv),
+            Filter::Trilinear => {
+                let level = lod.floor();
+                let t = lod - level;
+                let level = level as usize;
+
+                let near = self.sample_bilinear(&levels[level], uv);
+                if t == 0.0 {
+                    near
+                } else {
+                    let far = self.sample_bilinear(&levels[level + 1], uv);
+                    near.lerp(far, t)
+                }
+            }
+        };
+
+        if self.swizzle == Swizzle::IDENTITY {
+            color
+        } else {
+            let [r, g, b, a] = self.swizzle;
+            Vec4::new(
+                r.select(color),
+                g.select(color),
+                b.select(color),
+                a.select(color),
+            )
+        }
+    }
+
+    /// Samples TEXTURE at UV, selecting mipmap levels from the screen space
+    /// derivatives of UV, e.g. `FragmentInput::dvar_dx`.
+    pub fn sample_grad<T: MipLevels + ?Sized>(
+        &self,
+        texture: &T,
+        uv: Vec2,
+        duv_dx: Vec2,
+        duv_dy: Vec2,
+    ) -> Vec4 {
+        let lod = Sampler::lod(texture, duv_dx, duv_dy);
+        self.sample_lod(texture, uv, lod)
+    }
+
+    /// Computes the LOD for the screen space derivatives of UV: the log2 of
+    /// the larger of the texel footprints along screen X and Y.
+    pub fn lod<T: MipLevels + ?Sized>(texture: &T, duv_dx: Vec2, duv_dy: Vec2) -> f32 {
+        let (width, height) = texture.mip_levels()[0].dimensions();
+        let size = Vec2::new(width as f32, height as f32);
+
+        let footprint_x = (duv_dx * size).length_squared();
+        let footprint_y = (duv_dy * size).length_squared();
+
+        // log2(sqrt(x)) == log2(x) / 2
+        f32::max(footprint_x, footprint_y).log2() / 2.0
+    }
+
+    fn sample_nearest<I: Texels>(&self, image: &I, uv: Vec2) -> Vec4 {
+        let (width, height) = image.dimensions();
+        let x = (uv.x * width as f32).floor() as i64;
+        let y = (uv.y * height as f32).floor() as i64;
+
+        self.texel(image, x, y)
+    }
+
+    fn sample_bilinear<I: Texels>(&self, image: &I, uv: Vec2) -> Vec4 {
+        let (width, height) = image.dimensions();
+        let x = uv.x * width as f32 - 0.5;
+        let y = uv.y * height as f32 - 0.5;
+
+        let x0 = x.floor();
+        let y0 = y.floor();
+        let tx = x - x0;
+        let ty = y - y0;
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        let c00 = self.texel(image, x0, y0);
+        let c10 = self.texel(image, x0 + 1, y0);
+        let c01 = self.texel(image, x0, y0 + 1);
+        let c11 = self.texel(image, x0 + 1, y0 + 1);
+
+        let top = c00.lerp(c10, tx);
+        let bottom = c01.lerp(c11, tx);
+        top.lerp(bottom, ty)
+    }
+
+    /// Returns texel X, Y wrapped into IMAGE, or the border color if it is
+    /// outside a `Wrap::ClampToBorder` axis.
+    fn texel<I: Texels>(&self, image: &I, x: i64, y: i64) -> Vec4 {
+        let (width, height) = image.dimensions();
+        let x = self.wrap_u.wrap(x, i64::from(width));
+        let y = self.wrap_v.wrap(y, i64::from(height));
+
+        match (x, y) {
+            (Some(x), Some(y)) => {
+                let texel = image.texel(x as u32, y as u32);
+                if self.srgb {
+                    Vec4::new(
+                        srgb_to_linear(texel.x),
+                        srgb_to_linear(texel.y),
+                        srgb_to_linear(texel.z),
+                        texel.w,
+                    )
+                } else {
+                    texel
+                }
+            }
+            _ => self.border_color,
+        }
+    }
+}
+
+/// How fragment colors are combined with the colors stored in the target.
+/// Colors are blended in linear space, i.e. stored colors are decoded from
+/// sRGB first if `ColorEncoding::srgb` is set. The resulting alpha is
+/// `src_alpha + dst_alpha * (1 - src_alpha)` for both modes.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum BlendMode {
+    /// `src * src_alpha + dst * (1 - src_alpha)`, for colors with straight
+    /// alpha. Factors `SrcAlpha, OneMinusSrcAlpha` in OpenGL terms.
+    Alpha,
+    /// `src + dst * (1 - src_alpha)`, for colors with premultiplied alpha,
+    /// e.g. sampled from textures converted by `Image::premultiply_alpha`.
+    /// Factors `One, OneMinusSrcAlpha` in OpenGL terms.
+    Premultiplied,
+}
+
+impl BlendMode {
+    /// Blends SRC over DST.
+    pub fn apply(self, src: Vec4, dst: Vec4) -> Vec4 {
+        let src_factor = match self {
+            BlendMode::Alpha => src.w,
+            BlendMode::Premultiplied => 1.0,
+        };
+        let color = src.truncate() * src_factor + dst.truncate() * (1.0 - src.w);
+
+        color.extend(src.w + dst.w * (1.0 - src.w))
+    }
+}
+
+/// How float colors are quantized to 8-bit RGBA when written to an image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct ColorEncoding {
+    /// Encode RGB from linear to sRGB with the piecewise sRGB curve. Alpha
+    /// stays linear.
+    pub srgb: bool,
+    /// Apply a 4x4 ordered (Bayer) dither to RGB before quantizing to hide
+    /// banding. The dither only depends on the pixel coordinates, so output
+    /// stays deterministic.
+    pub dither: bool,
+    /// Blend written colors with the stored ones, see `BlendMode`.
+    pub blend: Option<BlendMode>,
+}
+
+impl ColorEncoding {
+    /// Quantizes COLOR written to pixel X, Y.
+    pub fn encode(&self, color: Vec4, x: u32, y: u32) -> [u8; 4] {
+        let mut color = if self.srgb {
+            Vec4::new(
+                linear_to_srgb(color.x.clamp(0.0, 1.0)),
+                linear_to_srgb(color.y.clamp(0.0, 1.0)),
+                linear_to_srgb(color.z.clamp(0.0, 1.0)),
+                color.w,
+            )
+        } else {
+            color
+        };
+
+        if self.dither {
+            // Quantization rounds, so offset by a threshold in [-0.5..0.5)
+            // quantization steps
+            let threshold = (BAYER_4X4[(y % 4) as usize][(x % 4) as usize] + 0.5) / 16.0;
+            let offset = (threshold - 0.5) / 255.0;
+            color.x += offset;
+            color.y += offset;
+            color.z += offset;
+        }
+
+        vec_to_rgba(color)
+    }
+
+    /// Decodes COLOR stored with this encoding back to linear. Inverse of
+    /// `encode`, up to quantization.
+    pub fn decode(&self, color: Vec4) -> Vec4 {
+        if self.srgb {
+            Vec4::new(
+                srgb_to_linear(color.x),
+                srgb_to_linear(color.y),
+                srgb_to_linear(color.z),
+                color.w,
+            )
+        } else {
+            color
+        }
+    }
+}
+
+const BAYER_4X4: [[f32; 4]; 4] = [
+    [0.0, 8.0, 2.0, 10.0],
+    [12.0, 4.0, 14.0, 6.0],
+    [3.0, 11.0, 1.0, 9.0],
+    [15.0, 7.0, 13.0, 5.0],
+];
+
+/// Image storing `SAMPLES` values per pixel for multisample anti-aliasing.
+/// Like `Image`, the samples can be interpreted as RGBA or depth.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MultisampleImage {
+    width: usize,
+    height: usize,
+    buffer: Vec<u32>,
+}
+
+impl MultisampleImage {
+    pub const SAMPLES: usize = 4;
+
+    pub fn new(width: u32, height: u32) -> MultisampleImage {
+        let w = cast_usize(width);
+        let h = cast_usize(height);
+
+        MultisampleImage {
+            width: w,
+            height: h,
+            buffer: vec![0; w * h * Self::SAMPLES],
+        }
+    }
+
+    pub fn from_pixel_rgba(width: u32, height: u32, pixel: [u8; 4]) -> MultisampleImage {
+        let mut image = MultisampleImage::new(width, height);
+        image.clear_rgba(pixel);
+
+        image
+    }
+
+    pub fn from_pixel_depth(width: u32, height: u32, pixel: f32) -> MultisampleImage {
+        let mut image = MultisampleImage::new(width, height);
+        image.clear_depth(pixel);
+
+        image
+    }
+
+    pub fn sample_rgba(&self, x: u32, y: u32, sample: usize) -> [u8; 4] {
+        self.buffer[self.index(x, y, sample)].to_le_bytes()
+    }
+
+    pub fn sample_depth(&self, x: u32, y: u32, sample: usize) -> f32 {
+        f32::from_bits(self.buffer[self.index(x, y, sample)])
+    }
+
+    pub fn set_sample_rgba(&mut self, x: u32, y: u32, sample: usize, pixel: [u8; 4]) {
+        let index = self.index(x, y, sample);
+        self.buffer[index] = u32::from_le_bytes(pixel);
+    }
+
+    pub fn set_sample_depth(&mut self, x: u32, y: u32, sample: usize, pixel: f32) {
+        let index = self.index(x, y, sample);
+        self.buffer[index] = pixel.to_bits();
+    }
+
+    pub fn clear_rgba(&mut self, pixel: [u8; 4]) {
+        let pixel_u32 = u32::from_le_bytes(pixel);
+        for p in self.buffer.iter_mut() {
+            *p = pixel_u32;
+        }
+    }
+
+    pub fn clear_depth(&mut self, pixel: f32) {
+        let pixel_u32 = pixel.to_bits();
+        for p in self.buffer.iter_mut() {
+            *p = pixel_u32;
+        }
+    }
+
+    /// Averages the RGBA samples of each pixel into TARGET, which must have
+    /// equal dimensions.
+    pub fn resolve_rgba(&self, target: &mut Image) {
+        assert!(
+            target.dimensions() == self.dimensions(),
+            "images must have equal dims"
+        );
+
+        let samples = self.buffer.chunks_exact(Self::SAMPLES);
+        for (p, pixel_samples) in target.buffer.iter_mut().zip(samples) {
+            let mut sum = [0u32; 4];
+            for sample in pixel_samples {
+                for (s, channel) in sum.iter_mut().zip(sample.to_le_bytes().iter()) {
+                    *s += u32::from(*channel);
+                }
+            }
+
+            let n = Self::SAMPLES as u32;
+            let mut pixel = [0; 4];
+            for (c, s) in pixel.iter_mut().zip(sum.iter()) {
+                *c = ((s + n / 2) / n) as u8;
+            }
+
+            *p = u32::from_le_bytes(pixel);
+        }
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width as u32
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height as u32
+    }
+
+    fn index(&self, x: u32, y: u32, sample: usize) -> usize {
+        let (ux, uy) = (cast_usize(x), cast_usize(y));
+        assert!(
+            ux < self.width && uy < self.height && sample < Self::SAMPLES,
+            "sample {} of pixel ({}, {}) out of bounds for {}x{} image",
+            sample,
+            x,
+            y,
+            self.width,
+            self.height,
+        );
+
+        (uy * self.width + ux) * Self::SAMPLES + sample
+    }
+}
+
+/// Percentage-closer filtering kernel used by `DepthSampler`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum PcfKernel {
+    /// Single comparison against the nearest texel.
+    None,
+    /// Four comparisons, bilinearly weighted by the sample position.
+    Bilinear2x2,
+    /// Nine comparisons around the nearest texel, averaged.
+    Box3x3,
+}
+
+/// What `DepthSampler` returns for UVs outside [0..1].
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ShadowBorder {
+    /// Always lit, i.e. 1.0.
+    Lit,
+    /// Clamp the UVs to the edge of the depth image.
+    Clamp,
+}
+
+/// Samples a depth image (e.g. a shadow map) by comparing a reference depth
+/// against the stored depths.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct DepthSampler {
+    /// A comparison passes if `reference` compares to the stored depth.
+    pub func: CompareFunc,
+    pub kernel: PcfKernel,
+    pub border: ShadowBorder,
+}
+
+impl Default for DepthSampler {
+    fn default() -> Self {
+        DepthSampler {
+            func: CompareFunc::LessEqual,
+            kernel: PcfKernel::Bilinear2x2,
+            border: ShadowBorder::Lit,
+        }
+    }
+}
+
+impl DepthSampler {
+    /// Returns the fraction of passing comparisons in [0..1], i.e. 1.0 for
+    /// fully lit and 0.0 for fully shadowed. Empty images have no texels to
+    /// cast shadows, so they are fully lit.
+    pub fn sample_compare(&self, image: &Image, uv: Vec2, reference: f32) -> f32 {
+        let outside = uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0;
+        let empty = image.width == 0 || image.height == 0;
+        if empty || outside && self.border == ShadowBorder::Lit {
+            return 1.0;
+        }
+
+        let u = uv.x.clamp(0.0, 1.0) * image.width as f32;
+        let v = uv.y.clamp(0.0, 1.0) * image.height as f32;
+
+        match self.kernel {
+            PcfKernel::None => self.compare_texel(image, u as i64, v as i64, reference),
+            PcfKernel::Bilinear2x2 => {
+                let fx = u - 0.5;
+                let fy = v - 0.5;
+                let x0 = fx.floor();
+                let y0 = fy.floor();
+                let tx = fx - x0;
+                let ty = fy - y0;
+                let (x0, y0) = (x0 as i64, y0 as i64);
+
+                let c00 = self.compare_texel(image, x0, y0, reference);
+                let c10 = self.compare_texel(image, x0 + 1, y0, reference);
+                let c01 = self.compare_texel(image, x0, y0 + 1, reference);
+                let c11 = self.compare_texel(image, x0 + 1, y0 + 1, reference);
+
+                let top = c00 + (c10 - c00) * tx;
+                let bottom = c01 + (c11 - c01) * tx;
+                top + (bottom - top) * ty
+            }
+            PcfKernel::Box3x3 => {
+                let (x, y) = (u as i64, v as i64);
+                let mut lit = 0.0;
+                for dy in -1..=1 {
+                    for dx in -1..=1 {
+                        lit += self.compare_texel(image, x + dx, y + dy, reference);
+                    }
+                }
+                lit / 9.0
+            }
+        }
+    }
+
+    /// Compares against texel X, Y, clamped to the image edges.
+    fn compare_texel(&self, image: &Image, x: i64, y: i64, reference: f32) -> f32 {
+        let x = x.clamp(0, image.width as i64 - 1) as u32;
+        let y = y.clamp(0, image.height as i64 - 1) as u32;
+
+        if self.func.compare(reference, image.pixel_depth(x, y)) {
+            1.0
+        } else {
+            0.0
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    #[should_panic(expected = "pixel (3, 1) out of bounds for 3x2 image")]
+    fn pixel_out_of_bounds() {
+        Image::new(3, 2).pixel(3, 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (0, 2) out of bounds for 3x2 image")]
+    fn set_pixel_out_of_bounds() {
+        Image::new(3, 2).set_pixel(0, 2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (2, 0) out of bounds for 2x1 view")]
+    fn view_pixel_out_of_bounds() {
+        let image = Image::new(4, 4);
+        image.view(1, 1, 2, 1).pixel(2, 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "pixel (0, 1) out of bounds for 2x1 view")]
+    fn view_mut_set_pixel_out_of_bounds() {
+        let mut image = Image::new(4, 4);
+        image.view_mut(1, 1, 2, 1).set_pixel(0, 1, 0);
+    }
+
+    #[test]
+    fn try_pixel_out_of_bounds() {
+        let mut image = Image::new(3, 2);
+        assert_eq!(image.try_pixel(3, 0), None);
+        assert!(!image.try_set_pixel(0, 2, 1));
+        assert!(image.try_set_pixel(2, 1, 1));
+        assert_eq!(image.try_pixel(2, 1), Some(1));
+    }
+
+    #[test]
+    fn sample_compare_filters_shadow_edge() {
+        // Left half shadowed, right half lit
+        let mut image = Image::from_pixel_depth(4, 1, 1.0);
+        image.fill_rect_depth(0, 0, 2, 1, 0.25);
+        let sample = |kernel, u: f32| {
+            let sampler = DepthSampler {
+                kernel,
+                ..DepthSampler::default()
+            };
+            sampler.sample_compare(&image, Vec2::new(u / 4.0, 0.5), 0.5)
+        };
+
+        assert_eq!(sample(PcfKernel::None, 1.9), 0.0);
+        assert_eq!(sample(PcfKernel::None, 2.1), 1.0);
+
+        // Bilinear weights ramp between the centers of the texels on either
+        // side of the edge
+        assert_eq!(sample(PcfKernel::Bilinear2x2, 1.5), 0.0);
+        assert_eq!(sample(PcfKernel::Bilinear2x2, 2.0), 0.5);
+        assert_eq!(sample(PcfKernel::Bilinear2x2, 2.5), 1.0);
+        let ramp: Vec<f32> = (0..=10)
+            .map(|i| sample(PcfKernel::Bilinear2x2, 1.5 + i as f32 / 10.0))
+            .collect();
+        assert!(ramp.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", ramp);
+
+        // The rows above and below are clamped to the only one
+        assert!((sample(PcfKernel::Box3x3, 2.0) - 2.0 / 3.0).abs() < 1e-6);
+        assert!((sample(PcfKernel::Box3x3, 1.5) - 1.0 / 3.0).abs() < 1e-6);
+        assert_eq!(sample(PcfKernel::Box3x3, 3.5), 1.0);
+    }
+
+    #[test]
+    fn shadow_map_edge_is_filtered() {
+        use crate::shaders::{UnlitColor, UnlitColorAttribute};
+        use crate::{Pipeline, PipelineOptions};
+        use glam::Mat4;
+
+        let quad = |x0: f32, x1: f32, y: f32| {
+            let corners = [
+                Vec3::new(x0, y, -4.0),
+                Vec3::new(x1, y, -4.0),
+                Vec3::new(x1, y, 4.0),
+                Vec3::new(x0, y, 4.0),
+            ];
+            let [a, b, c, d] = corners;
+            UnlitColorAttribute::solid(&[a, b, c, a, c, d], Vec4::ONE)
+        };
+
+        // A directional light slightly tilted off the vertical, casting the
+        // shadow of an occluder covering X < 0 onto the ground plane
+        let light_view = Mat4::look_at_rh(Vec3::new(2.0, 8.0, 0.0), Vec3::ZERO, Vec3::Z);
+        let light_proj = Mat4::orthographic_rh_gl(-3.0, 3.0, -3.0, 3.0, 0.1, 20.0);
+        let light = light_proj * light_view;
+
+        let mut shadow_map = Image::from_pixel_depth(32, 32, 1.0);
+        let mut buffer = quad(-4.0, 4.0, 0.0);
+        buffer.extend(quad(-4.0, 0.0, 1.0));
+        Pipeline::with_options(PipelineOptions::default()).depth_prepass(
+            &UnlitColor::new(light),
+            &buffer,
+            &mut shadow_map,
+        );
+
+        // Walk the ground plane across the shadow edge at X = -0.25
+        let walk = |kernel| -> Vec<f32> {
+            let sampler = DepthSampler {
+                kernel,
+                ..DepthSampler::default()
+            };
+            (0..=64)
+                .map(|i| {
+                    let ground = Vec3::new(-1.25 + i as f32 / 32.0, 0.0, 0.0);
+                    let clip = light * ground.extend(1.0);
+                    let ndc = clip.truncate() / clip.w;
+                    let uv = Vec2::new(ndc.x + 1.0, 1.0 - ndc.y) * 0.5;
+                    let reference = ndc.z * 0.5 + 0.5 - 0.01;
+                    sampler.sample_compare(&shadow_map, uv, reference)
+                })
+                .collect()
+        };
+
+        for &kernel in &[PcfKernel::None, PcfKernel::Bilinear2x2, PcfKernel::Box3x3] {
+            let lit = walk(kernel);
+            assert_eq!(lit[0], 0.0, "{:?} {:?}", kernel, lit);
+            assert_eq!(lit[64], 1.0, "{:?} {:?}", kernel, lit);
+            assert!(lit.windows(2).all(|pair| pair[0] <= pair[1]), "{:?}", lit);
+
+            let penumbra = lit.iter().filter(|&&l| l > 0.0 && l < 1.0).count();
+            if kernel == PcfKernel::None {
+                assert_eq!(penumbra, 0, "{:?}", lit);
+            } else {
+                assert!(penumbra >= 3, "{:?} {:?}", kernel, lit);
+            }
+        }
+    }
+
+    #[test]
+    fn sample_compare_empty_image_is_lit() {
+        let image = Image::new(0, 0);
+        for &kernel in &[PcfKernel::None, PcfKernel::Bilinear2x2, PcfKernel::Box3x3] {
+            let sampler = DepthSampler {
+                kernel,
+                border: ShadowBorder::Clamp,
+                ..DepthSampler::default()
+            };
+            assert_eq!(sampler.sample_compare(&image, Vec2::splat(0.5), 0.0), 1.0);
+        }
+    }
+
+    #[test]
+    fn mipmaps_end_in_mean() {
+        let image = Image::uv_grid(300, 200);
+
+        let levels = generate_mipmaps(&image, false);
+        let dimensions: Vec<_> = levels.iter().map(Image::dimensions).collect();
+        assert_eq!(
+            dimensions,
+            [
+                (300, 200),
+                (150, 100),
+                (75, 50),
+                (37, 25),
+                (18, 12),
+                (9, 6),
+                (4, 3),
+                (2, 1),
+                (1, 1),
+            ]
+        );
+
+        for &srgb in &[false, true] {
+            let decode = |value: f32| if srgb { srgb_to_linear(value) } else { value };
+            let mut sum = Vec4::ZERO;
+            for (_, _, pixel) in image.enumerate_pixels_rgba() {
+                let color = rgba_to_vec(pixel);
+                sum += Vec4::new(decode(color.x), decode(color.y), decode(color.z), color.w);
+            }
+            let mean = sum / (300.0 * 200.0);
+            let encode = |value: f32| if srgb { linear_to_srgb(value) } else { value };
+            let mean = vec_to_rgba(Vec4::new(
+                encode(mean.x),
+                encode(mean.y),
+                encode(mean.z),
+                mean.w,
+            ));
+
+            let levels = generate_mipmaps(&image, srgb);
+            let last = levels.last().unwrap().pixel_rgba(0, 0);
+            for (channel, (&actual, &expected)) in last.iter().zip(&mean).enumerate() {
+                assert!(
+                    (i32::from(actual) - i32::from(expected)).abs() <= 1,
+                    "channel {} is {}, mean {}, srgb {}",
+                    channel,
+                    actual,
+                    expected,
+                    srgb,
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn clamp_to_border_outside_edges() {
+        // Edge midpoints are between a white and a black cell
+        let image = Image::checkerboard(4, 4, 2, [255; 4], [0, 0, 0, 255]);
+        let border_color = Vec4::new(0.25, 0.5, 0.75, 0.0);
+        let sampler = |filter| Sampler {
+            filter,
+            wrap_u: Wrap::ClampToBorder,
+            wrap_v: Wrap::ClampToBorder,
+            border_color,
+            ..Sampler::default()
+        };
+        let nearest = sampler(Filter::Nearest);
+        let bilinear = sampler(Filter::Bilinear);
+
+        // Bilinear filtering also reads the neighbors half a texel away
+        for &(outside, far_outside) in &[(-0.01, -0.13), (1.01, 1.13)] {
+            for &uv in &[Vec2::new(outside, 0.5), Vec2::new(0.5, outside)] {
+                assert_eq!(nearest.sample(&image, uv), border_color, "{}", uv);
+            }
+            for &uv in &[Vec2::new(far_outside, 0.5), Vec2::new(0.5, far_outside)] {
+                assert_eq!(bilinear.sample(&image, uv), border_color, "{}", uv);
+            }
+        }
+        assert_eq!(nearest.sample(&image, Vec2::splat(-0.01)), border_color);
+        assert_eq!(bilinear.sample(&image, Vec2::splat(1.13)), border_color);
+
+        // On the edge, bilinear filtering blends the border with the edge
+        let half = border_color.lerp(Vec4::new(0.5, 0.5, 0.5, 1.0), 0.5);
+        for &uv in &[
+            Vec2::new(0.0, 0.5),
+            Vec2::new(1.0, 0.5),
+            Vec2::new(0.5, 0.0),
+            Vec2::new(0.5, 1.0),
+        ] {
+            assert_eq!(bilinear.sample(&image, uv), half, "{}", uv);
+        }
+        assert_eq!(nearest.sample(&image, Vec2::splat(0.5)), Vec4::ONE);
+    }
+
+    #[test]
+    fn cube_map_axes_sample_face_centers() {
+        // Faces only differ in their center texels
+        let center = |i: u8| [40 * i, 255 - 40 * i, 0, 255];
+        let faces = [0, 1, 2, 3, 4, 5].map(|i| {
+            let mut face = Image::from_pixel_rgba(3, 3, [0, 0, 0, 255]);
+            face.set_pixel_rgba(1, 1, center(i));
+            face
+        });
+
+        let mut cross = Image::from_pixel_rgba(12, 9, [0, 0, 0, 255]);
+        for (&(col, row), face) in [(2, 1), (0, 1), (1, 0), (1, 2), (1, 1), (3, 1)]
+            .iter()
+            .zip(&faces)
+        {
+            cross.copy_from(face, col * 3, row * 3);
+        }
+
+        let axes = [Vec3::X, -Vec3::X, Vec3::Y, -Vec3::Y, Vec3::Z, -Vec3::Z];
+        let cube_maps = [
+            CubeMap::from_faces(faces).unwrap(),
+            CubeMap::from_cross(&cross).unwrap(),
+        ];
+        for cube_map in &cube_maps {
+            for (i, (&axis, &face)) in axes.iter().zip(&CubeFace::ALL).enumerate() {
+                assert_eq!(CubeFace::from_direction(axis), (face, Vec2::splat(0.5)));
+                let expected = rgba_to_vec(center(i as u8));
+                assert_eq!(cube_map.sample(axis * 2.0), expected, "{:?}", face);
+            }
+        }
+    }
+
+    #[test]
+    fn fill_rect_clips_to_bounds() {
+        let cases = [
+            // Partially out of bounds to the right, below, and both
+            (3, 1, 4, 2),
+            (1, 2, 2, 5),
+            (2, 1, u32::MAX, u32::MAX),
+            // Covering the image
+            (0, 0, 10, 10),
+            // Entirely out of bounds
+            (5, 0, 1, 1),
+            (0, 3, 2, 2),
+            (u32::MAX, u32::MAX, 2, 2),
+        ];
+        for &(x, y, width, height) in &cases {
+            let mut image = Image::from_pixel_rgba(5, 3, [0, 0, 0, 255]);
+            image.fill_rect_rgba(x, y, width, height, [255; 4]);
+
+            let mut depth = Image::from_pixel_depth(5, 3, 1.0);
+            depth.fill_rect_depth(x, y, width, height, 0.5);
+
+            for (px, py, pixel) in image.enumerate_pixels_rgba() {
+                let inside = u64::from(px) >= u64::from(x)
+                    && u64::from(px) < u64::from(x) + u64::from(width)
+                    && u64::from(py) >= u64::from(y)
+                    && u64::from(py) < u64::from(y) + u64::from(height);
+                let (expected, expected_depth) = if inside {
+                    ([255; 4], 0.5)
+                } else {
+                    ([0, 0, 0, 255], 1.0)
+                };
+                let rect = (x, y, width, height);
+                assert_eq!(pixel, expected, "pixel ({}, {}) of {:?}", px, py, rect);
+                assert_eq!(depth.pixel_depth(px, py), expected_depth, "{:?}", rect);
+            }
+        }
+    }
+
+    #[test]
+    fn bgra_and_argb_match_reference() {
+        let mut seed = 0x1234_5678_u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 24) as u8
+        };
+        let patterns: [&dyn Fn(u32, u32) -> [u8; 4]; 3] = [
+            &|x, y| [x as u8, y as u8, (x * y) as u8, 255 - x as u8],
+            &|x, y| {
+                if (x + y) % 2 == 0 {
+                    [1, 2, 3, 4]
+                } else {
+                    [0xfe, 0, 0x80, 0xff]
+                }
+            },
+            &|_, _| [0xff; 4],
+        ];
+        let mut images: Vec<Image> = patterns
+            .iter()
+            .map(|pattern| {
+                let mut image = Image::new(7, 5);
+                for (x, y, pixel) in image.enumerate_pixels_rgba_mut() {
+                    *pixel = pattern(x, y);
+                }
+                image
+            })
+            .collect();
+        let mut noise = Image::new(7, 5);
+        for (_, _, pixel) in noise.enumerate_pixels_rgba_mut() {
+            *pixel = [random(), random(), random(), random()];
+        }
+        images.push(noise);
+
+        // Enough for every image, so the allocation is reused
+        let mut bgra = Vec::with_capacity(64);
+        let capacity = bgra.capacity();
+        let mut argb = Vec::new();
+        for image in &images {
+            let reference_bgra: Vec<u32> = image
+                .enumerate_pixels_rgba()
+                .map(|(_, _, [r, g, b, a])| u32::from_le_bytes([b, g, r, a]))
+                .collect();
+            let reference_argb: Vec<u32> = image
+                .enumerate_pixels_rgba()
+                .map(|(_, _, [r, g, b, a])| u32::from_le_bytes([a, r, g, b]))
+                .collect();
+
+            image.to_bgra_u32(&mut bgra);
+            assert_eq!(bgra, reference_bgra);
+            assert_eq!(bgra.capacity(), capacity);
+
+            let mut copied = vec![0; 7 * 5];
+            image.copy_to_bgra_u32(&mut copied);
+            assert_eq!(copied, reference_bgra);
+
+            image.to_argb_u32(&mut argb);
+            assert_eq!(argb, reference_argb);
+        }
+    }
+
+    #[test]
+    fn clear_color_matches_written_colors() {
+        for &srgb in &[false, true] {
+            for &color in &[
+                Vec4::new(0.5, 0.2, 1.0, 0.5),
+                Vec4::new(-0.5, 1.5, 0.001, 1.0),
+            ] {
+                let encoding = ColorEncoding {
+                    srgb,
+                    ..ColorEncoding::default()
+                };
+                let mut cleared = Image::new(2, 2);
+                cleared.clear_color(color, srgb);
+                let mut written = Image::new(2, 2);
+                for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+                    written.write_color(x, y, color, &encoding);
+                }
+                assert_eq!(cleared, written, "{} srgb {}", color, srgb);
+            }
+        }
     }
 }
@@ -0,0 +1,189 @@
+use crate::color::hsv;
+use crate::convert::vec_to_rgba;
+use crate::image::Image;
+
+/// Differences between two RGBA images of equal dimensions, see
+/// `Image::diff`. Channel differences are absolute, in 8-bit steps.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct DiffStats {
+    /// Largest difference of any channel of any pixel.
+    pub max_difference: u8,
+    /// Pixels with at least one differing channel.
+    pub differing_pixels: u64,
+    /// Mean difference over all channels of all pixels.
+    pub mean_error: f64,
+    /// First differing pixel, row by row.
+    pub first_difference: Option<(u32, u32)>,
+}
+
+impl Image {
+    /// Compares the pixels of the image and OTHER as RGBA. Returns None if
+    /// their dimensions differ.
+    pub fn diff(&self, other: &Image) -> Option<DiffStats> {
+        if self.dimensions() != other.dimensions() {
+            return None;
+        }
+
+        let mut stats = DiffStats {
+            max_difference: 0,
+            differing_pixels: 0,
+            mean_error: 0.0,
+            first_difference: None,
+        };
+        let mut sum = 0u64;
+
+        for (i, (&a, &b)) in self.buffer.iter().zip(&other.buffer).enumerate() {
+            let difference = channel_differences(a, b);
+            let max = difference.iter().copied().max().unwrap_or(0);
+            if max > 0 {
+                stats.max_difference = stats.max_difference.max(max);
+                stats.differing_pixels += 1;
+                if stats.first_difference.is_none() {
+                    stats.first_difference =
+                        Some(((i % self.width) as u32, (i / self.width) as u32));
+                }
+            }
+            sum += difference.iter().map(|&d| u64::from(d)).sum::<u64>();
+        }
+
+        if !self.buffer.is_empty() {
+            stats.mean_error = sum as f64 / (self.buffer.len() * 4) as f64;
+        }
+
+        Some(stats)
+    }
+
+    /// Returns a heatmap of the differences between the image and OTHER, for
+    /// viewing. Equal pixels are black, differing pixels go from blue to red
+    /// with their largest channel difference, relative to the largest one in
+    /// the image.
+    pub fn diff_image(&self, other: &Image) -> Image {
+        assert!(
+            self.dimensions() == other.dimensions(),
+            "images must have equal dims"
+        );
+
+        let max_differences: Vec<u8> = self
+            .buffer
+            .iter()
+            .zip(&other.buffer)
+            .map(|(&a, &b)| channel_differences(a, b).iter().copied().max().unwrap_or(0))
+            .collect();
+        let scale = f32::from(max_differences.iter().copied().max().unwrap_or(0).max(1));
+
+        let buffer = max_differences
+            .iter()
+            .map(|&difference| {
+                let rgba = if difference == 0 {
+                    [0, 0, 0, 255]
+                } else {
+                    let t = f32::from(difference) / scale;
+                    vec_to_rgba(hsv(2.0 / 3.0 * (1.0 - t), 1.0, 1.0))
+                };
+                u32::from_le_bytes(rgba)
+            })
+            .collect();
+
+        Image::from_buffer(self.width, self.height, buffer)
+    }
+}
+
+/// Panics unless ACTUAL and EXPECTED have equal dimensions and no channel of
+/// any pixel differs by more than TOLERANCE, e.g. in golden image tests. The
+/// message reports the first offending pixel and the `DiffStats`.
+#[track_caller]
+pub fn assert_images_match(actual: &Image, expected: &Image, tolerance: u8) {
+    let stats = match actual.diff(expected) {
+        Some(stats) => stats,
+        None => panic!(
+            "image dimensions differ: actual {:?}, expected {:?}",
+            actual.dimensions(),
+            expected.dimensions(),
+        ),
+    };
+    if stats.max_difference <= tolerance {
+        return;
+    }
+
+    let width = actual.width;
+    let (i, _) = actual
+        .buffer
+        .iter()
+        .zip(&expected.buffer)
+        .enumerate()
+        .find(|(_, (&a, &b))| channel_differences(a, b).iter().any(|&d| d > tolerance))
+        .expect("a pixel exceeds the tolerance");
+    let (x, y) = ((i % width) as u32, (i / width) as u32);
+
+    panic!(
+        "images differ by more than {} at pixel ({}, {}): actual {:?}, expected {:?}\n{:?}",
+        tolerance,
+        x,
+        y,
+        actual.pixel_rgba(x, y),
+        expected.pixel_rgba(x, y),
+        stats,
+    );
+}
+
+fn channel_differences(a: u32, b: u32) -> [u8; 4] {
+    let (a, b) = (a.to_le_bytes(), b.to_le_bytes());
+    [0, 1, 2, 3].map(|i| a[i].abs_diff(b[i]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn images() -> (Image, Image) {
+        let a = Image::from_pixel_rgba(4, 3, [10, 20, 30, 255]);
+        let mut b = a.clone();
+        b.set_pixel_rgba(2, 1, [10, 24, 30, 255]);
+        b.set_pixel_rgba(3, 2, [11, 20, 28, 255]);
+        (a, b)
+    }
+
+    #[test]
+    fn diff_stats() {
+        let (a, b) = images();
+        assert_eq!(
+            a.diff(&b),
+            Some(DiffStats {
+                max_difference: 4,
+                differing_pixels: 2,
+                mean_error: 7.0 / 48.0,
+                first_difference: Some((2, 1)),
+            })
+        );
+        assert_eq!(a.diff(&a).map(|stats| stats.differing_pixels), Some(0));
+        assert_eq!(a.diff(&Image::new(3, 4)), None);
+
+        let heatmap = a.diff_image(&b);
+        assert_eq!(heatmap.pixel_rgba(0, 0), [0, 0, 0, 255]);
+        assert_eq!(heatmap.pixel_rgba(2, 1), [255, 0, 0, 255]);
+        // Half the largest difference
+        assert_eq!(heatmap.pixel_rgba(3, 2), [0, 255, 0, 255]);
+    }
+
+    #[test]
+    fn images_match_within_tolerance() {
+        let (a, b) = images();
+        assert_images_match(&a, &b, 4);
+    }
+
+    #[test]
+    #[should_panic(
+        expected = "images differ by more than 2 at pixel (2, 1): actual [10, 20, 30, 255], expected [10, 24, 30, 255]"
+    )]
+    fn images_differ() {
+        let (a, b) = images();
+        assert_images_match(&a, &b, 2);
+    }
+
+    #[test]
+    #[should_panic(expected = "image dimensions differ: actual (4, 3), expected (3, 4)")]
+    fn image_dimensions_differ() {
+        let (a, _) = images();
+        assert_images_match(&a, &Image::new(3, 4), 255);
+    }
+}
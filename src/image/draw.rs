@@ -0,0 +1,622 @@
+use crate::image::Image;
+
+/// How the 2D drawing methods of `Image` write colors, e.g.
+/// `Image::draw_line`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DrawMode {
+    /// Overwrite pixels with the color.
+    #[default]
+    Replace,
+    /// Composite the color over pixels by its straight alpha, with the
+    /// Porter-Duff over operator.
+    Blend,
+}
+
+// 2D drawing for overlays, independent of the pipeline. Coordinates are
+// pixels of the image, with row 0 at the top of rendered images, and may lie
+// outside of it. Shapes are clipped to the image and each pixel is written
+// at most once per call, so blending doesn't darken overlaps.
+impl Image {
+    /// Draws a one pixel wide line from X0, Y0 to X1, Y1, both inclusive.
+    pub fn draw_line(
+        &mut self,
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        color: [u8; 4],
+        mode: DrawMode,
+    ) {
+        let (width, height) = (self.width as i64, self.height as i64);
+        let (x0, y0, x1, y1) = (i64::from(x0), i64::from(y0), i64::from(x1), i64::from(y1));
+
+        // Step along the major axis, A, one pixel at a time and round the
+        // position on the minor axis, B, like Bresenham's algorithm, but
+        // computed directly so that the loop starts inside the image
+        let steep = (y1 - y0).abs() > (x1 - x0).abs();
+        let (a0, b0, a1, b1, a_len, b_len) = if steep {
+            (y0, x0, y1, x1, height, width)
+        } else {
+            (x0, y0, x1, y1, width, height)
+        };
+        let (a0, b0, a1, b1) = if a0 > a1 {
+            (a1, b1, a0, b0)
+        } else {
+            (a0, b0, a1, b1)
+        };
+
+        let (da, db) = (i128::from(a1 - a0), i128::from(b1 - b0));
+        for a in a0.max(0)..=a1.min(a_len - 1) {
+            let b = if da == 0 {
+                b0
+            } else {
+                let offset = (2 * i128::from(a - a0) * db + da).div_euclid(2 * da);
+                b0 + offset as i64
+            };
+
+            if (0..b_len).contains(&b) {
+                let (x, y) = if steep { (b, a) } else { (a, b) };
+                self.draw_span(x, y, 1, color, mode);
+            }
+        }
+    }
+
+    /// Draws the one pixel wide outline of the WIDTH x HEIGHT rectangle at
+    /// X, Y.
+    pub fn draw_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        color: [u8; 4],
+        mode: DrawMode,
+    ) {
+        if width == 0 || height == 0 {
+            return;
+        }
+
+        let (x, y) = (i64::from(x), i64::from(y));
+        let (width, height) = (i64::from(width), i64::from(height));
+
+        self.fill_rect_clipped(x, y, width, 1, color, mode);
+        if height > 1 {
+            self.fill_rect_clipped(x, y + height - 1, width, 1, color, mode);
+        }
+        if height > 2 {
+            self.fill_rect_clipped(x, y + 1, 1, height - 2, color, mode);
+            if width > 1 {
+                self.fill_rect_clipped(x + width - 1, y + 1, 1, height - 2, color, mode);
+            }
+        }
+    }
+
+    /// Fills the WIDTH x HEIGHT rectangle at X, Y. Unlike `fill_rect`, the
+    /// rectangle may start outside of the image and can blend.
+    pub fn draw_filled_rect(
+        &mut self,
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        color: [u8; 4],
+        mode: DrawMode,
+    ) {
+        self.fill_rect_clipped(
+            i64::from(x),
+            i64::from(y),
+            i64::from(width),
+            i64::from(height),
+            color,
+            mode,
+        );
+    }
+
+    /// Draws the one pixel wide outline of the circle with RADIUS centered
+    /// on pixel X, Y.
+    pub fn draw_circle(&mut self, x: i32, y: i32, radius: u32, color: [u8; 4], mode: DrawMode) {
+        let radius = i64::from(radius);
+
+        // The outline is the disk minus the disk one pixel smaller, drawn as
+        // up to two spans per row
+        self.for_each_disk_row(y, radius, |image, row, outer| {
+            let inner = disk_half_width(radius - 1, row - i64::from(y));
+            let x = i64::from(x);
+            match inner {
+                Some(inner) => {
+                    image.draw_span(x - outer, row, outer - inner, color, mode);
+                    image.draw_span(x + inner + 1, row, outer - inner, color, mode);
+                }
+                None => image.draw_span(x - outer, row, 2 * outer + 1, color, mode),
+            }
+        });
+    }
+
+    /// Fills the circle with RADIUS centered on pixel X, Y.
+    pub fn draw_filled_circle(
+        &mut self,
+        x: i32,
+        y: i32,
+        radius: u32,
+        color: [u8; 4],
+        mode: DrawMode,
+    ) {
+        self.for_each_disk_row(y, i64::from(radius), |image, row, outer| {
+            image.draw_span(i64::from(x) - outer, row, 2 * outer + 1, color, mode);
+        });
+    }
+
+    /// Composites all of SRC over the image with its top left corner at
+    /// DST_X, DST_Y, by the straight alpha of SRC (Porter-Duff over). Parts
+    /// falling outside of the image are clipped.
+    pub fn blit_over(&mut self, src: &Image, dst_x: i32, dst_y: i32) {
+        self.blit_with(src, dst_x, dst_y, over);
+    }
+
+    /// Like `blit_over`, but for SRC with premultiplied alpha, see
+    /// `premultiply_alpha`. The image should be premultiplied too, or
+    /// opaque.
+    pub fn blit_over_premultiplied(&mut self, src: &Image, dst_x: i32, dst_y: i32) {
+        self.blit_with(src, dst_x, dst_y, over_premultiplied);
+    }
+
+    /// Combines the pixels of SRC with the image at DST_X, DST_Y with F,
+    /// clipped to the image.
+    fn blit_with<F>(&mut self, src: &Image, dst_x: i32, dst_y: i32, f: F)
+    where
+        F: Fn([u8; 4], [u8; 4]) -> [u8; 4],
+    {
+        let (dst_x, dst_y) = (i64::from(dst_x), i64::from(dst_y));
+        let x_start = dst_x.max(0);
+        let x_end = (dst_x + src.width as i64).min(self.width as i64);
+        if x_start >= x_end {
+            return;
+        }
+
+        let (src_x, len) = ((x_start - dst_x) as usize, (x_end - x_start) as usize);
+        let rows = dst_y.max(0)..(dst_y + src.height as i64).min(self.height as i64);
+        for row in rows {
+            let src_start = (row - dst_y) as usize * src.width + src_x;
+            let dst_start = row as usize * self.width + x_start as usize;
+            let src_row = &src.buffer[src_start..src_start + len];
+            let dst_row = &mut self.buffer[dst_start..dst_start + len];
+            for (dst, src) in dst_row.iter_mut().zip(src_row) {
+                *dst = u32::from_le_bytes(f(src.to_le_bytes(), dst.to_le_bytes()));
+            }
+        }
+    }
+
+    /// Runs F with the rows of the image covered by the disk with RADIUS
+    /// centered on row Y, and the half width of the disk on each.
+    fn for_each_disk_row<F>(&mut self, y: i32, radius: i64, mut f: F)
+    where
+        F: FnMut(&mut Image, i64, i64),
+    {
+        let y = i64::from(y);
+        let rows = (y - radius).max(0)..=(y + radius).min(self.height as i64 - 1);
+        for row in rows {
+            if let Some(outer) = disk_half_width(radius, row - y) {
+                f(self, row, outer);
+            }
+        }
+    }
+
+    fn fill_rect_clipped(
+        &mut self,
+        x: i64,
+        y: i64,
+        width: i64,
+        height: i64,
+        color: [u8; 4],
+        mode: DrawMode,
+    ) {
+        let rows = y.max(0)..(y + height).min(self.height as i64);
+        for row in rows {
+            self.draw_span(x, row, width, color, mode);
+        }
+    }
+
+    /// Writes COLOR to LEN pixels of row Y from X, clipped horizontally.
+    /// Y must be inside of the image.
+    fn draw_span(&mut self, x: i64, y: i64, len: i64, color: [u8; 4], mode: DrawMode) {
+        let start = x.max(0);
+        let end = (x + len).min(self.width as i64);
+        if start >= end {
+            return;
+        }
+
+        let row_start = y as usize * self.width;
+        let span = &mut self.buffer[row_start + start as usize..row_start + end as usize];
+        match mode {
+            DrawMode::Replace => span.fill(u32::from_le_bytes(color)),
+            DrawMode::Blend => {
+                for pixel in span {
+                    *pixel = u32::from_le_bytes(over(color, pixel.to_le_bytes()));
+                }
+            }
+        }
+    }
+}
+
+/// Half width of the row DY pixels from the center of the disk with RADIUS,
+/// or None if the row misses it. Pixels within `radius + 0.5` of the center
+/// are inside, which keeps small circles round.
+fn disk_half_width(radius: i64, dy: i64) -> Option<i64> {
+    // dx^2 + dy^2 <= (radius + 0.5)^2, in integers
+    let limit = radius * radius + radius - dy * dy;
+    if radius < 0 || limit < 0 {
+        return None;
+    }
+
+    // Correct the float square root, which can be off by one for large
+    // values
+    let mut half_width = (limit as f64).sqrt() as i64;
+    while half_width * half_width > limit {
+        half_width -= 1;
+    }
+    while (half_width + 1) * (half_width + 1) <= limit {
+        half_width += 1;
+    }
+
+    Some(half_width)
+}
+
+/// Composites straight alpha SRC over DST (Porter-Duff over) in 8 bits,
+/// rounding to nearest.
+pub(crate) fn over(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let src_alpha = u32::from(src[3]);
+    match src_alpha {
+        255 => return src,
+        0 => return dst,
+        _ => {}
+    }
+
+    // Alpha of DST showing through SRC
+    let dst_alpha = div_255(u32::from(dst[3]) * (255 - src_alpha));
+    let alpha = src_alpha + dst_alpha;
+    if alpha == 0 {
+        return [0; 4];
+    }
+
+    let channel = |i: usize| {
+        let sum = u32::from(src[i]) * src_alpha + u32::from(dst[i]) * dst_alpha;
+        ((sum + alpha / 2) / alpha) as u8
+    };
+
+    [channel(0), channel(1), channel(2), alpha as u8]
+}
+
+/// Composites premultiplied alpha SRC over DST in 8 bits, rounding to
+/// nearest.
+fn over_premultiplied(src: [u8; 4], dst: [u8; 4]) -> [u8; 4] {
+    let inverse_alpha = 255 - u32::from(src[3]);
+    let channel = |i: usize| {
+        let sum = u32::from(src[i]) + div_255(u32::from(dst[i]) * inverse_alpha);
+        sum.min(255) as u8
+    };
+
+    [channel(0), channel(1), channel(2), channel(3)]
+}
+
+/// Divides N, at most 255 * 255, by 255, rounding to nearest.
+pub(crate) fn div_255(n: u32) -> u32 {
+    let n = n + 128;
+    (n + (n >> 8)) >> 8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(width: u32, height: u32, alpha: u8) -> Image {
+        let mut image = Image::new(width, height);
+        for (x, y, pixel) in image.enumerate_pixels_rgba_mut() {
+            *pixel = [(x * 50) as u8, (y * 60) as u8, (x * y * 7) as u8, alpha];
+        }
+        image
+    }
+
+    /// Composites straight alpha SRC over DST in floats.
+    fn over_reference(src: [u8; 4], dst: [u8; 4]) -> [f32; 4] {
+        let [src, dst] = [src, dst].map(|pixel| pixel.map(|c| f32::from(c) / 255.0));
+        let alpha = src[3] + dst[3] * (1.0 - src[3]);
+        let channel =
+            |i: usize| (src[i] * src[3] + dst[i] * dst[3] * (1.0 - src[3])) / alpha * 255.0;
+        [channel(0), channel(1), channel(2), alpha * 255.0]
+    }
+
+    #[test]
+    fn blit_over_opaque_copies() {
+        let mut image = pattern(5, 4, 255);
+        let src = pattern(3, 2, 255).rotate180();
+        let expected = {
+            let mut image = image.clone();
+            image.copy_from(&src, 1, 1);
+            image
+        };
+        image.blit_over(&src, 1, 1);
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn blit_over_transparent_is_noop() {
+        let mut image = pattern(5, 4, 200);
+        let expected = image.clone();
+        image.blit_over(&pattern(3, 3, 0), 2, 1);
+        image.blit_over_premultiplied(&Image::new(3, 3), 2, 1);
+        assert_eq!(image, expected);
+    }
+
+    #[test]
+    fn blit_over_half_alpha_matches_analytic() {
+        let dsts = [
+            [0, 0, 255, 255],
+            [255, 255, 255, 255],
+            [10, 200, 30, 128],
+            [90, 90, 90, 0],
+        ];
+        let srcs = [
+            [255, 0, 0, 128],
+            [0, 255, 0, 127],
+            [40, 80, 160, 128],
+            [255, 255, 255, 64],
+        ];
+        for &dst in &dsts {
+            for &src in &srcs {
+                let mut image = Image::from_pixel_rgba(1, 1, dst);
+                image.blit_over(&Image::from_pixel_rgba(1, 1, src), 0, 0);
+
+                let actual = image.pixel_rgba(0, 0);
+                let expected = over_reference(src, dst);
+                for (&actual, &expected) in actual.iter().zip(&expected) {
+                    assert!(
+                        (f32::from(actual) - expected).abs() <= 1.0,
+                        "{:?} over {:?} is {:?}, expected {:?}",
+                        src,
+                        dst,
+                        image.pixel_rgba(0, 0),
+                        expected,
+                    );
+                }
+            }
+        }
+
+        // The premultiplied red of 50% red
+        let mut image = Image::from_pixel_rgba(1, 1, [0, 0, 255, 255]);
+        image.blit_over_premultiplied(&Image::from_pixel_rgba(1, 1, [128, 0, 0, 128]), 0, 0);
+        assert_eq!(image.pixel_rgba(0, 0), [128, 0, 127, 255]);
+    }
+
+    #[test]
+    fn blit_over_clips() {
+        let src = pattern(3, 3, 255);
+        for &(x, y) in &[(-1, -2), (3, 2), (-3, 0), (0, 4), (i32::MIN, i32::MAX)] {
+            let mut image = pattern(4, 4, 255).rotate90();
+            let mut expected = image.clone();
+            for (sx, sy, pixel) in src.enumerate_pixels_rgba() {
+                let (dx, dy) = (i64::from(x) + i64::from(sx), i64::from(y) + i64::from(sy));
+                if (0..4).contains(&dx) && (0..4).contains(&dy) {
+                    expected.set_pixel_rgba(dx as u32, dy as u32, pixel);
+                }
+            }
+
+            image.blit_over(&src, x, y);
+            assert_eq!(image, expected, "at ({}, {})", x, y);
+        }
+    }
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+
+    /// Draws a shape into the image at X, Y.
+    type Draw = fn(&mut Image, i32, i32);
+
+    /// Renders IMAGE as text, with `#` for COLOR and `.` for transparent
+    /// black.
+    fn ascii(image: &Image, color: [u8; 4]) -> String {
+        let mut text = String::new();
+        for y in 0..image.height() {
+            for x in 0..image.width() {
+                text.push(match image.pixel_rgba(x, y) {
+                    [0, 0, 0, 0] => '.',
+                    rgba if rgba == color => '#',
+                    rgba => panic!("unexpected color {:?}", rgba),
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    /// Draws with DRAW at X, Y on a 6x5 image, and checks that it matches
+    /// the same drawing on an image large enough not to clip it.
+    fn assert_clips(x: i32, y: i32, draw: Draw) -> Image {
+        const PAD: u32 = 16;
+        let mut canvas = Image::new(6 + 2 * PAD, 5 + 2 * PAD);
+        draw(&mut canvas, x + PAD as i32, y + PAD as i32);
+
+        let mut image = Image::new(6, 5);
+        draw(&mut image, x, y);
+        assert_eq!(image, canvas.crop(PAD, PAD, 6, 5), "at ({}, {})", x, y);
+        image
+    }
+
+    #[test]
+    fn line_golden() {
+        let mut image = Image::new(8, 4);
+        image.draw_line(0, 0, 7, 3, RED, DrawMode::Replace);
+        let expected = "\
+##......
+..##....
+....##..
+......##
+";
+        assert_eq!(ascii(&image, RED), expected);
+
+        let mut reversed = Image::new(8, 4);
+        reversed.draw_line(7, 3, 0, 0, RED, DrawMode::Replace);
+        assert_eq!(reversed, image);
+    }
+
+    #[test]
+    fn steep_lines_have_one_pixel_per_row() {
+        let mut image = Image::new(4, 8);
+        image.draw_line(1, 0, 2, 7, RED, DrawMode::Replace);
+        for y in 0..8 {
+            let row = (0..4).filter(|&x| image.pixel_rgba(x, y) == RED).count();
+            assert_eq!(row, 1, "row {}", y);
+        }
+        assert_eq!(image.pixel_rgba(1, 0), RED);
+        assert_eq!(image.pixel_rgba(2, 7), RED);
+
+        let mut reversed = Image::new(4, 8);
+        reversed.draw_line(2, 7, 1, 0, RED, DrawMode::Replace);
+        assert_eq!(reversed, image);
+    }
+
+    #[test]
+    fn single_point_lines() {
+        let mut image = Image::new(4, 4);
+        image.draw_line(2, 3, 2, 3, RED, DrawMode::Replace);
+        assert_eq!(ascii(&image, RED), "....\n....\n....\n..#.\n");
+
+        let mut image = Image::new(4, 4);
+        for &(x, y) in &[(-1, 0), (4, 0), (0, -1), (0, 4), (i32::MIN, i32::MAX)] {
+            image.draw_line(x, y, x, y, RED, DrawMode::Replace);
+        }
+        assert_eq!(image, Image::new(4, 4));
+    }
+
+    #[test]
+    fn lines_with_extreme_endpoints() {
+        let (min, max) = (i32::MIN, i32::MAX);
+
+        // The slope is exactly 1, so the line passes through the diagonal
+        let mut image = Image::new(4, 4);
+        image.draw_line(min, min, max, max, RED, DrawMode::Replace);
+        assert_eq!(ascii(&image, RED), "#...\n.#..\n..#.\n...#\n");
+
+        let mut image = Image::new(4, 4);
+        image.draw_line(max, 2, min, 2, RED, DrawMode::Replace);
+        image.draw_line(1, min, 1, max, RED, DrawMode::Replace);
+        assert_eq!(ascii(&image, RED), ".#..\n.#..\n####\n.#..\n");
+
+        // Entirely outside, along and across the image
+        let mut image = Image::new(4, 4);
+        image.draw_line(min, -1, max, -1, RED, DrawMode::Replace);
+        image.draw_line(min, max, max, min, RED, DrawMode::Replace);
+        image.draw_line(max, max, max, min, RED, DrawMode::Blend);
+        image.draw_line(min, 4, max, 4, RED, DrawMode::Blend);
+        assert_eq!(image, Image::new(4, 4));
+
+        // Nearly horizontal across all of i32, crossing the image
+        let mut image = Image::new(4, 4);
+        image.draw_line(min, 0, max, 3, RED, DrawMode::Replace);
+        let written = image
+            .enumerate_pixels_rgba()
+            .filter(|&(_, _, rgba)| rgba == RED)
+            .count();
+        assert_eq!(written, 4);
+    }
+
+    #[test]
+    fn rects_and_circles_clip() {
+        let draws: [(&str, Draw); 4] = [
+            ("rect", |image, x, y| {
+                image.draw_rect(x, y, 4, 3, RED, DrawMode::Replace)
+            }),
+            ("filled rect", |image, x, y| {
+                image.draw_filled_rect(x, y, 4, 3, RED, DrawMode::Replace)
+            }),
+            ("circle", |image, x, y| {
+                image.draw_circle(x, y, 2, RED, DrawMode::Replace)
+            }),
+            ("filled circle", |image, x, y| {
+                image.draw_filled_circle(x, y, 2, RED, DrawMode::Replace)
+            }),
+        ];
+
+        // Partly off the left, right, top and bottom edge, and the corners
+        let partly = [(-2, 1), (4, 1), (1, -1), (1, 3), (-2, -1), (4, 3)];
+        // Entirely off each edge
+        let outside = [(-7, 1), (9, 1), (1, -6), (1, 8)];
+        for &(name, draw) in &draws {
+            for &(x, y) in &partly {
+                let image = assert_clips(x, y, draw);
+                assert_ne!(image, Image::new(6, 5), "{} at ({}, {})", name, x, y);
+            }
+            for &(x, y) in &outside {
+                let image = assert_clips(x, y, draw);
+                assert_eq!(image, Image::new(6, 5), "{} at ({}, {})", name, x, y);
+            }
+        }
+
+        let mut image = Image::new(6, 5);
+        image.draw_rect(-2, -1, 4, 3, RED, DrawMode::Replace);
+        image.draw_circle(5, 4, 2, RED, DrawMode::Replace);
+        let expected = "\
+.#....
+##....
+....##
+...#..
+...#..
+";
+        assert_eq!(ascii(&image, RED), expected);
+
+        let mut image = Image::new(6, 5);
+        image.draw_filled_circle(2, 2, 2, RED, DrawMode::Replace);
+        image.draw_filled_rect(
+            i32::MAX,
+            i32::MIN,
+            u32::MAX,
+            u32::MAX,
+            RED,
+            DrawMode::Replace,
+        );
+        let expected = "\
+.###..
+#####.
+#####.
+#####.
+.###..
+";
+        assert_eq!(ascii(&image, RED), expected);
+    }
+
+    #[test]
+    fn blend_writes_each_pixel_once() {
+        const COLOR: [u8; 4] = [255, 0, 0, 128];
+        let black = [0, 0, 0, 255];
+        let once = over(COLOR, black);
+
+        let draws: [fn(&mut Image); 10] = [
+            |image| image.draw_rect(1, 1, 5, 4, COLOR, DrawMode::Blend),
+            |image| image.draw_rect(1, 1, 1, 4, COLOR, DrawMode::Blend),
+            |image| image.draw_rect(1, 1, 5, 1, COLOR, DrawMode::Blend),
+            |image| image.draw_rect(-1, -1, 2, 2, COLOR, DrawMode::Blend),
+            |image| image.draw_circle(3, 3, 0, COLOR, DrawMode::Blend),
+            |image| image.draw_circle(3, 3, 1, COLOR, DrawMode::Blend),
+            |image| image.draw_circle(3, 3, 3, COLOR, DrawMode::Blend),
+            |image| image.draw_filled_circle(3, 3, 3, COLOR, DrawMode::Blend),
+            |image| image.draw_line(0, 0, 6, 6, COLOR, DrawMode::Blend),
+            |image| image.draw_line(1, 6, 2, 0, COLOR, DrawMode::Blend),
+        ];
+        for (i, draw) in draws.iter().enumerate() {
+            let mut image = Image::from_pixel_rgba(7, 7, black);
+            draw(&mut image);
+
+            let mut blended = 0;
+            for (x, y, rgba) in image.enumerate_pixels_rgba() {
+                assert!(
+                    rgba == black || rgba == once,
+                    "draw {} pixel ({}, {}) is {:?}",
+                    i,
+                    x,
+                    y,
+                    rgba
+                );
+                blended += u32::from(rgba == once);
+            }
+            assert!(blended > 0, "draw {}", i);
+        }
+    }
+}
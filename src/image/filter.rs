@@ -0,0 +1,214 @@
+use glam::Vec4;
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::image::{ColorEncoding, ColorPixel, Image, Wrap};
+
+/// Returns the weights of a normalized Gaussian kernel with standard
+/// deviation SIGMA, from -RADIUS to RADIUS pixels.
+pub fn gaussian_kernel(sigma: f32, radius: u32) -> Vec<f32> {
+    let radius = radius as i32;
+    let mut kernel: Vec<f32> = (-radius..=radius)
+        .map(|i| (-(i * i) as f32 / (2.0 * sigma * sigma)).exp())
+        .collect();
+
+    let sum: f32 = kernel.iter().sum();
+    for weight in &mut kernel {
+        *weight /= sum;
+    }
+
+    kernel
+}
+
+/// Blurs IMAGE with a Gaussian kernel with standard deviation SIGMA,
+/// truncated to RADIUS pixels, e.g. `(3.0 * sigma).ceil()`. Pixels outside
+/// of the image are mapped back by WRAP, `Wrap::ClampToBorder` reads them
+/// as transparent black.
+///
+/// Colors are blurred as stored, i.e. sRGB encoded 8-bit colors are not
+/// linearized first.
+pub fn gaussian_blur<P: ColorPixel>(
+    image: &Image<P>,
+    sigma: f32,
+    radius: u32,
+    wrap: Wrap,
+) -> Image<P> {
+    let kernel = gaussian_kernel(sigma, radius);
+    convolve_separable(image, &kernel, &kernel, wrap)
+}
+
+/// Like `gaussian_blur`, but processes the rows on the rayon thread pool.
+#[cfg(feature = "rayon")]
+pub fn gaussian_blur_parallel<P: ColorPixel>(
+    image: &Image<P>,
+    sigma: f32,
+    radius: u32,
+    wrap: Wrap,
+) -> Image<P> {
+    let kernel = gaussian_kernel(sigma, radius);
+    convolve_separable_parallel(image, &kernel, &kernel, wrap)
+}
+
+/// Convolves IMAGE with the outer product of KERNEL_X and KERNEL_Y, as a
+/// horizontal and a vertical pass. The kernels have odd lengths and are
+/// centered on the pixel. See `gaussian_blur` for WRAP.
+pub fn convolve_separable<P: ColorPixel>(
+    image: &Image<P>,
+    kernel_x: &[f32],
+    kernel_y: &[f32],
+    wrap: Wrap,
+) -> Image<P> {
+    separable(image, kernel_x, kernel_y, wrap, false)
+}
+
+/// Like `convolve_separable`, but processes the rows on the rayon thread
+/// pool.
+#[cfg(feature = "rayon")]
+pub fn convolve_separable_parallel<P: ColorPixel>(
+    image: &Image<P>,
+    kernel_x: &[f32],
+    kernel_y: &[f32],
+    wrap: Wrap,
+) -> Image<P> {
+    separable(image, kernel_x, kernel_y, wrap, true)
+}
+
+/// Convolves IMAGE with a square KERNEL of odd SIZE, stored row by row and
+/// centered on the pixel. See `gaussian_blur` for WRAP.
+///
+/// Prefer `convolve_separable` for kernels that are separable, as this
+/// reads SIZE * SIZE pixels for every pixel.
+pub fn convolve<P: ColorPixel>(
+    image: &Image<P>,
+    kernel: &[f32],
+    size: u32,
+    wrap: Wrap,
+) -> Image<P> {
+    assert!(size % 2 == 1, "kernel size must be odd");
+    assert!(
+        kernel.len() == (size * size) as usize,
+        "kernel must have size * size weights"
+    );
+
+    let (width, height) = (image.width as i64, image.height as i64);
+    let radius = i64::from(size / 2);
+    let src = decode(image);
+
+    let mut dst = vec![Vec4::ZERO; src.len()];
+    for_each_row(&mut dst, image.width, false, |y, dst_row| {
+        for (x, dst) in dst_row.iter_mut().enumerate() {
+            let mut sum = Vec4::ZERO;
+            for (ky, kernel_row) in kernel.chunks_exact(size as usize).enumerate() {
+                let sy = match wrap.wrap(y as i64 + ky as i64 - radius, height) {
+                    Some(sy) => sy,
+                    None => continue,
+                };
+                for (kx, &weight) in kernel_row.iter().enumerate() {
+                    if let Some(sx) = wrap.wrap(x as i64 + kx as i64 - radius, width) {
+                        sum += src[(sy * width + sx) as usize] * weight;
+                    }
+                }
+            }
+            *dst = sum;
+        }
+    });
+
+    encode(&dst, image)
+}
+
+fn separable<P: ColorPixel>(
+    image: &Image<P>,
+    kernel_x: &[f32],
+    kernel_y: &[f32],
+    wrap: Wrap,
+    parallel: bool,
+) -> Image<P> {
+    assert!(
+        kernel_x.len() % 2 == 1 && kernel_y.len() % 2 == 1,
+        "kernel size must be odd"
+    );
+
+    let row_len = image.width;
+    let (width, height) = (image.width as i64, image.height as i64);
+    let src = decode(image);
+
+    // Rows are blurred horizontally in place of the pixel, then whole rows
+    // are accumulated vertically, so both passes read memory in order
+    let radius_x = (kernel_x.len() / 2) as i64;
+    let mut horizontal = vec![Vec4::ZERO; src.len()];
+    for_each_row(&mut horizontal, row_len, parallel, |y, dst_row| {
+        let src_row = &src[y * row_len..(y + 1) * row_len];
+        for (x, dst) in dst_row.iter_mut().enumerate() {
+            let mut sum = Vec4::ZERO;
+            for (k, &weight) in kernel_x.iter().enumerate() {
+                if let Some(sx) = wrap.wrap(x as i64 + k as i64 - radius_x, width) {
+                    sum += src_row[sx as usize] * weight;
+                }
+            }
+            *dst = sum;
+        }
+    });
+
+    let radius_y = (kernel_y.len() / 2) as i64;
+    let mut dst = vec![Vec4::ZERO; src.len()];
+    for_each_row(&mut dst, row_len, parallel, |y, dst_row| {
+        for (k, &weight) in kernel_y.iter().enumerate() {
+            if let Some(sy) = wrap.wrap(y as i64 + k as i64 - radius_y, height) {
+                let start = sy as usize * row_len;
+                let src_row = &horizontal[start..start + row_len];
+                for (dst, src) in dst_row.iter_mut().zip(src_row) {
+                    *dst += *src * weight;
+                }
+            }
+        }
+    });
+
+    encode(&dst, image)
+}
+
+/// Runs F with the index and pixels of each row of BUFFER, an image WIDTH
+/// pixels wide, on the rayon thread pool if PARALLEL is set.
+fn for_each_row<F>(buffer: &mut [Vec4], width: usize, parallel: bool, f: F)
+where
+    F: Fn(usize, &mut [Vec4]) + Sync,
+{
+    if width == 0 {
+        return;
+    }
+
+    #[cfg(feature = "rayon")]
+    if parallel {
+        buffer
+            .par_chunks_exact_mut(width)
+            .enumerate()
+            .for_each(|(y, row)| f(y, row));
+        return;
+    }
+
+    let _ = parallel;
+    for (y, row) in buffer.chunks_exact_mut(width).enumerate() {
+        f(y, row);
+    }
+}
+
+fn decode<P: ColorPixel>(image: &Image<P>) -> Vec<Vec4> {
+    image
+        .buffer
+        .iter()
+        .map(|&storage| P::decode(storage).to_color())
+        .collect()
+}
+
+fn encode<P: ColorPixel>(colors: &[Vec4], like: &Image<P>) -> Image<P> {
+    let encoding = ColorEncoding::default();
+    let buffer = colors
+        .iter()
+        .enumerate()
+        .map(|(i, &color)| {
+            let (x, y) = (i % like.width, i / like.width);
+            P::from_color(color, x as u32, y as u32, &encoding).encode()
+        })
+        .collect();
+
+    Image::from_buffer(like.width, like.height, buffer)
+}
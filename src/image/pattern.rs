@@ -0,0 +1,182 @@
+use crate::image::Image;
+
+/// Number of cells along each axis of `Image::uv_grid`.
+const UV_GRID_CELLS: u32 = 8;
+
+// Test patterns, e.g. as reference content for tests or placeholder
+// textures. Pixel values are exact and documented, so that tests can rely on
+// them.
+impl Image {
+    /// Creates a checkerboard of square CELL x CELL pixel cells, starting
+    /// with COLOR_A in the cell at pixel 0, 0. Pixel X, Y is COLOR_A if
+    /// `x / cell + y / cell` is even, otherwise COLOR_B.
+    pub fn checkerboard(
+        width: u32,
+        height: u32,
+        cell: u32,
+        color_a: [u8; 4],
+        color_b: [u8; 4],
+    ) -> Image {
+        assert!(cell > 0, "cell size must be positive");
+
+        Image::from_fn(width, height, |x, y| {
+            if (x / cell + y / cell).is_multiple_of(2) {
+                color_a
+            } else {
+                color_b
+            }
+        })
+    }
+
+    /// Creates a gradient from FROM in column 0 to TO in the last column,
+    /// the same in every row. Each channel of pixel X is
+    /// `from + (to - from) * x / (width - 1)`, with the offset from FROM
+    /// rounded to nearest, halves away from zero. Images one pixel wide are
+    /// FROM.
+    pub fn gradient_horizontal(width: u32, height: u32, from: [u8; 4], to: [u8; 4]) -> Image {
+        let last = i64::from(width.saturating_sub(1).max(1));
+
+        Image::from_fn(width, height, |x, _| {
+            let x = i64::from(x);
+            let mut pixel = [0; 4];
+            for (channel, (&from, &to)) in pixel.iter_mut().zip(from.iter().zip(&to)) {
+                let delta = (i64::from(to) - i64::from(from)) * x;
+                // Round half away from zero, symmetric for falling channels
+                let offset = (2 * delta + delta.signum() * last) / (2 * last);
+                *channel = (i64::from(from) + offset) as u8;
+            }
+            pixel
+        })
+    }
+
+    /// Creates an 8 x 8 grid of colored cells for checking texture mapping.
+    ///
+    /// Cell I, J covers columns `[i * width / 8, (i + 1) * width / 8)` and
+    /// likewise rows, so cell 0, 0 is at UV 0, 0 with the convention of
+    /// `Sampler`. Its pixels are `[16 + 32 * i, 16 + 32 * j, b, 255]`, where
+    /// B is 255 if `i + j` is even and 128 otherwise. The first row and
+    /// column of every cell are black grid lines, and a white marker of
+    /// `i + 1` pixels in the second row of the cell numbers the columns.
+    /// Cells need to be at least 10 pixels wide for the markers to fit.
+    pub fn uv_grid(width: u32, height: u32) -> Image {
+        let cells = u64::from(UV_GRID_CELLS);
+        let cell_of = |p: u32, size: u32| (u64::from(p) * cells / u64::from(size)) as u32;
+        let cell_start =
+            |c: u32, size: u32| (u64::from(c) * u64::from(size)).div_ceil(cells) as u32;
+
+        Image::from_fn(width, height, |x, y| {
+            let (i, j) = (cell_of(x, width), cell_of(y, height));
+            let (dx, dy) = (x - cell_start(i, width), y - cell_start(j, height));
+
+            if dx == 0 || dy == 0 {
+                [0, 0, 0, 255]
+            } else if dy == 1 && dx <= i + 1 {
+                [255, 255, 255, 255]
+            } else {
+                let blue = if (i + j).is_multiple_of(2) { 255 } else { 128 };
+                [(16 + 32 * i) as u8, (16 + 32 * j) as u8, blue, 255]
+            }
+        })
+    }
+
+    fn from_fn<F>(width: u32, height: u32, f: F) -> Image
+    where
+        F: Fn(u32, u32) -> [u8; 4],
+    {
+        let mut image = Image::new(width, height);
+        for (y, row) in image.rows_mut().enumerate() {
+            for (x, pixel) in row.iter_mut().enumerate() {
+                *pixel = u32::from_le_bytes(f(x as u32, y as u32));
+            }
+        }
+
+        image
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const A: [u8; 4] = [255, 0, 0, 255];
+    const B: [u8; 4] = [0, 0, 255, 255];
+
+    #[test]
+    fn checkerboard_cells() {
+        let image = Image::checkerboard(5, 4, 2, A, B);
+        for (x, y, pixel) in image.enumerate_pixels_rgba() {
+            let expected = if (x / 2 + y / 2) % 2 == 0 { A } else { B };
+            assert_eq!(pixel, expected, "pixel ({}, {})", x, y);
+        }
+        assert_eq!(image.pixel_rgba(4, 0), A);
+        assert_eq!(image.pixel_rgba(4, 3), B);
+
+        // Cells larger than the image
+        let image = Image::checkerboard(3, 5, 4, A, B);
+        for (x, y, pixel) in image.enumerate_pixels_rgba() {
+            assert_eq!(pixel, if y < 4 { A } else { B }, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn gradient_rounds_half_away_from_zero() {
+        let from = [0, 255, 100, 255];
+        let to = [255, 0, 101, 0];
+        let image = Image::gradient_horizontal(5, 2, from, to);
+
+        // Steps of 63.75 rise and fall symmetrically, and 127.5 rounds
+        // away from FROM either way
+        let expected = [
+            [0, 255, 100, 255],
+            [64, 191, 100, 191],
+            [128, 127, 101, 127],
+            [191, 64, 101, 64],
+            [255, 0, 101, 0],
+        ];
+        for (x, y, pixel) in image.enumerate_pixels_rgba() {
+            assert_eq!(pixel, expected[x as usize], "pixel ({}, {})", x, y);
+        }
+
+        assert_eq!(
+            Image::gradient_horizontal(1, 3, from, to),
+            Image::from_pixel_rgba(1, 3, from)
+        );
+        let image = Image::gradient_horizontal(2, 1, from, to);
+        assert_eq!((image.pixel_rgba(0, 0), image.pixel_rgba(1, 0)), (from, to));
+        assert_eq!(
+            Image::gradient_horizontal(0, 3, from, to).dimensions(),
+            (0, 3)
+        );
+    }
+
+    #[test]
+    fn uv_grid_cells() {
+        // Cells start at columns ceil(2.5 * i), and rows 3 * j
+        let image = Image::uv_grid(20, 24);
+        let black = [0, 0, 0, 255];
+        let white = [255; 4];
+        for &x in &[0, 3, 5, 8, 10, 13, 15, 18] {
+            assert_eq!(image.pixel_rgba(x, 2), black, "column {}", x);
+            assert_ne!(image.pixel_rgba(x + 1, 2), black, "column {}", x + 1);
+        }
+        for y in (0..24).step_by(3) {
+            assert!(
+                (0..20).all(|x| image.pixel_rgba(x, y) == black),
+                "row {}",
+                y
+            );
+        }
+
+        // Cell 1, 0 spans columns 3 and 4, cell 7, 7 columns 18 and 19
+        assert_eq!(image.pixel_rgba(1, 2), [16, 16, 255, 255]);
+        assert_eq!(image.pixel_rgba(4, 2), [48, 16, 128, 255]);
+        assert_eq!(image.pixel_rgba(19, 23), [240, 240, 255, 255]);
+        assert_eq!(image.pixel_rgba(7, 5), [80, 48, 128, 255]);
+
+        // Markers number the columns, clipped by the cell
+        assert_eq!(image.pixel_rgba(1, 1), white);
+        assert_eq!(image.pixel_rgba(2, 1), [16, 16, 255, 255]);
+        assert_eq!(image.pixel_rgba(4, 1), white);
+        assert_eq!(image.pixel_rgba(19, 1), white);
+    }
+}
@@ -1,12 +1,41 @@
+pub mod bounds;
+pub mod color;
 pub mod image;
+pub mod mesh;
+pub mod occlusion;
+pub mod outline;
+pub mod pick;
+#[cfg(feature = "pixels")]
+pub mod pixels;
+pub mod post;
+#[cfg(feature = "present")]
+pub mod present;
+pub mod reflection;
 pub mod shader;
+pub mod shaders;
 
 mod convert;
+#[cfg(feature = "rayon")]
+mod parallel;
+#[cfg(feature = "png")]
+mod png_io;
+mod ppm;
+mod quad;
+mod tile;
 
-use glam::{Vec2, Vec3, Vec4};
+use std::borrow::Borrow;
+use std::ops::AddAssign;
 
-use crate::image::Image;
-use crate::shader::{ShaderProgram, Smooth};
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::bounds::{Frustum, Sphere};
+use crate::convert::rgba_to_vec;
+use crate::image::{
+    BlendMode, ColorEncoding, ColorTarget, DepthPixel, Image, MultisampleImage, Pixel, RenderTarget,
+};
+use crate::quad::{Quad, QUAD_PIXELS};
+use crate::shader::{FragmentInput, FragmentOutputs, ShaderProgram, Smooth};
+use crate::tile::{tile_bounds, Bins, CoarseDepth, TILE_SIZE};
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub enum CullFace {
@@ -16,15 +45,472 @@ pub enum CullFace {
     FrontAndBack,
 }
 
+#[allow(clippy::derivable_impls)]
 impl Default for CullFace {
     fn default() -> Self {
         Self::None
     }
 }
 
+/// Row of the attachments the pipeline writes the top of the rendered image,
+/// NDC Y = 1, to.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Origin {
+    /// Row 0 is the top of the image, like windows and image files expect.
+    #[default]
+    TopLeft,
+    /// Row 0 is the bottom of the image, like OpenGL framebuffers.
+    BottomLeft,
+}
+
+impl Origin {
+    /// Returns the row of attachments HEIGHT rows high storing screen space
+    /// row Y, which grows upwards.
+    pub(crate) fn row(self, y: u32, height: u32) -> u32 {
+        match self {
+            Origin::TopLeft => height - 1 - y,
+            Origin::BottomLeft => y,
+        }
+    }
+}
+
+/// Rectangle of the attachments the pipeline renders to, see
+/// `PipelineOptions::viewport`. X and Y are its corner with the lowest
+/// pixel coordinates, with rows as set by `PipelineOptions::origin`, i.e.
+/// its top left corner for `Origin::TopLeft`.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Viewport {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Range of NDC depth of the view volume, which the pipeline remaps to the
+/// [0..1] range of stored depth.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum DepthConvention {
+    /// NDC depth in [-1..1], like OpenGL and `Mat4::perspective_rh_gl`.
+    #[default]
+    NegativeOneToOne,
+    /// NDC depth in [0..1], like Direct3D, Vulkan, wgpu and
+    /// `Mat4::perspective_rh`. Stored as is.
+    ZeroToOne,
+}
+
+impl DepthConvention {
+    /// Remaps NDC depth Z to stored depth.
+    pub fn ndc_to_depth(self, z: f32) -> f32 {
+        let (scale, bias) = self.remap();
+        z * scale + bias
+    }
+
+    /// Remaps stored DEPTH back to NDC depth.
+    pub fn depth_to_ndc(self, depth: f32) -> f32 {
+        let (scale, bias) = self.remap();
+        (depth - bias) / scale
+    }
+
+    /// Returns the scale and bias remapping NDC depth to stored depth.
+    pub(crate) fn remap(self) -> (f32, f32) {
+        match self {
+            DepthConvention::NegativeOneToOne => (0.5, 0.5),
+            DepthConvention::ZeroToOne => (1.0, 0.0),
+        }
+    }
+}
+
+/// Comparison used by the depth and stencil tests. The test passes if
+/// `reference` compares to the stored value, e.g. `Less` passes if
+/// `reference < stored`. Defaults to `Less`, the usual depth test.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum CompareFunc {
+    Never,
+    #[default]
+    Less,
+    Equal,
+    LessEqual,
+    Greater,
+    NotEqual,
+    GreaterEqual,
+    Always,
+}
+
+impl CompareFunc {
+    pub fn compare<T: PartialOrd>(self, reference: T, stored: T) -> bool {
+        match self {
+            CompareFunc::Never => false,
+            CompareFunc::Less => reference < stored,
+            CompareFunc::Equal => reference == stored,
+            CompareFunc::LessEqual => reference <= stored,
+            CompareFunc::Greater => reference > stored,
+            CompareFunc::NotEqual => reference != stored,
+            CompareFunc::GreaterEqual => reference >= stored,
+            CompareFunc::Always => true,
+        }
+    }
+}
+
+/// Operation applied to the stored stencil value depending on the outcome of
+/// the stencil and depth tests.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum StencilOp {
+    Keep,
+    Zero,
+    Replace,
+    /// Increment, clamping at 255.
+    Incr,
+    /// Increment, wrapping 255 to 0.
+    IncrWrap,
+    /// Decrement, clamping at 0.
+    Decr,
+    /// Decrement, wrapping 0 to 255.
+    DecrWrap,
+    Invert,
+}
+
+impl StencilOp {
+    fn apply(self, stored: u8, reference: u8) -> u8 {
+        match self {
+            StencilOp::Keep => stored,
+            StencilOp::Zero => 0,
+            StencilOp::Replace => reference,
+            StencilOp::Incr => stored.saturating_add(1),
+            StencilOp::IncrWrap => stored.wrapping_add(1),
+            StencilOp::Decr => stored.saturating_sub(1),
+            StencilOp::DecrWrap => stored.wrapping_sub(1),
+            StencilOp::Invert => !stored,
+        }
+    }
+}
+
+/// Stencil test configuration, evaluated per fragment before the depth test.
+///
+/// The test compares `reference & read_mask` against `stored & read_mask`
+/// with `func`. Then `fail_op` is applied if the stencil test fails,
+/// `zfail_op` if the stencil test passes but the depth test fails, and
+/// `pass_op` if both pass. Only bits set in `write_mask` are written.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct StencilState {
+    pub func: CompareFunc,
+    pub reference: u8,
+    pub read_mask: u8,
+    pub write_mask: u8,
+    pub fail_op: StencilOp,
+    pub zfail_op: StencilOp,
+    pub pass_op: StencilOp,
+}
+
+impl Default for StencilState {
+    fn default() -> Self {
+        StencilState {
+            func: CompareFunc::Always,
+            reference: 0,
+            read_mask: 0xff,
+            write_mask: 0xff,
+            fail_op: StencilOp::Keep,
+            zfail_op: StencilOp::Keep,
+            pass_op: StencilOp::Keep,
+        }
+    }
+}
+
+impl StencilState {
+    fn test(&self, stored: u8) -> bool {
+        self.func
+            .compare(self.reference & self.read_mask, stored & self.read_mask)
+    }
+
+    fn update(&self, stored: u8, op: StencilOp) -> u8 {
+        let value = op.apply(stored, self.reference);
+        (stored & !self.write_mask) | (value & self.write_mask)
+    }
+}
+
+/// Distance fog, blending fragment colors towards `color` with growing
+/// distance from the camera.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Fog {
+    /// Linear color of the fog. Its alpha is ignored, fragments keep theirs.
+    pub color: Vec4,
+    pub mode: FogMode,
+}
+
+/// How the density of `Fog` grows with the distance D from the camera.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum FogMode {
+    /// No fog before START, growing linearly to full fog at END.
+    Linear { start: f32, end: f32 },
+    /// Fragments keep `exp(-density * D)` of their color.
+    Exp { density: f32 },
+    /// Fragments keep `exp(-(density * D)^2)` of their color.
+    Exp2 { density: f32 },
+}
+
+impl Fog {
+    /// Returns the fraction of the fragment color kept at DISTANCE, 1.0
+    /// without fog and 0.0 in full fog.
+    pub fn visibility(&self, distance: f32) -> f32 {
+        let visibility = match self.mode {
+            FogMode::Linear { end, .. } if distance >= end => 0.0,
+            FogMode::Linear { start, end } => (end - distance) / (end - start),
+            FogMode::Exp { density } => (-density * distance).exp(),
+            FogMode::Exp2 { density } => (-(density * distance).powi(2)).exp(),
+        };
+
+        visibility.clamp(0.0, 1.0)
+    }
+
+    /// Blends COLOR towards the fog color by the fog at DISTANCE.
+    pub fn apply(&self, color: Vec4, distance: f32) -> Vec4 {
+        let visibility = self.visibility(distance);
+        let rgb = self.color.truncate().lerp(color.truncate(), visibility);
+
+        rgb.extend(color.w)
+    }
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
 pub struct PipelineOptions {
     pub cull_face: CullFace,
+    /// Interpolate varyings perspective-correctly instead of linearly in
+    /// screen space. See `Smooth::interpolate_perspective`.
+    pub perspective_correct: bool,
+    /// Stencil test, requires a framebuffer with a stencil attachment.
+    pub stencil: Option<StencilState>,
+    /// Discard fragments whose alpha is below the cutoff, without writing
+    /// color or depth. The alpha is taken from the first fragment output,
+    /// see `FragmentOutputs::alpha`.
+    pub alpha_cutoff: Option<f32>,
+    /// Encode fragment colors from linear to sRGB before quantizing them.
+    pub srgb_framebuffer: bool,
+    /// Dither fragment colors before quantizing them, see
+    /// `ColorEncoding::dither`.
+    pub dither: bool,
+    /// Blend fragment colors with the stored colors instead of replacing
+    /// them, see `BlendMode`. Raw `u32` fragment outputs are written as is.
+    pub blend: Option<BlendMode>,
+    /// Added to the depth of fragments before the depth test, in the [0..1]
+    /// range of stored depth, like the units of `glPolygonOffset`. Negative
+    /// values pull triangles towards the camera, e.g. for decals.
+    pub depth_bias: f32,
+    /// Added to the depth of fragments before the depth test, scaled by the
+    /// largest change of depth per pixel along screen space X or Y, like the
+    /// factor of `glPolygonOffset`. Grows towards grazing angles, e.g. to
+    /// avoid acne in shadow maps.
+    pub depth_bias_slope: f32,
+    /// Reject triangles whose bounding box contains no pixel sample point,
+    /// which can't cover any pixel, and triangles covering less than the
+    /// given screen space area in pixels, which can. `Some(0.0)` only rejects
+    /// the former, saving their setup without changing the output, except for
+    /// `Pipeline::triangles_multisample`, which also samples between pixels.
+    pub small_triangle_cull: Option<f32>,
+    /// Make `Pipeline::draw_parallel` rasterize on the calling thread, e.g.
+    /// to rule out threading when debugging. Output is the same either way.
+    pub single_threaded: bool,
+    /// Whether rendered images are stored top down, the default, or bottom
+    /// up in the attachments. Texture sampling is not affected.
+    pub origin: Origin,
+    /// Range of NDC depth produced by the projection matrices of the vertex
+    /// shaders.
+    pub depth_convention: DepthConvention,
+    /// Written to `Framebuffer::ids` for every pixel written, e.g. the
+    /// index of the object drawn. Set it with `Pipeline::options_mut`
+    /// between draw calls.
+    pub draw_id: u32,
+    /// Comparison of the depth of fragments with the stored depth, see
+    /// `CompareFunc`. Fragments pass the depth test if it holds.
+    pub depth_func: CompareFunc,
+    /// Test fragments against the stored depth without writing theirs, e.g.
+    /// for the shading pass after a depth pre-pass, see
+    /// `Pipeline::shade_equal`.
+    pub depth_read_only: bool,
+    /// Planes in clip space clipping away the parts of triangles behind
+    /// them, i.e. points P with `plane.dot(P) < 0`, e.g. for cross-sections.
+    /// Varyings are interpolated at the new vertices. Transform world space
+    /// planes to clip space with `reflection::transform_plane` and the
+    /// view-projection matrix.
+    pub clip_planes: [Option<Vec4>; MAX_CLIP_PLANES],
+    /// Fog applied to the color of the first fragment output before it is
+    /// written, see `FragmentOutputs::map_color`. The distance is the eye
+    /// space depth of the fragment, i.e. clip space W, which perspective
+    /// projections set to the distance along the view direction, so fog
+    /// doesn't band near the camera like stored depth would. Orthographic
+    /// projections keep W at 1, so the fog is uniform.
+    pub fog: Option<Fog>,
+    /// Rectangle of the attachments NDC is mapped to, instead of all of
+    /// them, e.g. for split-screen rendering. It also scissors: pixels
+    /// outside of it are not written, even by triangles extending past the
+    /// edges of NDC, so neighboring viewports never bleed into each other.
+    /// See `Pipeline::draw_multiview` to draw into several at once.
+    pub viewport: Option<Viewport>,
+}
+
+/// Number of `PipelineOptions::clip_planes`.
+pub const MAX_CLIP_PLANES: usize = 4;
+
+impl PipelineOptions {
+    /// Converts DEPTH stored by the pipeline back to the eye space distance
+    /// from the camera, for a perspective projection with the NEAR and FAR
+    /// planes. Stored depth is the NDC z remapped to [0..1], see
+    /// `depth_convention`.
+    pub fn linearize_depth(&self, depth: f32, near: f32, far: f32) -> f32 {
+        match self.depth_convention {
+            DepthConvention::NegativeOneToOne => {
+                let z_ndc = depth * 2.0 - 1.0;
+                2.0 * near * far / (far + near - z_ndc * (far - near))
+            }
+            DepthConvention::ZeroToOne => near * far / (far - depth * (far - near)),
+        }
+    }
+
+    /// Linearizes all pixels of IMAGE_DEPTH, row by row, see
+    /// `linearize_depth`.
+    pub fn linearize_depth_image(&self, image_depth: &Image, near: f32, far: f32) -> Vec<f32> {
+        image_depth
+            .enumerate_pixels_depth()
+            .map(|(_, _, depth)| self.linearize_depth(depth, near, far))
+            .collect()
+    }
+}
+
+/// Counters of the work done by a draw call, e.g. to diagnose culling or
+/// performance problems.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct PipelineStats {
+    /// Triangles in the attribute buffer.
+    pub triangles_submitted: u64,
+    /// Triangles culled by `PipelineOptions::cull_face`.
+    pub triangles_culled: u64,
+    /// Triangles rejected for having (near) zero area, so that they can't
+    /// cover any pixel.
+    pub triangles_degenerate: u64,
+    /// Triangles rejected for lying entirely outside of the screen or the
+    /// depth range.
+    pub triangles_clipped: u64,
+    /// Triangles rejected by `PipelineOptions::small_triangle_cull`.
+    pub triangles_small: u64,
+    /// Triangles rejected for a vertex position with NaN or infinite
+    /// components after the perspective divide, usually from a bug in the
+    /// vertex shader, e.g. normalizing a zero vector, or a zero W. Triangles
+    /// so large that their area overflows are counted here too.
+    pub triangles_nonfinite: u64,
+    /// Covered pixels that went through the stencil and depth tests. Pixels
+    /// of blocks rejected by the coarse depth test are not counted.
+    pub fragments_tested: u64,
+    /// Fragments that passed the depth test and were shaded, if there is
+    /// something to shade them for.
+    pub fragments_passed: u64,
+    /// Pixels written to the attachments, i.e. fragments that also passed
+    /// the alpha test.
+    pub pixels_written: u64,
+}
+
+impl AddAssign for PipelineStats {
+    fn add_assign(&mut self, other: PipelineStats) {
+        self.triangles_submitted += other.triangles_submitted;
+        self.triangles_culled += other.triangles_culled;
+        self.triangles_degenerate += other.triangles_degenerate;
+        self.triangles_clipped += other.triangles_clipped;
+        self.triangles_small += other.triangles_small;
+        self.triangles_nonfinite += other.triangles_nonfinite;
+        self.fragments_tested += other.fragments_tested;
+        self.fragments_passed += other.fragments_passed;
+        self.pixels_written += other.pixels_written;
+    }
+}
+
+/// Set of images the pipeline renders into. All attachments must have equal
+/// dimensions. Color attachments are 8-bit `Image`s by default, or any other
+/// `ColorTarget`, e.g. `ImageF32` for HDR rendering. Depth is stored in
+/// `Image`s by default, or any other `RenderTarget` of `DepthPixel`s, e.g.
+/// `ImageDepth16` to save memory. Stencil and IDs are stored in `Image`s, or
+/// any other `RenderTarget` with the same storage, e.g. `ImageViewMut` to
+/// render into a part of an image, set in the fields directly.
+pub struct Framebuffer<
+    'a,
+    'b,
+    C: ColorTarget = Image,
+    D: RenderTarget = Image,
+    T: RenderTarget<Pixel = u32> = Image,
+> {
+    /// Color attachments, one per fragment output, or none to draw depth
+    /// only.
+    pub color: &'a mut [&'b mut C],
+    pub depth: &'a mut D,
+    /// Stencil attachment storing 8 bits per pixel, see
+    /// `Image::pixel_stencil`.
+    pub stencil: Option<&'a mut T>,
+    /// ID attachment, e.g. for picking. Pixels written by a draw call are
+    /// set to `PipelineOptions::draw_id`, so that reading one back tells
+    /// which draw call covered it last.
+    pub ids: Option<&'a mut T>,
+    /// Like `ids`, but pixels are set to the primitive index of the
+    /// triangle, see `FragmentInput::primitive`.
+    pub primitive_ids: Option<&'a mut T>,
+    /// Auxiliary attachment, e.g. for velocities quantized to 32 bits or
+    /// per-pixel flags. Pixels are set to the word returned by
+    /// `FragmentOutputs::aux`, see `WithAux`, together with the color
+    /// attachments, so they stay consistent with the depth test.
+    pub aux: Option<&'a mut T>,
+    /// Depth of the previous layer in depth peeling. Fragments only pass
+    /// the depth test if they are also strictly behind it, see
+    /// `Pipeline::depth_peel`.
+    pub peel_depth: Option<&'a D>,
+}
+
+impl<'a, 'b, C, D> Framebuffer<'a, 'b, C, D>
+where
+    C: ColorTarget,
+    D: RenderTarget,
+{
+    pub fn new(color: &'a mut [&'b mut C], depth: &'a mut D) -> Self {
+        Framebuffer {
+            color,
+            depth,
+            stencil: None,
+            ids: None,
+            primitive_ids: None,
+            aux: None,
+            peel_depth: None,
+        }
+    }
+}
+
+impl<'a, 'b, C, D, T> Framebuffer<'a, 'b, C, D, T>
+where
+    C: ColorTarget,
+    D: RenderTarget,
+    T: RenderTarget<Pixel = u32>,
+{
+    pub fn with_stencil(mut self, stencil: &'a mut T) -> Self {
+        self.stencil = Some(stencil);
+        self
+    }
+
+    pub fn with_ids(mut self, ids: &'a mut T) -> Self {
+        self.ids = Some(ids);
+        self
+    }
+
+    pub fn with_primitive_ids(mut self, primitive_ids: &'a mut T) -> Self {
+        self.primitive_ids = Some(primitive_ids);
+        self
+    }
+
+    pub fn with_aux(mut self, aux: &'a mut T) -> Self {
+        self.aux = Some(aux);
+        self
+    }
+
+    pub fn with_peel_depth(mut self, peel_depth: &'a D) -> Self {
+        self.peel_depth = Some(peel_depth);
+        self
+    }
+
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.depth.dimensions()
+    }
 }
 
 pub struct Pipeline {
@@ -36,137 +522,1637 @@ impl Pipeline {
         Pipeline { options }
     }
 
-    pub fn triangles<S: ShaderProgram>(
-        &self,
+    pub fn options(&self) -> &PipelineOptions {
+        &self.options
+    }
+
+    /// Returns the options for mutation between draw calls, e.g. to disable
+    /// culling for foliage after drawing opaque geometry. The pipeline keeps
+    /// no other state, so this is free, and each draw call uses the options
+    /// current when it starts for all of its stages.
+    pub fn options_mut(&mut self) -> &mut PipelineOptions {
+        &mut self.options
+    }
+
+    /// Returns false if an object within BOUNDS is entirely outside of the
+    /// view volume of TRANSFORM, the transform from the space of BOUNDS to
+    /// clip space applied by the vertex shader. Drawing such an object
+    /// writes nothing, so the draw call can be skipped, vertex shading
+    /// included.
+    pub fn would_draw(&self, bounds: &Sphere, transform: Mat4) -> bool {
+        Frustum::with_depth_convention(transform, self.options.depth_convention)
+            .intersects_sphere(bounds)
+    }
+
+    pub fn triangles<S, C, D>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        image_color: &mut C,
+        image_depth: &mut D,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        self.triangles_mrt(shader, buffer, &mut [image_color], image_depth)
+    }
+
+    /// Like `triangles`, but writes the fragment outputs to multiple color
+    /// attachments. The depth test runs once per fragment against
+    /// `image_depth` before any attachment is written.
+    pub fn triangles_mrt<S, C, D>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        images_color: &mut [&mut C],
+        image_depth: &mut D,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        self.draw(
+            shader,
+            buffer,
+            &mut Framebuffer::new(images_color, image_depth),
+        )
+    }
+
+    /// Like `triangles`, but the attribute of each vertex is its index, from
+    /// 0 to VERTEX_COUNT. The shader fetches the vertex data from its own
+    /// storage, e.g. separate position, normal and texture coordinate slices.
+    pub fn triangles_by_index<S, C, D>(
+        &self,
+        shader: &S,
+        vertex_count: u32,
+        image_color: &mut C,
+        image_depth: &mut D,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram<Attribute = u32>,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        self.draw_attributes(
+            shader,
+            0..vertex_count,
+            &mut Framebuffer::new(&mut [image_color], image_depth),
+        )
+    }
+
+    /// Draws triangles to all attachments of the framebuffer.
+    ///
+    /// All triangles are transformed and binned to screen space tiles first,
+    /// then rasterized one tile at a time, so that the pixels written stay in
+    /// cache. Trailing vertices that don't make up a whole triangle are
+    /// ignored.
+    pub fn draw<S, C, D, T>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        framebuffer: &mut Framebuffer<C, D, T>,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        self.draw_attributes(shader, buffer, framebuffer)
+    }
+
+    /// Like `draw`, but consumes the vertex attributes from an iterator, so
+    /// that generated geometry doesn't need to be collected first. Trailing
+    /// vertices that don't make up a whole triangle are ignored.
+    pub fn draw_iter<S, I, C, D, T>(
+        &self,
+        shader: &S,
+        attributes: I,
+        framebuffer: &mut Framebuffer<C, D, T>,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        I: IntoIterator<Item = S::Attribute>,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        self.draw_attributes(shader, attributes, framebuffer)
+    }
+
+    /// Draws the triangles in BUFFER once into each of VIEWS, e.g. the eyes
+    /// of a stereo pair side by side, or split-screen players.
+    ///
+    /// Each view is a `PipelineOptions::viewport` and the transform applied
+    /// to the positions returned by the vertex shader, e.g. a
+    /// view-projection matrix, so the vertex shader of SHADER runs only
+    /// once per vertex for all views and must return positions in the space
+    /// the transforms map from, e.g. world space. Its varyings are shared by
+    /// the views too, so they can't depend on the view. The viewport of the
+    /// pipeline options is ignored. Triangles are counted in
+    /// `PipelineStats` once per view.
+    pub fn draw_multiview<S, C, D, T>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        views: &[(Viewport, Mat4)],
+        framebuffer: &mut Framebuffer<C, D, T>,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        S::Varying: Clone,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        let (width, height) = framebuffer.dimensions();
+        self.check_framebuffer::<S, C, D, T>(framebuffer);
+
+        let vertices: Vec<(Vec4, S::Varying)> = buffer[..buffer.len() / 3 * 3]
+            .iter()
+            .map(|attribute| shader.vertex(attribute))
+            .collect();
+
+        let mut stats = PipelineStats::default();
+        for &(viewport, transform) in views {
+            let pipeline = Pipeline::with_options(PipelineOptions {
+                viewport: Some(viewport),
+                ..self.options
+            });
+
+            let mut bins = Bins::new(width, height);
+            for (primitive, triangle) in vertices.chunks_exact(3).enumerate() {
+                let [(a, va), (b, vb), (c, vc)] = [&triangle[0], &triangle[1], &triangle[2]];
+                pipeline.setup_clipped(
+                    (transform * *a, transform * *b, transform * *c),
+                    (va.clone(), vb.clone(), vc.clone()),
+                    width,
+                    height,
+                    &mut stats,
+                    |screen, vars| bins.push(primitive as u32, screen, vars),
+                );
+            }
+
+            for (bounds, triangles) in bins.tiles() {
+                let mut coarse_depth = CoarseDepth::new(bounds, self.options.origin);
+                for &i in triangles {
+                    let (primitive, screen, vars) = bins.triangle(i);
+                    pipeline.triangle(
+                        shader,
+                        framebuffer,
+                        &mut coarse_depth,
+                        &mut stats,
+                        primitive,
+                        screen,
+                        vars,
+                    );
+                }
+            }
+        }
+
+        stats
+    }
+
+    /// Writes the depth of the triangles in BUFFER to IMAGE_DEPTH without
+    /// shading them, unless for the alpha test, so that `shade_equal` with
+    /// the same SHADER and BUFFER then shades only the visible fragments.
+    ///
+    /// The shading pass passes the depth test only for depth bit-identical
+    /// to the stored depth, which this pairing guarantees: both passes run
+    /// the same vertex shader on the same attributes with the same options,
+    /// so they rasterize the same depth. A cheaper shader in this pass must
+    /// compute positions with exactly the same operations, e.g. not fold
+    /// the model, view and projection matrices into one where the other
+    /// multiplies them one by one. The stencil test is ignored.
+    pub fn depth_prepass<S, D>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        image_depth: &mut D,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            stencil: None,
+            ..self.options
+        });
+        let images_color: &mut [&mut Image] = &mut [];
+        pipeline.draw(
+            shader,
+            buffer,
+            &mut Framebuffer::new(images_color, image_depth),
+        )
+    }
+
+    /// Draws the triangles in BUFFER after `depth_prepass` wrote their depth
+    /// to the depth attachment of FRAMEBUFFER, shading only the fragments
+    /// that are visible, i.e. those whose depth equals the stored depth.
+    /// Depth is not written again.
+    pub fn shade_equal<S, C, D, T>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        framebuffer: &mut Framebuffer<C, D, T>,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            depth_func: CompareFunc::Equal,
+            depth_read_only: true,
+            ..self.options
+        });
+        pipeline.draw(shader, buffer, framebuffer)
+    }
+
+    /// Renders the nearest LAYERS surfaces of the translucent triangles in
+    /// BUFFER at every pixel and composites them over IMAGE_COLOR from back
+    /// to front, so that transparency is correct regardless of the order of
+    /// the triangles, even if they intersect.
+    ///
+    /// Each pass renders the next nearest layer, with the depth of the
+    /// previous layer as `Framebuffer::peel_depth`, in front of the opaque
+    /// scene in IMAGE_DEPTH, which is not written. Surfaces behind the last
+    /// layer are dropped, as is all but one of surfaces with exactly equal
+    /// depth, e.g. where they intersect. Layers are rendered without blending and
+    /// composited by their straight alpha in 8 bits, like
+    /// `Image::blit_over`. Passes stop early once a layer is empty.
+    pub fn depth_peel<S, D>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        image_color: &mut Image,
+        image_depth: &D,
+        layers: u32,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram<Fragment = Vec4>,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (width, height) = image_depth.dimensions();
+        assert!(
+            image_color.dimensions() == (width, height),
+            "images must have equal dims"
+        );
+
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            stencil: None,
+            blend: None,
+            depth_func: CompareFunc::Less,
+            depth_read_only: false,
+            ..self.options
+        });
+
+        let mut stats = PipelineStats::default();
+        let mut images_layer = Vec::new();
+        let mut peel_depth: Option<Image<D::Pixel>> = None;
+        for _ in 0..layers {
+            let mut layer_depth = Image::from_pixel(width, height, D::Pixel::CLEAR);
+            for y in 0..height {
+                for x in 0..width {
+                    layer_depth.set_pixel(x, y, image_depth.pixel(x, y));
+                }
+            }
+
+            let mut image_layer = Image::new(width, height);
+            let mut images_color = [&mut image_layer];
+            let mut framebuffer = Framebuffer::new(&mut images_color, &mut layer_depth);
+            if let Some(peel_depth) = &peel_depth {
+                framebuffer = framebuffer.with_peel_depth(peel_depth);
+            }
+
+            let layer_stats = pipeline.draw(shader, buffer, &mut framebuffer);
+            stats += layer_stats;
+            if layer_stats.pixels_written == 0 {
+                break;
+            }
+
+            images_layer.push(image_layer);
+            peel_depth = Some(layer_depth);
+        }
+
+        for image_layer in images_layer.iter().rev() {
+            image_color.blit_over(image_layer, 0, 0);
+        }
+
+        stats
+    }
+
+    fn draw_attributes<S, I, C, D, T>(
+        &self,
+        shader: &S,
+        attributes: I,
+        framebuffer: &mut Framebuffer<C, D, T>,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram,
+        I: IntoIterator,
+        I::Item: Borrow<S::Attribute>,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        let (width, height) = framebuffer.dimensions();
+        self.check_framebuffer::<S, C, D, T>(framebuffer);
+
+        let mut stats = PipelineStats::default();
+        let bins = self.bin(shader, attributes, width, height, &mut stats);
+        for (bounds, triangles) in bins.tiles() {
+            let mut coarse_depth = CoarseDepth::new(bounds, self.options.origin);
+            for &i in triangles {
+                let (primitive, screen, vars) = bins.triangle(i);
+                self.triangle(
+                    shader,
+                    framebuffer,
+                    &mut coarse_depth,
+                    &mut stats,
+                    primitive,
+                    screen,
+                    vars,
+                );
+            }
+        }
+
+        stats
+    }
+
+    /// Asserts that the attachments of FRAMEBUFFER match the fragment
+    /// outputs of S and the pipeline options.
+    fn check_framebuffer<S, C, D, T>(&self, framebuffer: &Framebuffer<C, D, T>)
+    where
+        S: ShaderProgram,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        let (width, height) = framebuffer.dimensions();
+
+        // Framebuffers without color attachments draw depth only, with any
+        // shader
+        assert!(
+            framebuffer.color.is_empty() || framebuffer.color.len() == S::Fragment::COUNT,
+            "color attachment count must match fragment outputs"
+        );
+        for image_color in framebuffer.color.iter() {
+            assert!(
+                image_color.dimensions() == (width, height),
+                "images must have equal dims"
+            );
+        }
+        let words = [
+            &framebuffer.stencil,
+            &framebuffer.ids,
+            &framebuffer.primitive_ids,
+            &framebuffer.aux,
+        ];
+        for image in words.iter().filter_map(|image| image.as_ref()) {
+            assert!(
+                image.dimensions() == (width, height),
+                "images must have equal dims"
+            );
+        }
+        if let Some(peel_depth) = framebuffer.peel_depth {
+            assert!(
+                peel_depth.dimensions() == (width, height),
+                "images must have equal dims"
+            );
+        }
+        assert!(
+            self.options.stencil.is_none() || framebuffer.stencil.is_some(),
+            "stencil test requires a stencil attachment"
+        );
+    }
+
+    /// Like `triangles`, but renders into multisampled images, evaluating
+    /// coverage and depth at `MultisampleImage::SAMPLES` positions per
+    /// pixel. The fragment shader still runs once per covered pixel. Use
+    /// `MultisampleImage::resolve_rgba` to produce the final image.
+    pub fn triangles_multisample<S: ShaderProgram<Fragment = Vec4>>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        image_color: &mut MultisampleImage,
+        image_depth: &mut MultisampleImage,
+    ) -> PipelineStats {
+        let (width, height) = image_depth.dimensions();
+
+        assert!(
+            image_color.dimensions() == (width, height),
+            "images must have equal dims"
+        );
+
+        let mut stats = PipelineStats::default();
+        let mut fragment_stats = PipelineStats::default();
+        self.geometry(
+            shader,
+            buffer,
+            width,
+            height,
+            &mut stats,
+            |primitive, screen, (va, vb, vc)| {
+                self.triangle_multisample(
+                    shader,
+                    image_color,
+                    image_depth,
+                    &mut fragment_stats,
+                    primitive,
+                    screen,
+                    (&va, &vb, &vc),
+                );
+            },
+        );
+
+        stats += fragment_stats;
+        stats
+    }
+
+    /// Counts the fragments of the triangles in BUFFER that would pass the
+    /// depth test against IMAGE_DEPTH, e.g. to test the visibility of a
+    /// cheap proxy of an expensive object.
+    ///
+    /// Culling works as for `triangles`, but only the vertex shader runs and
+    /// nothing is written, so the triangles don't occlude each other. The
+    /// stencil and alpha tests are ignored.
+    pub fn occlusion_query<S, D>(&self, shader: &S, buffer: &[S::Attribute], image_depth: &D) -> u64
+    where
+        S: ShaderProgram,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (width, height) = image_depth.dimensions();
+
+        let mut count = 0;
+        let mut stats = PipelineStats::default();
+        self.geometry(shader, buffer, width, height, &mut stats, |_, screen, _| {
+            count += self.triangle_query(image_depth, screen, false);
+        });
+
+        count
+    }
+
+    /// Like `occlusion_query`, but only returns whether any fragment would
+    /// pass the depth test, stopping rasterization at the first one.
+    pub fn occlusion_query_any<S, D>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        image_depth: &D,
+    ) -> bool
+    where
+        S: ShaderProgram,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (width, height) = image_depth.dimensions();
+
+        let mut visible = false;
+        let mut stats = PipelineStats::default();
+        self.geometry(shader, buffer, width, height, &mut stats, |_, screen, _| {
+            visible = visible || self.triangle_query(image_depth, screen, true) > 0;
+        });
+
+        visible
+    }
+
+    /// Runs the geometry phase for all triangles in ATTRIBUTES, binning the
+    /// screen space triangles to tiles of a WIDTH x HEIGHT framebuffer.
+    fn bin<S, I>(
+        &self,
+        shader: &S,
+        attributes: I,
+        width: u32,
+        height: u32,
+        stats: &mut PipelineStats,
+    ) -> Bins<S::Varying>
+    where
+        S: ShaderProgram,
+        I: IntoIterator,
+        I::Item: Borrow<S::Attribute>,
+    {
+        let mut bins = Bins::new(width, height);
+        self.geometry(
+            shader,
+            attributes,
+            width,
+            height,
+            stats,
+            |primitive, screen, vars| {
+                bins.push(primitive, screen, vars);
+            },
+        );
+
+        bins
+    }
+
+    /// Runs the vertex shader and culling for each triangle in ATTRIBUTES
+    /// and passes the screen space triangles to RASTERIZE with their
+    /// primitive index, see `FragmentInput::primitive`. Degenerate triangles
+    /// and triangles outside of the screen are rejected too.
+    fn geometry<S, I, F>(
+        &self,
+        shader: &S,
+        attributes: I,
+        width: u32,
+        height: u32,
+        stats: &mut PipelineStats,
+        mut rasterize: F,
+    ) where
+        S: ShaderProgram,
+        I: IntoIterator,
+        I::Item: Borrow<S::Attribute>,
+        F: FnMut(u32, (Vec4, Vec4, Vec4), (S::Varying, S::Varying, S::Varying)),
+    {
+        let mut attributes = attributes.into_iter();
+        let mut primitive = 0;
+        while let (Some(attr_a), Some(attr_b), Some(attr_c)) =
+            (attributes.next(), attributes.next(), attributes.next())
+        {
+            let (world_a, var_a) = shader.vertex(attr_a.borrow());
+            let (world_b, var_b) = shader.vertex(attr_b.borrow());
+            let (world_c, var_c) = shader.vertex(attr_c.borrow());
+
+            self.setup_clipped(
+                (world_a, world_b, world_c),
+                (var_a, var_b, var_c),
+                width,
+                height,
+                stats,
+                |screen, vars| rasterize(primitive, screen, vars),
+            );
+            primitive += 1;
+        }
+    }
+
+    /// Clips a triangle against `PipelineOptions::clip_planes` and passes
+    /// the screen space triangles the remaining polygon is split into to
+    /// EMIT, with their varyings, see `setup`.
+    fn setup_clipped<V, F>(
+        &self,
+        (world_a, world_b, world_c): (Vec4, Vec4, Vec4),
+        (var_a, var_b, var_c): (V, V, V),
+        width: u32,
+        height: u32,
+        stats: &mut PipelineStats,
+        mut emit: F,
+    ) where
+        V: Smooth,
+        F: FnMut((Vec4, Vec4, Vec4), (V, V, V)),
+    {
+        stats.triangles_submitted += 1;
+
+        let polygon = match self.clip((world_a, world_b, world_c)) {
+            Clip::Inside => {
+                if let Some(screen) = self.setup((world_a, world_b, world_c), width, height, stats)
+                {
+                    emit(screen, (var_a, var_b, var_c));
+                }
+                return;
+            }
+            Clip::Outside => {
+                stats.triangles_clipped += 1;
+                return;
+            }
+            Clip::Polygon(polygon) => polygon,
+        };
+
+        // The polygon is convex, so it can be split into a fan
+        let (world_first, bc_first) = polygon[0];
+        for pair in polygon[1..].windows(2) {
+            let [(world_b, bc_b), (world_c, bc_c)] = [pair[0], pair[1]];
+            if let Some(screen) = self.setup((world_first, world_b, world_c), width, height, stats)
+            {
+                let interpolate = |bc| V::interpolate(&var_a, &var_b, &var_c, bc);
+                emit(
+                    screen,
+                    (interpolate(bc_first), interpolate(bc_b), interpolate(bc_c)),
+                );
+            }
+        }
+    }
+
+    /// Clips the clip space triangle A, B, C against
+    /// `PipelineOptions::clip_planes` with the Sutherland-Hodgman algorithm.
+    fn clip(&self, (a, b, c): (Vec4, Vec4, Vec4)) -> Clip {
+        let planes = self.options.clip_planes.iter().flatten();
+
+        // Most triangles are entirely inside. Non-finite positions are left
+        // to `setup` to reject
+        let mut inside = true;
+        for plane in planes.clone() {
+            let distances = Vec3::new(plane.dot(a), plane.dot(b), plane.dot(c));
+            if !distances.is_finite() {
+                return Clip::Inside;
+            }
+            if distances.max_element() < 0.0 {
+                return Clip::Outside;
+            }
+            inside &= distances.min_element() >= 0.0;
+        }
+        if inside {
+            return Clip::Inside;
+        }
+
+        let mut polygon = vec![(a, Vec3::X), (b, Vec3::Y), (c, Vec3::Z)];
+        let mut clipped = Vec::with_capacity(polygon.len() + MAX_CLIP_PLANES);
+        for plane in planes {
+            clipped.clear();
+            for (i, &from) in polygon.iter().enumerate() {
+                let to = polygon[(i + 1) % polygon.len()];
+                let from_inside = plane.dot(from.0) >= 0.0;
+                if from_inside {
+                    clipped.push(from);
+                }
+
+                // Interpolate from the inside vertex, so that triangles
+                // sharing the edge get exactly the same vertex
+                if from_inside != (plane.dot(to.0) >= 0.0) {
+                    let (inner, outer) = if from_inside { (from, to) } else { (to, from) };
+                    let (d_inner, d_outer) = (plane.dot(inner.0), plane.dot(outer.0));
+                    let t = d_inner / (d_inner - d_outer);
+                    clipped.push((inner.0.lerp(outer.0, t), inner.1 + (outer.1 - inner.1) * t));
+                }
+            }
+
+            std::mem::swap(&mut polygon, &mut clipped);
+            if polygon.len() < 3 {
+                return Clip::Outside;
+            }
+        }
+
+        Clip::Polygon(polygon)
+    }
+
+    /// Transforms a triangle from clip space to the screen space of a WIDTH x
+    /// HEIGHT framebuffer. Returns None if the triangle is culled, degenerate
+    /// or outside of the screen.
+    fn setup(
+        &self,
+        (world_a, world_b, world_c): (Vec4, Vec4, Vec4),
+        width: u32,
+        height: u32,
+        stats: &mut PipelineStats,
+    ) -> Option<(Vec4, Vec4, Vec4)> {
+        let (x, y, viewport_width, viewport_height) = self.screen_viewport(width, height);
+        let offset = Vec4::new(x as f32, y as f32, 0.0, 0.0);
+        let half_width = viewport_width as f32 / 2.0;
+        let half_height = viewport_height as f32 / 2.0;
+
+        let screen_a = world_to_screen(from_homogenous(world_a), half_width, half_height) + offset;
+        let screen_b = world_to_screen(from_homogenous(world_b), half_width, half_height) + offset;
+        let screen_c = world_to_screen(from_homogenous(world_c), half_width, half_height) + offset;
+
+        // NaN would pass or fail the tests below arbitrarily, and saturate
+        // to 0 when converted to pixel coordinates
+        let finite = screen_a.is_finite() && screen_b.is_finite() && screen_c.is_finite();
+        if !finite {
+            stats.triangles_nonfinite += 1;
+            return None;
+        }
+
+        let a2 = Vec2::new(screen_a.x, screen_a.y);
+        let b2 = Vec2::new(screen_b.x, screen_b.y);
+        let c2 = Vec2::new(screen_c.x, screen_c.y);
+
+        // Counter-clockwise triangles, i.e. with positive area, are front
+        // facing
+        let area = signed_area(a2, b2, c2);
+        let do_cull = match self.options.cull_face {
+            CullFace::None => false,
+            CullFace::FrontAndBack => true,
+            CullFace::Front => area > 0.0,
+            CullFace::Back => area < 0.0,
+        };
+        if do_cull {
+            stats.triangles_culled += 1;
+            return None;
+        }
+
+        // Degenerate triangles don't cover any pixel, see `barycentric`
+        if !area.is_finite() {
+            stats.triangles_nonfinite += 1;
+            return None;
+        }
+        if f32::abs(area) < 1.0 {
+            stats.triangles_degenerate += 1;
+            return None;
+        }
+
+        // Depth is linear in screen space, so no fragment of a triangle with
+        // all vertices beyond the same depth plane is in range
+        let z = Vec3::new(screen_a.z, screen_b.z, screen_c.z);
+        let convention = self.options.depth_convention;
+        let outside_depth = convention.ndc_to_depth(z.min_element()) > 1.0
+            || convention.ndc_to_depth(z.max_element()) < 0.0;
+
+        // Conservative for multisampling, so that no sample is missed
+        let bounds = self.scissored_bounding_box(a2, b2, c2, width, height, SAMPLE_MARGIN);
+        if outside_depth || bounds.is_none() {
+            stats.triangles_clipped += 1;
+            return None;
+        }
+
+        if let Some(min_area) = self.options.small_triangle_cull {
+            // Pixels are sampled at integer coordinates
+            let xmin = f32::min(f32::min(a2.x, b2.x), c2.x);
+            let xmax = f32::max(f32::max(a2.x, b2.x), c2.x);
+            let ymin = f32::min(f32::min(a2.y, b2.y), c2.y);
+            let ymax = f32::max(f32::max(a2.y, b2.y), c2.y);
+            let misses_samples = xmin.ceil() > xmax.floor() || ymin.ceil() > ymax.floor();
+
+            if misses_samples || f32::abs(area) / 2.0 < min_area {
+                stats.triangles_small += 1;
+                return None;
+            }
+        }
+
+        Some((screen_a, screen_b, screen_c))
+    }
+
+    /// Writes the pixels of a triangle inside the tile of COARSE_DEPTH to
+    /// the framebuffer attachments.
+    ///
+    /// Pixels are processed in aligned 2x2 quads, so that screen space
+    /// derivatives of the varyings can be computed for each quad from its
+    /// pixels, whether they are covered or not. Quads in blocks of
+    /// `COARSE_DEPTH` entirely in front of the triangle are skipped.
+    #[allow(clippy::too_many_arguments)]
+    fn triangle<S, C, D, T>(
+        &self,
+        shader: &S,
+        framebuffer: &mut Framebuffer<C, D, T>,
+        coarse_depth: &mut CoarseDepth,
+        stats: &mut PipelineStats,
+        primitive: u32,
+        (a, b, c): (Vec4, Vec4, Vec4),
+        (va, vb, vc): (&S::Varying, &S::Varying, &S::Varying),
+    ) where
+        S: ShaderProgram,
+        C: ColorTarget,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        T: RenderTarget<Pixel = u32>,
+    {
+        let (width, height) = framebuffer.dimensions();
+
+        let a2 = Vec2::new(a.x, a.y);
+        let b2 = Vec2::new(b.x, b.y);
+        let c2 = Vec2::new(c.x, c.y);
+
+        let (minx, miny, maxx, maxy) =
+            match self.scissored_bounding_box(a2, b2, c2, width, height, 0.0) {
+                Some(bounds) => bounds,
+                None => return,
+            };
+        let clip = coarse_depth.bounds();
+        let (minx, miny) = (minx.max(clip.0), miny.max(clip.1));
+        let (maxx, maxy) = (maxx.min(clip.2), maxy.min(clip.3));
+
+        let origin = Vec2::new((minx & !1) as f32, (miny & !1) as f32);
+        let edges = match EdgeFunctions::new(a2, b2, c2, origin) {
+            Some(edges) => edges,
+            None => return,
+        };
+
+        let z = Vec3::new(a.z, b.z, c.z);
+        let depth_offset = self.depth_offset(&edges, z);
+        let front_facing = signed_area(a2, b2, c2) > 0.0;
+        let bc_steps = edges.barycentric_steps();
+
+        // Without color or auxiliary attachments, fragments are only shaded
+        // for the alpha test, e.g. in depth pre-passes and shadow maps
+        let shade = !framebuffer.color.is_empty()
+            || framebuffer.aux.is_some()
+            || self.options.alpha_cutoff.is_some();
+
+        // Stencil operations also apply to fragments failing the depth test,
+        // so blocks can only be rejected without the stencil test. The
+        // nearest depth of the triangle is compared with the farthest stored
+        // depth, which can only reject comparisons passing for nearer depth
+        let depth_func = self.options.depth_func;
+        let coarse_func = match depth_func {
+            _ if self.options.stencil.is_some() => None,
+            CompareFunc::Less => Some(CompareFunc::Less),
+            CompareFunc::Equal | CompareFunc::LessEqual => Some(CompareFunc::LessEqual),
+            _ => None,
+        };
+        let convention = self.options.depth_convention;
+        let min_depth =
+            convention.ndc_to_depth(z.min_element()) + depth_offset - COARSE_DEPTH_MARGIN;
+
+        let mut row = edges.value;
+        for qy in (miny & !1..=maxy).step_by(2) {
+            let mut value = row;
+            row += edges.dy * 2.0;
+
+            for qx in (minx & !1..=maxx).step_by(2) {
+                let quad_value = value;
+                value += edges.dx * 2.0;
+
+                // The depth test fails for all pixels of the block
+                if let Some(coarse_func) = coarse_func {
+                    let max_depth = coarse_depth.max(&*framebuffer.depth, qx, qy);
+                    if !coarse_func.compare(min_depth, max_depth) {
+                        continue;
+                    }
+                }
+
+                let mut quad = Quad::new(&edges, quad_value, z, self.options.depth_convention);
+                quad.depth = quantize_depth::<D::Pixel>(quad.depth + Vec4::splat(depth_offset));
+
+                let mut coverage = quad.coverage;
+                for (i, &(ox, oy)) in QUAD_PIXELS.iter().enumerate() {
+                    let (x, y) = (qx + ox, qy + oy);
+                    if x < minx || y < miny || x > maxx || y > maxy {
+                        coverage &= !(1 << i);
+                    }
+                }
+                if coverage == 0 {
+                    continue;
+                }
+
+                // Pixels of a quad don't affect each other, so the depth
+                // test can run for all of them before any is written
+                let mut stored_depth = Vec4::ZERO;
+                let mut peel_depth = Vec4::ZERO;
+                for (i, &(ox, oy)) in QUAD_PIXELS.iter().enumerate() {
+                    if coverage & 1 << i != 0 {
+                        let (x, image_y) = (qx + ox, self.options.origin.row(qy + oy, height));
+                        stored_depth[i] = framebuffer.depth.pixel(x, image_y).to_depth();
+                        if let Some(image_peel) = framebuffer.peel_depth {
+                            peel_depth[i] = image_peel.pixel(x, image_y).to_depth();
+                        }
+                    }
+                }
+                let mut depth_pass_mask = quad.depth_test(stored_depth, depth_func);
+                if framebuffer.peel_depth.is_some() {
+                    depth_pass_mask &= quad.depth_test(peel_depth, CompareFunc::Greater);
+                }
+
+                let mut quad_derivatives = None;
+
+                for (i, &(ox, oy)) in QUAD_PIXELS.iter().enumerate() {
+                    if coverage & 1 << i == 0 {
+                        continue;
+                    }
+                    stats.fragments_tested += 1;
+
+                    let (x, y) = (qx + ox, qy + oy);
+                    let bc = quad.bc[i];
+
+                    let mut f_pos = Vec4::interpolate(&a, &b, &c, bc);
+                    f_pos.z = quad.depth[i];
+                    let f_depth = f_pos.z;
+
+                    let image_y = self.options.origin.row(y, height);
+
+                    if let (Some(state), Some(image_stencil)) =
+                        (&self.options.stencil, framebuffer.stencil.as_deref_mut())
+                    {
+                        let stored = image_stencil.pixel(x, image_y) as u8;
+                        if !state.test(stored) {
+                            let updated = state.update(stored, state.fail_op);
+                            image_stencil.set_pixel(x, image_y, u32::from(updated));
+                            continue;
+                        }
+                    }
+
+                    let depth_pass = depth_pass_mask & 1 << i != 0;
+                    if depth_pass {
+                        stats.fragments_passed += 1;
+                    }
+
+                    // Fragments failing the depth test are not shaded, so
+                    // only fragments passing it can be discarded by the alpha
+                    // test
+                    let f_outputs = if depth_pass && shade {
+                        let (dvar_dx, dvar_dy) = quad_derivatives.get_or_insert_with(|| {
+                            self.derivatives::<S>((a, b, c), (va, vb, vc), quad.bc[0], bc_steps)
+                        });
+
+                        let f_var = self.interpolate_varying::<S>((a, b, c), (va, vb, vc), bc);
+                        let f_outputs = shader.fragment_input(&FragmentInput {
+                            position: f_pos,
+                            varying: &f_var,
+                            dvar_dx,
+                            dvar_dy,
+                            pixel: (x, image_y),
+                            barycentric: bc,
+                            front_facing,
+                            primitive,
+                        });
+                        if !self.alpha_test(f_outputs.alpha()) {
+                            continue;
+                        }
+
+                        let mut f_outputs = f_outputs;
+                        if let Some(fog) = &self.options.fog {
+                            f_outputs.map_color(|color| fog.apply(color, 1.0 / f_pos.w));
+                        }
+
+                        Some(f_outputs)
+                    } else {
+                        None
+                    };
+
+                    if let (Some(state), Some(image_stencil)) =
+                        (&self.options.stencil, framebuffer.stencil.as_deref_mut())
+                    {
+                        let op = if depth_pass {
+                            state.pass_op
+                        } else {
+                            state.zfail_op
+                        };
+                        let stored = image_stencil.pixel(x, image_y) as u8;
+                        let updated = state.update(stored, op);
+                        image_stencil.set_pixel(x, image_y, u32::from(updated));
+                    }
+
+                    if depth_pass {
+                        if !self.options.depth_read_only {
+                            framebuffer
+                                .depth
+                                .set_pixel(x, image_y, D::Pixel::from_depth(f_depth));
+                            coarse_depth.lower(x, y, stored_depth[i]);
+                        }
+                        if let Some(image_ids) = framebuffer.ids.as_deref_mut() {
+                            image_ids.set_pixel(x, image_y, self.options.draw_id);
+                        }
+                        if let Some(image_ids) = framebuffer.primitive_ids.as_deref_mut() {
+                            image_ids.set_pixel(x, image_y, primitive);
+                        }
+                        stats.pixels_written += 1;
+                        if let Some(f_outputs) = f_outputs {
+                            if !framebuffer.color.is_empty() {
+                                f_outputs.write(
+                                    framebuffer.color,
+                                    x,
+                                    image_y,
+                                    &self.color_encoding(),
+                                );
+                            }
+                            if let (Some(image_aux), Some(aux)) =
+                                (framebuffer.aux.as_deref_mut(), f_outputs.aux())
+                            {
+                                image_aux.set_pixel(x, image_y, aux);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Counts the pixels of a triangle passing the depth test against
+    /// IMAGE_DEPTH without writing anything. Stops at the first quad with
+    /// passing pixels if ANY is set.
+    ///
+    /// The triangle is rasterized tile by tile like in `draw`, so that
+    /// exactly the same pixels are covered.
+    fn triangle_query<D>(&self, image_depth: &D, screen: (Vec4, Vec4, Vec4), any: bool) -> u64
+    where
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (width, height) = image_depth.dimensions();
+        let (a, b, c) = screen;
+
+        let a2 = Vec2::new(a.x, a.y);
+        let b2 = Vec2::new(b.x, b.y);
+        let c2 = Vec2::new(c.x, c.y);
+
+        let (minx, miny, maxx, maxy) =
+            match self.scissored_bounding_box(a2, b2, c2, width, height, 0.0) {
+                Some(bounds) => bounds,
+                None => return 0,
+            };
+
+        let mut count = 0;
+        for ty in miny / TILE_SIZE..=maxy / TILE_SIZE {
+            for tx in minx / TILE_SIZE..=maxx / TILE_SIZE {
+                let clip = tile_bounds(tx, ty, width, height);
+                count += self.tile_query(image_depth, clip, screen, any);
+                if any && count > 0 {
+                    return count;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Counts the pixels of a triangle inside CLIP passing the depth test,
+    /// see `triangle_query`.
+    fn tile_query<D>(
+        &self,
+        image_depth: &D,
+        clip: (u32, u32, u32, u32),
+        (a, b, c): (Vec4, Vec4, Vec4),
+        any: bool,
+    ) -> u64
+    where
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (width, height) = image_depth.dimensions();
+
+        let a2 = Vec2::new(a.x, a.y);
+        let b2 = Vec2::new(b.x, b.y);
+        let c2 = Vec2::new(c.x, c.y);
+
+        let (minx, miny, maxx, maxy) =
+            match self.scissored_bounding_box(a2, b2, c2, width, height, 0.0) {
+                Some(bounds) => bounds,
+                None => return 0,
+            };
+        let (minx, miny) = (minx.max(clip.0), miny.max(clip.1));
+        let (maxx, maxy) = (maxx.min(clip.2), maxy.min(clip.3));
+
+        let origin = Vec2::new((minx & !1) as f32, (miny & !1) as f32);
+        let edges = match EdgeFunctions::new(a2, b2, c2, origin) {
+            Some(edges) => edges,
+            None => return 0,
+        };
+
+        let z = Vec3::new(a.z, b.z, c.z);
+        let depth_offset = self.depth_offset(&edges, z);
+
+        let mut count = 0;
+        let mut row = edges.value;
+        for qy in (miny & !1..=maxy).step_by(2) {
+            let mut value = row;
+            row += edges.dy * 2.0;
+
+            for qx in (minx & !1..=maxx).step_by(2) {
+                let mut quad = Quad::new(&edges, value, z, self.options.depth_convention);
+                quad.depth = quantize_depth::<D::Pixel>(quad.depth + Vec4::splat(depth_offset));
+                value += edges.dx * 2.0;
+
+                let mut stored_depth = Vec4::ZERO;
+                let mut coverage = quad.coverage;
+                for (i, &(ox, oy)) in QUAD_PIXELS.iter().enumerate() {
+                    let (x, y) = (qx + ox, qy + oy);
+                    if x < minx || y < miny || x > maxx || y > maxy {
+                        coverage &= !(1 << i);
+                    } else if coverage & 1 << i != 0 {
+                        let image_y = self.options.origin.row(y, height);
+                        stored_depth[i] = image_depth.pixel(x, image_y).to_depth();
+                    }
+                }
+
+                let depth_pass_mask = quad.depth_test(stored_depth, self.options.depth_func);
+                count += u64::from((coverage & depth_pass_mask).count_ones());
+                if any && count > 0 {
+                    return count;
+                }
+            }
+        }
+
+        count
+    }
+
+    /// Computes the derivatives of the varyings along screen space X and Y
+    /// for the 2x2 quad whose lower left pixel has the barycentric
+    /// coordinates BC, as differences between its pixels. BC_STEPS are the
+    /// changes of the barycentric coordinates one pixel right and up, see
+    /// `EdgeFunctions::barycentric_steps`.
+    ///
+    /// Interpolation is linear in the barycentric coordinates, so the
+    /// difference of two interpolated values equals the interpolation at the
+    /// difference of their barycentric coordinates. Only the perspective
+    /// correction depends on the quad.
+    fn derivatives<S: ShaderProgram>(
+        &self,
+        (a, b, c): (Vec4, Vec4, Vec4),
+        (va, vb, vc): (&S::Varying, &S::Varying, &S::Varying),
+        bc: Vec3,
+        (bc_dx, bc_dy): (Vec3, Vec3),
+    ) -> (S::Varying, S::Varying) {
+        if self.options.perspective_correct {
+            let bc_persp = perspective_barycentric(bc, a.w, b.w, c.w);
+            let bc_persp_x = perspective_barycentric(bc + bc_dx, a.w, b.w, c.w);
+            let bc_persp_y = perspective_barycentric(bc + bc_dy, a.w, b.w, c.w);
+
+            (
+                S::Varying::interpolate_perspective(va, vb, vc, bc_dx, bc_persp_x - bc_persp),
+                S::Varying::interpolate_perspective(va, vb, vc, bc_dy, bc_persp_y - bc_persp),
+            )
+        } else {
+            (
+                S::Varying::interpolate(va, vb, vc, bc_dx),
+                S::Varying::interpolate(va, vb, vc, bc_dy),
+            )
+        }
+    }
+
+    /// Writes a triangle to multisampled color and depth images.
+    #[allow(clippy::too_many_arguments)]
+    fn triangle_multisample<S: ShaderProgram<Fragment = Vec4>>(
+        &self,
+        shader: &S,
+        image_color: &mut MultisampleImage,
+        image_depth: &mut MultisampleImage,
+        stats: &mut PipelineStats,
+        primitive: u32,
+        (a, b, c): (Vec4, Vec4, Vec4),
+        (va, vb, vc): (&S::Varying, &S::Varying, &S::Varying),
+    ) {
+        let (width, height) = image_depth.dimensions();
+
+        let a2 = Vec2::new(a.x, a.y);
+        let b2 = Vec2::new(b.x, b.y);
+        let c2 = Vec2::new(c.x, c.y);
+
+        let (minx, miny, maxx, maxy) =
+            match self.scissored_bounding_box(a2, b2, c2, width, height, SAMPLE_MARGIN) {
+                Some(bounds) => bounds,
+                None => return,
+            };
+
+        // Setup rejects degenerate triangles, which have no edge functions
+        let edges = match EdgeFunctions::new(a2, b2, c2, Vec2::ZERO) {
+            Some(edges) => edges,
+            None => return,
+        };
+        let depth_offset = self.depth_offset(&edges, Vec3::new(a.z, b.z, c.z));
+        let bc_steps = edges.barycentric_steps();
+        let convention = self.options.depth_convention;
+        let front_facing = signed_area(a2, b2, c2) > 0.0;
+
+        for x in minx..=maxx {
+            for y in miny..=maxy {
+                let point = Vec2::new(x as f32, y as f32);
+                let image_y = self.options.origin.row(y, height);
+
+                let mut covered = false;
+                let mut f_bc = None;
+                let mut passed = [false; MultisampleImage::SAMPLES];
+                let mut sample_depths = [0.0; MultisampleImage::SAMPLES];
+
+                for (s, offset) in MULTISAMPLE_OFFSETS.iter().enumerate() {
+                    let sample_point = point + Vec2::new(offset[0], offset[1]);
+                    let bc = match barycentric(a2, b2, c2, sample_point) {
+                        Some(bc) if bc.x >= 0.0 && bc.y >= 0.0 && bc.z >= 0.0 => bc,
+                        _ => continue,
+                    };
+
+                    // Compute sample depth and remap it from NDC to [0..1].
+                    // Samples outside of the depth range are not covered,
+                    // like in `Quad::new`
+                    let s_depth = convention.ndc_to_depth(f32::interpolate(&a.z, &b.z, &c.z, bc));
+                    if !(0.0..=1.0).contains(&s_depth) {
+                        continue;
+                    }
+                    covered = true;
+                    let s_depth = s_depth + depth_offset;
+
+                    let stored = image_depth.sample_depth(x, image_y, s);
+                    if self.options.depth_func.compare(s_depth, stored) {
+                        passed[s] = true;
+                        sample_depths[s] = s_depth;
+                        f_bc.get_or_insert(bc);
+                    }
+                }
+
+                if covered {
+                    stats.fragments_tested += 1;
+                }
+                let f_bc = match f_bc {
+                    Some(f_bc) => f_bc,
+                    None => continue,
+                };
+                stats.fragments_passed += 1;
+
+                // Shade at the pixel position if it is covered, otherwise at
+                // the first passing sample
+                let bc = match barycentric(a2, b2, c2, point) {
+                    Some(bc) if bc.x >= 0.0 && bc.y >= 0.0 && bc.z >= 0.0 => bc,
+                    _ => f_bc,
+                };
+
+                let mut f_pos = Vec4::interpolate(&a, &b, &c, bc);
+                f_pos.z = convention.ndc_to_depth(f_pos.z);
+
+                // Pixels are shaded one at a time, but derivatives are still
+                // those of the aligned quad the pixel belongs to
+                let quad_bc = edges.barycentric_at(x & !1, y & !1);
+                let (dvar_dx, dvar_dy) =
+                    self.derivatives::<S>((a, b, c), (va, vb, vc), quad_bc, bc_steps);
+
+                let f_var = self.interpolate_varying::<S>((a, b, c), (va, vb, vc), bc);
+                let f_color = shader.fragment_input(&FragmentInput {
+                    position: f_pos,
+                    varying: &f_var,
+                    dvar_dx: &dvar_dx,
+                    dvar_dy: &dvar_dy,
+                    pixel: (x, image_y),
+                    barycentric: bc,
+                    front_facing,
+                    primitive,
+                });
+                if !self.alpha_test(f_color.w) {
+                    continue;
+                }
+                let f_color = match &self.options.fog {
+                    Some(fog) => fog.apply(f_color, 1.0 / f_pos.w),
+                    None => f_color,
+                };
+
+                let encoding = self.color_encoding();
+                let f_rgba = encoding.encode(f_color, x, image_y);
+                stats.pixels_written += 1;
+
+                for s in 0..MultisampleImage::SAMPLES {
+                    if passed[s] {
+                        // Each sample blends with its own stored color
+                        let rgba = match encoding.blend {
+                            Some(blend) => {
+                                let stored = image_color.sample_rgba(x, image_y, s);
+                                let stored = encoding.decode(rgba_to_vec(stored));
+                                encoding.encode(blend.apply(f_color, stored), x, image_y)
+                            }
+                            None => f_rgba,
+                        };
+                        if !self.options.depth_read_only {
+                            image_depth.set_sample_depth(x, image_y, s, sample_depths[s]);
+                        }
+                        image_color.set_sample_rgba(x, image_y, s, rgba);
+                    }
+                }
+            }
+        }
+    }
+
+    fn color_encoding(&self) -> ColorEncoding {
+        ColorEncoding {
+            srgb: self.options.srgb_framebuffer,
+            dither: self.options.dither,
+            blend: self.options.blend,
+        }
+    }
+
+    /// Returns the screen space X, Y, width and height of the viewport in
+    /// attachments WIDTH x HEIGHT pixels large. It can extend past their
+    /// edges.
+    fn screen_viewport(&self, width: u32, height: u32) -> (i64, i64, i64, i64) {
+        match self.options.viewport {
+            Some(viewport) => {
+                let (x, y) = (i64::from(viewport.x), i64::from(viewport.y));
+                let (w, h) = (i64::from(viewport.width), i64::from(viewport.height));
+                let y = match self.options.origin {
+                    Origin::TopLeft => i64::from(height) - y - h,
+                    Origin::BottomLeft => y,
+                };
+                (x, y, w, h)
+            }
+            None => (0, 0, i64::from(width), i64::from(height)),
+        }
+    }
+
+    /// Like `bounding_box`, but also clipped to the viewport.
+    fn scissored_bounding_box(
+        &self,
+        a: Vec2,
+        b: Vec2,
+        c: Vec2,
+        width: u32,
+        height: u32,
+        margin: f32,
+    ) -> Option<(u32, u32, u32, u32)> {
+        let (minx, miny, maxx, maxy) = bounding_box(a, b, c, width, height, margin)?;
+        if self.options.viewport.is_none() {
+            return Some((minx, miny, maxx, maxy));
+        }
+
+        let (x, y, w, h) = self.screen_viewport(width, height);
+        let minx = i64::from(minx).max(x);
+        let miny = i64::from(miny).max(y);
+        let maxx = i64::from(maxx).min(x + w - 1);
+        let maxy = i64::from(maxy).min(y + h - 1);
+        if minx > maxx || miny > maxy {
+            return None;
+        }
+
+        Some((minx as u32, miny as u32, maxx as u32, maxy as u32))
+    }
+
+    /// Returns whether a fragment with ALPHA passes the alpha test.
+    fn alpha_test(&self, alpha: f32) -> bool {
+        match self.options.alpha_cutoff {
+            Some(cutoff) => alpha >= cutoff,
+            None => true,
+        }
+    }
+
+    /// Returns the depth bias of the triangle with EDGES and the NDC depth Z
+    /// of its vertices, see `PipelineOptions::depth_bias`.
+    fn depth_offset(&self, edges: &EdgeFunctions, z: Vec3) -> f32 {
+        let slope = self.options.depth_bias_slope;
+        if slope == 0.0 {
+            return self.options.depth_bias;
+        }
+
+        // Stored depth is linear in the barycentric coordinates, whose
+        // changes per pixel are those of the edge functions over the area
+        let (scale, _) = self.options.depth_convention.remap();
+        let dz_dx = z.dot(edges.dx) * edges.inv_area * scale;
+        let dz_dy = z.dot(edges.dy) * edges.inv_area * scale;
+
+        self.options.depth_bias + slope * f32::max(dz_dx.abs(), dz_dy.abs())
+    }
+
+    /// Interpolates the varyings at barycentric coordinates BC, correcting
+    /// for perspective if enabled.
+    fn interpolate_varying<S: ShaderProgram>(
+        &self,
+        (a, b, c): (Vec4, Vec4, Vec4),
+        (va, vb, vc): (&S::Varying, &S::Varying, &S::Varying),
+        bc: Vec3,
+    ) -> S::Varying {
+        if self.options.perspective_correct {
+            let bc_persp = perspective_barycentric(bc, a.w, b.w, c.w);
+            S::Varying::interpolate_perspective(va, vb, vc, bc, bc_persp)
+        } else {
+            S::Varying::interpolate(va, vb, vc, bc)
+        }
+    }
+}
+
+/// Renders at an integer multiple of the target resolution and box-filters the
+/// result down. The scaled buffers are owned by the `Supersample` and reused
+/// between draws.
+pub struct Supersample {
+    scale: u32,
+    image_color: Image,
+    image_depth: Image,
+}
+
+impl Supersample {
+    pub fn new(scale: u32) -> Supersample {
+        assert!(scale > 0, "scale must be positive");
+
+        Supersample {
+            scale,
+            image_color: Image::new(0, 0),
+            image_depth: Image::new(0, 0),
+        }
+    }
+
+    pub fn scale(&self) -> u32 {
+        self.scale
+    }
+
+    /// Like `Pipeline::triangles`, but rasterizes at `scale` times the
+    /// resolution of the images.
+    ///
+    /// The current contents of the images are upscaled before drawing, so
+    /// draws compose as usual. Afterwards, color is averaged in linear space,
+    /// decoding and encoding it like `PipelineOptions::srgb_framebuffer`
+    /// says, and depth keeps the nearest value of each block, or the
+    /// farthest for the `Greater` depth functions. Both scaled dimensions
+    /// are exact multiples of the target, so no row or column is dropped.
+    pub fn triangles<S: ShaderProgram<Fragment = Vec4>>(
+        &mut self,
+        pipeline: &Pipeline,
         shader: &S,
         buffer: &[S::Attribute],
         image_color: &mut Image,
         image_depth: &mut Image,
-    ) {
-        let width = image_color.width();
-        let height = image_color.height();
-
-        assert!(width == image_depth.width(), "images must have equal dims");
+    ) -> PipelineStats {
+        let (width, height) = image_depth.dimensions();
         assert!(
-            height == image_depth.height(),
+            image_color.dimensions() == (width, height),
             "images must have equal dims"
         );
 
-        let half_width = width as f32 / 2.0;
-        let half_height = height as f32 / 2.0;
-
-        for i in 0..buffer.len() / 3 {
-            let attr = i * 3;
-
-            let mut var_a = S::Varying::default();
-            let mut var_b = S::Varying::default();
-            let mut var_c = S::Varying::default();
+        let scale = |size: u32| {
+            size.checked_mul(self.scale)
+                .expect("scaled image dimensions overflow u32")
+        };
+        let scaled = (scale(width), scale(height));
+        if self.image_color.dimensions() != scaled {
+            self.image_color = Image::new(scaled.0, scaled.1);
+            self.image_depth = Image::new(scaled.0, scaled.1);
+        }
 
-            let world_a = shader.vertex(&buffer[attr], &mut var_a);
-            let world_b = shader.vertex(&buffer[attr + 1], &mut var_b);
-            let world_c = shader.vertex(&buffer[attr + 2], &mut var_c);
+        for y in 0..scaled.1 {
+            for x in 0..scaled.0 {
+                let (tx, ty) = (x / self.scale, y / self.scale);
+                let color = image_color.pixel_u32(tx, ty);
+                let depth = image_depth.pixel_u32(tx, ty);
+                self.image_color.set_pixel_u32(x, y, color);
+                self.image_depth.set_pixel_u32(x, y, depth);
+            }
+        }
 
-            if self.options.cull_face != CullFace::None {
-                let normal = face_normal(
-                    Vec3::new(world_a.x, world_a.y, world_a.z),
-                    Vec3::new(world_b.x, world_b.y, world_b.z),
-                    Vec3::new(world_c.x, world_c.y, world_c.z),
-                );
+        let stats =
+            pipeline.triangles(shader, buffer, &mut self.image_color, &mut self.image_depth);
 
-                let do_cull = match self.options.cull_face {
-                    CullFace::FrontAndBack => true,
-                    CullFace::Front => normal.z > 0.0,
-                    CullFace::Back => normal.z < 0.0,
-                    CullFace::None => unreachable!(),
+        // Depth keeps the value of each block that wins the depth test
+        let keep_far = matches!(
+            pipeline.options.depth_func,
+            CompareFunc::Greater | CompareFunc::GreaterEqual
+        );
+        let encoding = pipeline.color_encoding();
+        let block_size = (self.scale * self.scale) as f32;
+        for ty in 0..height {
+            for tx in 0..width {
+                let mut color = Vec4::ZERO;
+                let mut depth = if keep_far {
+                    f32::NEG_INFINITY
+                } else {
+                    f32::INFINITY
                 };
 
-                if do_cull {
-                    continue;
+                for y in ty * self.scale..(ty + 1) * self.scale {
+                    for x in tx * self.scale..(tx + 1) * self.scale {
+                        color += encoding.decode(rgba_to_vec(self.image_color.pixel_rgba(x, y)));
+                        let sample = self.image_depth.pixel_depth(x, y);
+                        depth = if keep_far {
+                            depth.max(sample)
+                        } else {
+                            depth.min(sample)
+                        };
+                    }
                 }
+
+                let color = encoding.encode(color / block_size, tx, ty);
+                image_color.set_pixel_rgba(tx, ty, color);
+                image_depth.set_pixel_depth(tx, ty, depth);
             }
+        }
 
-            // TODO: clipping
-            // TODO: viewport transform
+        stats
+    }
+}
 
-            let screen_a = world_to_screen(from_homogenous(world_a), half_width, half_height);
-            let screen_b = world_to_screen(from_homogenous(world_b), half_width, half_height);
-            let screen_c = world_to_screen(from_homogenous(world_c), half_width, half_height);
+/// Result of clipping a triangle against `PipelineOptions::clip_planes`.
+enum Clip {
+    Inside,
+    Outside,
+    /// Vertices of the convex polygon remaining of a triangle crossing some
+    /// planes, with their clip space positions and barycentric coordinates
+    /// in the triangle.
+    Polygon(Vec<(Vec4, Vec3)>),
+}
 
-            self.triangle(
-                shader,
-                image_color,
-                image_depth,
-                (screen_a, screen_b, screen_c),
-                (&var_a, &var_b, &var_c),
-            );
-        }
-    }
+/// Margin subtracted from the nearest vertex depth of a triangle before
+/// comparing it to `CoarseDepth`, covering rounding of the depth interpolated
+/// at its pixels, so that rejection stays conservative.
+const COARSE_DEPTH_MARGIN: f32 = 1e-3;
 
-    /// Writes a triangle to image and z_buffer.
-    fn triangle<S: ShaderProgram>(
-        &self,
-        shader: &S,
-        image_color: &mut Image,
-        image_depth: &mut Image,
-        (a, b, c): (Vec4, Vec4, Vec4),
-        (va, vb, vc): (&S::Varying, &S::Varying, &S::Varying),
-    ) {
-        let width = image_color.width();
-        let height = image_color.height();
+/// Sample positions relative to the pixel position, in a rotated grid.
+const MULTISAMPLE_OFFSETS: [[f32; 2]; MultisampleImage::SAMPLES] = [
+    [-2.0 / 16.0, -6.0 / 16.0],
+    [6.0 / 16.0, -2.0 / 16.0],
+    [-6.0 / 16.0, 2.0 / 16.0],
+    [2.0 / 16.0, 6.0 / 16.0],
+];
 
-        let a2 = Vec2::new(a.x, a.y);
-        let b2 = Vec2::new(b.x, b.y);
-        let c2 = Vec2::new(c.x, c.y);
+/// Rounds the DEPTH of the pixels of a quad to the precision of P, see
+/// `DepthPixel`.
+fn quantize_depth<P: DepthPixel>(depth: Vec4) -> Vec4 {
+    let quantize = |depth| P::from_depth(depth).to_depth();
+    Vec4::new(
+        quantize(depth.x),
+        quantize(depth.y),
+        quantize(depth.z),
+        quantize(depth.w),
+    )
+}
 
-        let (minx, miny, maxx, maxy) = bounding_box(a2, b2, c2, width, height);
+/// Distance from the pixel position beyond which there are no samples, see
+/// `MULTISAMPLE_OFFSETS`.
+const SAMPLE_MARGIN: f32 = 0.5;
 
-        for x in minx..=maxx {
-            for y in miny..=maxy {
-                let point = Vec2::new(x as f32, y as f32);
-                if let Some(bc) = barycentric(a2, b2, c2, point) {
-                    if bc.x < 0.0 || bc.y < 0.0 || bc.z < 0.0 {
-                        continue;
-                    }
+/// Computes the doubled signed area of the screen space triangle A, B, C,
+/// positive if its vertices are counter-clockwise.
+fn signed_area(a: Vec2, b: Vec2, c: Vec2) -> f32 {
+    let ab = b - a;
+    let ac = c - a;
+    ab.x * ac.y - ab.y * ac.x
+}
 
-                    // Compute frag depth and remap it from NDC to [0..1]
-                    let mut f_pos = Vec4::interpolate(&a, &b, &c, bc);
-                    f_pos.z = f_pos.z / 2.0 + 0.5;
-                    let f_depth = f_pos.z;
+/// Compute a bounding box (in screenspace coords) for triangle A, B, C,
+/// grown by MARGIN, as the inclusive min and max corners of the pixels of a
+/// WIDTH x HEIGHT framebuffer it may cover. Returns None if it covers none.
+fn bounding_box(
+    a: Vec2,
+    b: Vec2,
+    c: Vec2,
+    width: u32,
+    height: u32,
+    margin: f32,
+) -> Option<(u32, u32, u32, u32)> {
+    if width == 0 || height == 0 {
+        return None;
+    }
 
-                    // GL_LESS
-                    let flipped_y = height - 1 - y;
-                    if f_depth < image_depth.pixel_depth(x, flipped_y) {
-                        let f_var = S::Varying::interpolate(va, vb, vc, bc);
-                        let f_color = shader.fragment(f_pos, &f_var);
+    // Pixels are sampled at integer coordinates, so flooring the min corner
+    // is conservative. Reject in floats before converting, as negative
+    // coordinates would saturate to 0
+    let xmin = (f32::min(f32::min(a.x, b.x), c.x) - margin).floor();
+    let xmax = (f32::max(f32::max(a.x, b.x), c.x) + margin).floor();
+    let ymin = (f32::min(f32::min(a.y, b.y), c.y) - margin).floor();
+    let ymax = (f32::max(f32::max(a.y, b.y), c.y) + margin).floor();
 
-                        image_depth.set_pixel_depth(x, flipped_y, f_depth);
-                        image_color.set_pixel_rgba(x, flipped_y, vec_to_rgba(f_color));
-                    }
-                }
-            }
-        }
+    let (right, top) = ((width - 1) as f32, (height - 1) as f32);
+    if xmin > xmax || ymin > ymax || xmax < 0.0 || ymax < 0.0 || xmin > right || ymin > top {
+        return None;
     }
+
+    Some((
+        xmin.max(0.0) as u32,
+        ymin.max(0.0) as u32,
+        xmax.min(right) as u32,
+        ymax.min(top) as u32,
+    ))
 }
 
-/// Compute a normal vector for the face A, B, C
-fn face_normal(a: Vec3, b: Vec3, c: Vec3) -> Vec3 {
-    let ab = b - a;
-    let ac = c - a;
-    ab.cross(ac)
-}
-
-/// Compute a bounding box (in screenspace coords) for triangle A, B, C.
-fn bounding_box(a: Vec2, b: Vec2, c: Vec2, width: u32, height: u32) -> (u32, u32, u32, u32) {
-    let xmin = f32::min(f32::min(a.x, b.x), c.x);
-    let xmax = f32::max(f32::max(a.x, b.x), c.x);
-    let ymin = f32::min(f32::min(a.y, b.y), c.y);
-    let ymax = f32::max(f32::max(a.y, b.y), c.y);
-    (
-        xmin as u32,
-        ymin as u32,
-        (xmax as u32).min(width.saturating_sub(1)),
-        (ymax as u32).min(height.saturating_sub(1)),
-    )
+/// Edge functions of a screen space triangle, evaluated incrementally while
+/// stepping across pixels.
+///
+/// Each edge function is the doubled signed area of the triangle formed by a
+/// point and the edge opposite to one of the vertices, so dividing them by
+/// the doubled signed area of the whole triangle gives the barycentric
+/// coordinates of the point, matching `barycentric`. They are linear in the
+/// point, so they change by constant deltas per pixel.
+struct EdgeFunctions {
+    /// Edge functions opposite to A, B and C at the starting point.
+    value: Vec3,
+    /// Change of the edge functions one pixel right.
+    dx: Vec3,
+    /// Change of the edge functions one pixel up.
+    dy: Vec3,
+    /// Reciprocal of the doubled signed area of the triangle.
+    inv_area: f32,
+    /// Offsets of the edge functions opposite to A, B and C of the pixels
+    /// of a 2x2 quad from its lower left pixel, see `QUAD_PIXELS`.
+    quad_offsets: [Vec4; 3],
+}
+
+impl EdgeFunctions {
+    /// Sets up the edge functions of triangle A, B, C at point P. Returns
+    /// None for degenerate triangles, like `barycentric`.
+    fn new(a: Vec2, b: Vec2, c: Vec2, p: Vec2) -> Option<EdgeFunctions> {
+        let ab = b - a;
+        let ac = c - a;
+        let pa = a - p;
+
+        let area = ac.x * ab.y - ab.x * ac.y;
+        if f32::abs(area) < 1.0 {
+            return None;
+        }
+
+        let edge_b = pa.x * ac.y - ac.x * pa.y;
+        let edge_c = ab.x * pa.y - pa.x * ab.y;
+
+        let dx = Vec3::new(ac.y - ab.y, -ac.y, ab.y);
+        let dy = Vec3::new(ab.x - ac.x, ac.x, -ab.x);
+        let dxy = dx + dy;
+
+        Some(EdgeFunctions {
+            value: Vec3::new(area - edge_b - edge_c, edge_b, edge_c),
+            dx,
+            dy,
+            inv_area: 1.0 / area,
+            quad_offsets: [
+                Vec4::new(0.0, dx.x, dy.x, dxy.x),
+                Vec4::new(0.0, dx.y, dy.y, dxy.y),
+                Vec4::new(0.0, dx.z, dy.z, dxy.z),
+            ],
+        })
+    }
+
+    /// Returns the barycentric coordinates of the pixel X, Y pixels right of
+    /// and above the starting point.
+    fn barycentric_at(&self, x: u32, y: u32) -> Vec3 {
+        (self.value + self.dx * x as f32 + self.dy * y as f32) * self.inv_area
+    }
+
+    /// Returns the changes of the barycentric coordinates one pixel right
+    /// and one pixel up, the same for every pixel of the triangle.
+    fn barycentric_steps(&self) -> (Vec3, Vec3) {
+        (self.dx * self.inv_area, self.dy * self.inv_area)
+    }
 }
 
 /// Computes barycentric coordinates of point P in triangle A, B, C. Returns
@@ -189,20 +2175,18 @@ fn barycentric(a: Vec2, b: Vec2, c: Vec2, p: Vec2) -> Option<Vec3> {
     }
 }
 
-fn vec_to_rgba(color: Vec4) -> [u8; 4] {
-    [
-        (color.x.clamp(0.0, 1.0) * 255.0) as u8,
-        (color.y.clamp(0.0, 1.0) * 255.0) as u8,
-        (color.z.clamp(0.0, 1.0) * 255.0) as u8,
-        (color.w.clamp(0.0, 1.0) * 255.0) as u8,
-    ]
+/// Corrects screen-space barycentric coordinates BC for perspective, given the
+/// reciprocal clip-space W of each vertex.
+fn perspective_barycentric(bc: Vec3, inv_wa: f32, inv_wb: f32, inv_wc: f32) -> Vec3 {
+    let weighted = Vec3::new(bc.x * inv_wa, bc.y * inv_wb, bc.z * inv_wc);
+    weighted / (weighted.x + weighted.y + weighted.z)
 }
 
 fn world_to_screen(world_coords: Vec4, half_width: f32, half_height: f32) -> Vec4 {
     Vec4::new(
         (world_coords.x + 1.0) * half_width,
         (world_coords.y + 1.0) * half_height,
-        world_coords.z.clamp(-1.0, 1.0),
+        world_coords.z,
         world_coords.w,
     )
 }
@@ -211,78 +2195,997 @@ fn from_homogenous(vec: Vec4) -> Vec4 {
     Vec4::new(vec.x / vec.w, vec.y / vec.w, vec.z / vec.w, 1.0 / vec.w)
 }
 
-// pub fn lines(
-//     &self,
-//     buffer: &[S::Attribute],
-//     image_color: &mut RgbaImage<u8>,
-// ) {
-//     let width = image_color.width();
-//     let height = image_color.height();
-//     let half_width = f64::from(width / 2);
-//     let half_height = f64::from(height / 2);
-
-//     let mut va = S::Varying::default();
-//     let mut vb = S::Varying::default();
-
-//     for i in 0..buffer.len() / 2 {
-//         let attr = i * 2;
-
-//         let world_a = self.shader.vertex(&buffer[attr], &mut va);
-//         let world_b = self.shader.vertex(&buffer[attr + 1], &mut vb);
-
-//         let screen_a = world_to_screen(world_a, half_width, half_height);
-//         let screen_b = world_to_screen(world_b, half_width, half_height);
-
-//         line(
-//             image_color,
-//             // TODO: interpolate color
-//             Rgba { data: [255; 4] },
-//             screen_a.x as i32,
-//             screen_a.y as i32,
-//             screen_b.x as i32,
-//             screen_b.y as i32,
-//         );
-//     }
-// }
-
-// /// Draw a line from src to dst using Bresenham's Algorithm
-// fn line(
-//     image: &mut RgbaImage<u8>,
-//     color: Rgba<u8>,
-//     mut src_x: i32,
-//     mut src_y: i32,
-//     mut dst_x: i32,
-//     mut dst_y: i32,
-// ) {
-//     let transposed = (dst_x - src_x).abs() < (dst_y - src_y).abs();
-//     if transposed {
-//         mem::swap(&mut src_x, &mut src_y);
-//         mem::swap(&mut dst_x, &mut dst_y);
-//     }
-
-//     if src_x > dst_x {
-//         mem::swap(&mut src_x, &mut dst_x);
-//         mem::swap(&mut src_y, &mut dst_y);
-//     }
-
-//     let dx = dst_x - src_x;
-//     let dy = dst_y - src_y;
-
-//     let derror2 = i32::abs(dy) * 2;
-//     let mut error2 = 0;
-
-//     let mut y = src_y;
-//     for x in src_x..=dst_x {
-//         if transposed {
-//             image.set_pixel(y as u32, x as u32, color);
-//         } else {
-//             image.set_pixel(x as u32, y as u32, color);
-//         }
-
-//         error2 += derror2;
-//         if error2 > dx {
-//             y += if src_y > dst_y { -1 } else { 1 };
-//             error2 -= dx * 2;
-//         }
-//     }
-// }
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::image::ImageF32;
+    use crate::shaders::{UnlitColor, UnlitColorAttribute};
+
+    const RED: Vec4 = glam::const_vec4!([1.0, 0.0, 0.0, 1.0]);
+
+    /// Returns two counter-clockwise triangles covering the NDC rectangle
+    /// from MIN to MAX at NDC depth Z.
+    fn rect(min: Vec2, max: Vec2, z: f32, color: Vec4) -> Vec<UnlitColorAttribute> {
+        let positions = [
+            Vec3::new(min.x, min.y, z),
+            Vec3::new(max.x, min.y, z),
+            Vec3::new(max.x, max.y, z),
+            Vec3::new(min.x, min.y, z),
+            Vec3::new(max.x, max.y, z),
+            Vec3::new(min.x, max.y, z),
+        ];
+        UnlitColorAttribute::solid(&positions, color)
+    }
+
+    /// Returns a rectangle covering every pixel once. Edges are inclusive,
+    /// so its diagonal must not pass through any pixel position, or the
+    /// pixels on it would be drawn by both triangles.
+    fn full_screen(z: f32, color: Vec4) -> Vec<UnlitColorAttribute> {
+        rect(Vec2::new(-1.1, -1.2), Vec2::splat(1.1), z, color)
+    }
+
+    #[test]
+    fn stencil_masks_second_pass() {
+        let mut image_color = Image::new(8, 8);
+        let mut image_depth = Image::from_pixel_depth(8, 8, 1.0);
+        let mut image_stencil = Image::from_pixel_stencil(8, 8, 0);
+        let shader = UnlitColor::identity();
+
+        // Write the lower left quarter of the screen to the stencil only
+        let mask = rect(Vec2::new(-1.0, -1.0), Vec2::ZERO, 0.0, RED);
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            stencil: Some(StencilState {
+                reference: 1,
+                pass_op: StencilOp::Replace,
+                ..StencilState::default()
+            }),
+            depth_read_only: true,
+            ..PipelineOptions::default()
+        });
+        let images_color: &mut [&mut Image] = &mut [];
+        let mut framebuffer =
+            Framebuffer::new(images_color, &mut image_depth).with_stencil(&mut image_stencil);
+        pipeline.draw(&shader, &mask, &mut framebuffer);
+
+        let screen = rect(Vec2::splat(-1.0), Vec2::ONE, 0.0, RED);
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            stencil: Some(StencilState {
+                func: CompareFunc::Equal,
+                reference: 1,
+                ..StencilState::default()
+            }),
+            ..PipelineOptions::default()
+        });
+        let mut images_color = [&mut image_color];
+        let mut framebuffer =
+            Framebuffer::new(&mut images_color, &mut image_depth).with_stencil(&mut image_stencil);
+        pipeline.draw(&shader, &screen, &mut framebuffer);
+
+        let mut masked = 0;
+        for (x, y, rgba) in image_color.enumerate_pixels_rgba() {
+            let in_mask = image_stencil.pixel_stencil(x, y) == 1;
+            let expected = if in_mask { [255, 0, 0, 255] } else { [0; 4] };
+            assert_eq!(rgba, expected, "pixel ({}, {})", x, y);
+            masked += u32::from(in_mask);
+        }
+        assert!(masked > 0 && masked < 8 * 8);
+        assert_eq!(image_stencil.pixel_stencil(0, 7), 1);
+        assert_eq!(image_stencil.pixel_stencil(7, 0), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "scaled image dimensions overflow u32")]
+    fn supersample_overflow() {
+        // Empty, so that nothing is allocated
+        let mut image_color = Image::new(1 << 31, 0);
+        let mut image_depth = Image::new(1 << 31, 0);
+        Supersample::new(2).triangles(
+            &Pipeline::with_options(PipelineOptions::default()),
+            &UnlitColor::identity(),
+            &[],
+            &mut image_color,
+            &mut image_depth,
+        );
+    }
+
+    #[test]
+    fn alpha_cutoff_skips_depth_and_blend() {
+        let mut image_color = Image::from_pixel_rgba(4, 4, [0, 0, 255, 255]);
+        let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+        let shader = UnlitColor::identity();
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            alpha_cutoff: Some(0.5),
+            blend: Some(BlendMode::Alpha),
+            ..PipelineOptions::default()
+        });
+
+        // Rejected fragments neither blend nor occlude
+        let buffer = full_screen(-0.5, Vec4::new(1.0, 0.0, 0.0, 0.25));
+        let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+        assert_eq!(stats.fragments_passed, 16);
+        assert_eq!(stats.pixels_written, 0);
+        for (x, y, rgba) in image_color.enumerate_pixels_rgba() {
+            assert_eq!(rgba, [0, 0, 255, 255]);
+            assert_eq!(image_depth.pixel_depth(x, y), 1.0);
+        }
+
+        let buffer = full_screen(-0.5, Vec4::new(1.0, 0.0, 0.0, 0.75));
+        let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+        assert_eq!(stats.pixels_written, 16);
+        for (x, y, rgba) in image_color.enumerate_pixels_rgba() {
+            assert_eq!(rgba, [191, 0, 64, 255]);
+            assert_eq!(image_depth.pixel_depth(x, y), 0.25);
+        }
+
+        // Fragments passing the alpha test write depth, which occludes
+        let buffer = full_screen(0.5, Vec4::new(0.0, 1.0, 0.0, 1.0));
+        let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+        assert_eq!(stats.fragments_passed, 0);
+        for (_, _, rgba) in image_color.enumerate_pixels_rgba() {
+            assert_eq!(rgba, [191, 0, 64, 255]);
+        }
+    }
+
+    #[test]
+    fn dither_adds_gradient_levels() {
+        // Red grows by a few quantization steps from left to right
+        let buffer: Vec<_> = full_screen(0.0, Vec4::ZERO)
+            .into_iter()
+            .map(|vertex| UnlitColorAttribute {
+                color: Vec4::new((vertex.pos.x + 1.0) * 2.0 / 255.0, 0.0, 0.0, 1.0),
+                ..vertex
+            })
+            .collect();
+
+        // Dithering varies the pixels of a column, so count the distinct
+        // sums of columns
+        let levels = |dither| {
+            let mut image_color = Image::new(64, 4);
+            let mut image_depth = Image::from_pixel_depth(64, 4, 1.0);
+            let pipeline = Pipeline::with_options(PipelineOptions {
+                dither,
+                ..PipelineOptions::default()
+            });
+            pipeline.triangles(
+                &UnlitColor::identity(),
+                &buffer,
+                &mut image_color,
+                &mut image_depth,
+            );
+
+            let mut sums: Vec<u32> = (0..64)
+                .map(|x| {
+                    (0..4)
+                        .map(|y| u32::from(image_color.pixel_rgba(x, y)[0]))
+                        .sum()
+                })
+                .collect();
+            sums.sort_unstable();
+            sums.dedup();
+            sums.len()
+        };
+
+        let (plain, dithered) = (levels(false), levels(true));
+        assert!(
+            dithered > plain,
+            "{} levels, {} without dithering",
+            dithered,
+            plain
+        );
+    }
+
+    /// Outputs the screen space derivatives of a varying linear in the
+    /// screen space position of an 8x8 framebuffer.
+    struct Derivatives;
+
+    impl ShaderProgram for Derivatives {
+        type Attribute = Vec4;
+        type Varying = Vec2;
+        type Fragment = Vec4;
+
+        fn vertex(&self, position: &Vec4) -> (Vec4, Vec2) {
+            let (x, y) = ((position.x + 1.0) * 4.0, (position.y + 1.0) * 4.0);
+            (*position, Vec2::new(3.0 * x + y, -2.0 * x))
+        }
+
+        fn fragment(&self, _position: Vec4, _varying: &Vec2) -> Vec4 {
+            unreachable!("derivatives are read from the fragment input")
+        }
+
+        fn fragment_input(&self, input: &FragmentInput<Vec2>) -> Vec4 {
+            Vec4::new(
+                input.dvar_dx.x,
+                input.dvar_dx.y,
+                input.dvar_dy.x,
+                input.dvar_dy.y,
+            )
+        }
+    }
+
+    #[test]
+    fn derivatives_of_linear_varying_are_constant() {
+        // Vertices on pixel positions keep barycentric coordinates exact.
+        // Pixels on the diagonal are drawn twice, with the same output
+        let buffer: Vec<Vec4> = rect(Vec2::splat(-1.0), Vec2::ONE, 0.0, Vec4::ZERO)
+            .iter()
+            .map(|vertex| vertex.pos)
+            .collect();
+
+        for &perspective_correct in &[false, true] {
+            let mut image_color: ImageF32 = Image::from_pixel(8, 8, Vec4::splat(f32::NAN));
+            let mut image_depth = Image::from_pixel_depth(8, 8, 1.0);
+            let pipeline = Pipeline::with_options(PipelineOptions {
+                perspective_correct,
+                ..PipelineOptions::default()
+            });
+            pipeline.triangles(&Derivatives, &buffer, &mut image_color, &mut image_depth);
+
+            for (x, y, derivatives) in image_color.enumerate_pixels() {
+                assert_eq!(
+                    derivatives,
+                    Vec4::new(3.0, -2.0, 1.0, 0.0),
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn draw_iter_ignores_trailing_vertices_like_draw() {
+        let shader = UnlitColor::identity();
+        let mut buffer = full_screen(0.0, RED);
+        buffer.extend(UnlitColorAttribute::solid(&[Vec3::ZERO, Vec3::X], RED));
+
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let render = |draw: &dyn Fn(&mut Framebuffer) -> PipelineStats| {
+            let mut image_color = Image::new(4, 4);
+            let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+            let stats = draw(&mut Framebuffer::new(
+                &mut [&mut image_color],
+                &mut image_depth,
+            ));
+            (stats, image_color)
+        };
+
+        let (stats, image) = render(&|framebuffer| pipeline.draw(&shader, &buffer, framebuffer));
+        let (iter_stats, iter_image) =
+            render(&|framebuffer| pipeline.draw_iter(&shader, buffer.iter().copied(), framebuffer));
+        assert_eq!(stats.triangles_submitted, 2);
+        assert_eq!(stats.pixels_written, 16);
+        assert_eq!(iter_stats, stats);
+        assert_eq!(iter_image, image);
+    }
+
+    #[test]
+    fn depth_bias_resolves_coplanar_triangles() {
+        // Planes receding steeply to the right, so the depth changes a lot
+        // per pixel, like at grazing angles
+        let slanted = |buffer: Vec<UnlitColorAttribute>, offset: f32| {
+            let tilt = |mut vertex: UnlitColorAttribute| {
+                vertex.pos.z = 0.9 * vertex.pos.x + offset;
+                vertex
+            };
+            buffer.into_iter().map(tilt).collect::<Vec<_>>()
+        };
+        let blue = Vec4::new(0.0, 0.0, 1.0, 1.0);
+        let green = Vec4::new(0.0, 1.0, 0.0, 1.0);
+        let plane = slanted(full_screen(0.0, RED), 0.0);
+        let decal = slanted(full_screen(0.0, green), 0.0);
+        let occluder = slanted(
+            rect(Vec2::new(-1.1, -1.2), Vec2::new(0.0, 1.1), 0.0, blue),
+            -0.02,
+        );
+
+        let shader = UnlitColor::identity();
+        let mut image_color = Image::new(16, 4);
+        let mut image_depth = Image::from_pixel_depth(16, 4, 1.0);
+        let mut pipeline = Pipeline::with_options(PipelineOptions::default());
+        pipeline.triangles(&shader, &plane, &mut image_color, &mut image_depth);
+        pipeline.triangles(&shader, &occluder, &mut image_color, &mut image_depth);
+
+        // Without bias, the coplanar decal loses the depth test
+        let before = image_color.clone();
+        let stats = pipeline.triangles(&shader, &decal, &mut image_color, &mut image_depth);
+        assert_eq!(stats.fragments_passed, 0);
+        assert_eq!(image_color, before);
+
+        pipeline.options_mut().depth_bias = -1e-5;
+        pipeline.options_mut().depth_bias_slope = -0.01;
+        pipeline.triangles(&shader, &decal, &mut image_color, &mut image_depth);
+
+        // The decal covers the plane, but stays behind the occluder in front
+        // of it
+        for (x, y, rgba) in image_color.enumerate_pixels_rgba() {
+            match x {
+                0..=7 => assert_eq!(rgba, [0, 0, 255, 255], "pixel ({}, {})", x, y),
+                8 => assert_ne!(rgba, [255, 0, 0, 255], "pixel ({}, {})", x, y),
+                _ => assert_eq!(rgba, [0, 255, 0, 255], "pixel ({}, {})", x, y),
+            }
+        }
+    }
+
+    #[test]
+    fn options_mut_applies_to_following_draws() {
+        let shader = UnlitColor::identity();
+        let mut image_color = Image::new(4, 4);
+        let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+        let mut pipeline = Pipeline::with_options(PipelineOptions {
+            cull_face: CullFace::Back,
+            ..PipelineOptions::default()
+        });
+        let clockwise: Vec<_> = full_screen(0.0, RED).into_iter().rev().collect();
+
+        // Geometry stage
+        let stats = pipeline.triangles(&shader, &clockwise, &mut image_color, &mut image_depth);
+        assert_eq!(stats.triangles_culled, 2);
+        pipeline.options_mut().cull_face = CullFace::None;
+        let stats = pipeline.triangles(&shader, &clockwise, &mut image_color, &mut image_depth);
+        assert_eq!(stats.triangles_culled, 0);
+        assert_eq!(stats.pixels_written, 16);
+
+        // Raster stage, keeping the changed geometry stage options
+        let green = Vec4::new(0.0, 1.0, 0.0, 1.0);
+        pipeline.options_mut().depth_func = CompareFunc::Greater;
+        let stats = pipeline.triangles(
+            &shader,
+            &full_screen(-0.5, green),
+            &mut image_color,
+            &mut image_depth,
+        );
+        assert_eq!(stats.pixels_written, 0);
+        let stats = pipeline.triangles(
+            &shader,
+            &full_screen(0.5, green),
+            &mut image_color,
+            &mut image_depth,
+        );
+        assert_eq!(stats.pixels_written, 16);
+
+        // Clockwise again, so it is only drawn if culling stays disabled
+        pipeline.options_mut().blend = Some(BlendMode::Alpha);
+        let translucent = Vec4::new(0.0, 0.0, 1.0, 0.5);
+        let behind: Vec<_> = full_screen(0.75, translucent).into_iter().rev().collect();
+        pipeline.triangles(&shader, &behind, &mut image_color, &mut image_depth);
+
+        assert_eq!(pipeline.options().cull_face, CullFace::None);
+        for (_, _, rgba) in image_color.enumerate_pixels_rgba() {
+            assert_eq!(rgba, [0, 128, 128, 255]);
+        }
+    }
+
+    #[test]
+    fn nonfinite_triangles_are_skipped() {
+        let vertex = |x: f32, y: f32, z: f32, w: f32| UnlitColorAttribute {
+            pos: Vec4::new(x, y, z, w),
+            color: RED,
+        };
+        let nan = f32::NAN;
+        let mut buffer = vec![
+            // NaN, e.g. from normalizing a zero vector
+            vertex(nan, 0.0, 0.0, 1.0),
+            vertex(1.0, 0.0, 0.0, 1.0),
+            vertex(0.0, 1.0, 0.0, 1.0),
+            // Infinity
+            vertex(-1.0, -1.0, 0.0, 1.0),
+            vertex(1.0, -1.0, f32::INFINITY, 1.0),
+            vertex(0.0, 1.0, 0.0, 1.0),
+            // Zero W
+            vertex(-1.0, -1.0, 0.0, 1.0),
+            vertex(0.0, 0.0, 0.0, 0.0),
+            vertex(0.0, 1.0, 0.0, 1.0),
+            // Finite, but with an area overflowing to infinity
+            vertex(-1e30, -1e30, 0.0, 1.0),
+            vertex(1e30, -1e30, 0.0, 1.0),
+            vertex(0.0, 1e30, 0.0, 1.0),
+        ];
+        buffer.extend(full_screen(0.0, RED));
+
+        // Non-finite distances to clip planes are left to `setup` to count
+        let clip_planes = [Some(Vec4::W), None, None, None];
+        for &clip_planes in &[[None; MAX_CLIP_PLANES], clip_planes] {
+            let pipeline = Pipeline::with_options(PipelineOptions {
+                clip_planes,
+                ..PipelineOptions::default()
+            });
+
+            let mut image_color = Image::new(4, 4);
+            let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+            let stats = pipeline.triangles(
+                &UnlitColor::identity(),
+                &buffer[..12],
+                &mut image_color,
+                &mut image_depth,
+            );
+            assert_eq!(stats.triangles_submitted, 4);
+            assert_eq!(stats.triangles_nonfinite, 4);
+            assert_eq!(stats.fragments_tested, 0);
+            assert_eq!(image_color, Image::new(4, 4));
+            assert_eq!(image_depth, Image::from_pixel_depth(4, 4, 1.0));
+
+            // Other triangles of the draw call are not affected
+            let stats = pipeline.triangles(
+                &UnlitColor::identity(),
+                &buffer,
+                &mut image_color,
+                &mut image_depth,
+            );
+            assert_eq!(stats.triangles_nonfinite, 4);
+            assert_eq!(stats.pixels_written, 16);
+            assert!(image_depth
+                .enumerate_pixels_depth()
+                .all(|(_, _, depth)| depth == 0.5));
+        }
+    }
+
+    #[test]
+    fn offscreen_triangles_are_clipped() {
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let shader = UnlitColor::identity();
+        let triangle = |a: Vec2, b: Vec2, c: Vec2| {
+            UnlitColorAttribute::solid(&[a.extend(0.0), b.extend(0.0), c.extend(0.0)], RED)
+        };
+
+        // Entirely off the left, right, bottom and top side
+        for &center in &[-Vec2::X, Vec2::X, -Vec2::Y, Vec2::Y] {
+            let center = center * 2.0;
+            let buffer = triangle(
+                center + Vec2::new(-0.5, -0.5),
+                center + Vec2::new(0.5, -0.5),
+                center + Vec2::new(0.0, 0.5),
+            );
+            let mut image_color = Image::new(4, 4);
+            let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+            let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+            assert_eq!(stats.triangles_clipped, 1, "center {}", center);
+            assert_eq!(stats.fragments_tested, 0, "center {}", center);
+            assert_eq!(image_color, Image::new(4, 4));
+        }
+
+        // Passes by the bottom left corner, with the bounding box overlapping
+        // the screen
+        let buffer = triangle(
+            Vec2::new(-3.0, 0.5),
+            Vec2::new(-3.0, -3.0),
+            Vec2::new(0.5, -3.0),
+        );
+        let mut image_color = Image::new(4, 4);
+        let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+        let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+        assert_eq!(stats.triangles_clipped, 0);
+        assert_eq!(stats.pixels_written, 0);
+        assert_eq!(image_color, Image::new(4, 4));
+
+        // Covers only the top right pixel
+        let buffer = triangle(
+            Vec2::new(0.4, 0.4),
+            Vec2::new(3.0, 0.4),
+            Vec2::new(0.4, 3.0),
+        );
+        let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+        assert_eq!(stats.triangles_clipped, 0);
+        assert_eq!(stats.pixels_written, 1);
+        for (x, y, rgba) in image_color.enumerate_pixels_rgba() {
+            let expected = if (x, y) == (3, 0) {
+                [255, 0, 0, 255]
+            } else {
+                [0; 4]
+            };
+            assert_eq!(rgba, expected, "pixel ({}, {})", x, y);
+        }
+    }
+
+    #[test]
+    fn fragments_beyond_far_plane_are_discarded() {
+        // NDC z grows from -0.5 on the left edge to 1.7 on the right, so
+        // stored depth goes from 0.25 to 1.35
+        let z = |x: f32| x * 1.1 + 0.6;
+        let mut buffer = full_screen(0.0, RED);
+        for vertex in &mut buffer {
+            vertex.pos.z = z(vertex.pos.x);
+        }
+
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let mut image_color = Image::new(8, 2);
+        let mut image_depth = Image::from_pixel_depth(8, 2, 1.0);
+        pipeline.triangles(
+            &UnlitColor::identity(),
+            &buffer,
+            &mut image_color,
+            &mut image_depth,
+        );
+
+        for (x, y, depth) in image_depth.enumerate_pixels_depth() {
+            let ndc_x = x as f32 / 4.0 - 1.0;
+            let expected = z(ndc_x) * 0.5 + 0.5;
+            let rgba = image_color.pixel_rgba(x, y);
+            if expected > 1.0 {
+                assert_eq!(rgba, [0; 4], "pixel ({}, {})", x, y);
+                assert_eq!(depth, 1.0, "pixel ({}, {})", x, y);
+            } else {
+                assert_eq!(rgba, [255, 0, 0, 255], "pixel ({}, {})", x, y);
+                assert!((depth - expected).abs() < 1e-5, "pixel ({}, {})", x, y);
+            }
+        }
+        assert_eq!(image_color.pixel_rgba(5, 0), [255, 0, 0, 255]);
+        assert_eq!(image_color.pixel_rgba(6, 0), [0; 4]);
+    }
+
+    #[test]
+    fn depth_conventions_store_same_depth() {
+        let floor = [
+            Vec3::new(-10.0, -1.0, -0.5),
+            Vec3::new(10.0, -1.0, -0.5),
+            Vec3::new(10.0, -1.0, -8.0),
+            Vec3::new(-10.0, -1.0, -0.5),
+            Vec3::new(10.0, -1.0, -8.0),
+            Vec3::new(-10.0, -1.0, -8.0),
+        ];
+        let occluder = [
+            Vec3::new(-0.5, -1.0, -2.0),
+            Vec3::new(0.5, -1.0, -2.0),
+            Vec3::new(0.0, 0.5, -2.5),
+        ];
+        let mut buffer = UnlitColorAttribute::solid(&floor, RED);
+        buffer.extend(UnlitColorAttribute::solid(&occluder, Vec4::ONE));
+
+        let fov = std::f32::consts::FRAC_PI_2;
+        let render = |projection: Mat4, depth_convention: DepthConvention| {
+            let pipeline = Pipeline::with_options(PipelineOptions {
+                depth_convention,
+                ..PipelineOptions::default()
+            });
+            let mut image_color = Image::new(16, 16);
+            let mut image_depth = Image::from_pixel_depth(16, 16, 1.0);
+            pipeline.triangles(
+                &UnlitColor::new(projection),
+                &buffer,
+                &mut image_color,
+                &mut image_depth,
+            );
+            (image_color, image_depth)
+        };
+        let (gl_color, gl_depth) = render(
+            Mat4::perspective_rh_gl(fov, 1.0, 0.1, 10.0),
+            DepthConvention::NegativeOneToOne,
+        );
+        let (wgpu_color, wgpu_depth) = render(
+            Mat4::perspective_rh(fov, 1.0, 0.1, 10.0),
+            DepthConvention::ZeroToOne,
+        );
+
+        assert_eq!(gl_color, wgpu_color);
+        let depths = gl_depth
+            .enumerate_pixels_depth()
+            .zip(wgpu_depth.enumerate_pixels_depth());
+        let mut covered = 0;
+        for ((x, y, gl), (_, _, wgpu)) in depths {
+            assert!(
+                (gl - wgpu).abs() < 1e-5,
+                "pixel ({}, {}): {} != {}",
+                x,
+                y,
+                gl,
+                wgpu
+            );
+            covered += u32::from(gl < 1.0);
+        }
+        assert!(covered > 16 * 4);
+        assert!(gl_color
+            .enumerate_pixels_rgba()
+            .any(|(_, _, rgba)| rgba == [255; 4]));
+    }
+
+    #[test]
+    fn depth16_resolves_surfaces_steps_apart() {
+        use crate::image::ImageDepth16;
+
+        // Stored depth 0.6 and 2.5 steps of 1/65535 behind it
+        let ndc = |depth: f32| depth * 2.0 - 1.0;
+        let near = full_screen(ndc(0.6), Vec4::ONE);
+        let far = full_screen(ndc(0.6 + 2.5 / 65535.0), RED);
+
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let shader = UnlitColor::identity();
+        for &(first, second) in &[(&near, &far), (&far, &near)] {
+            let mut image_color = Image::new(4, 4);
+            let mut image_depth = ImageDepth16::from_pixel(4, 4, u16::CLEAR);
+            pipeline.triangles(&shader, first, &mut image_color, &mut image_depth);
+            pipeline.triangles(&shader, second, &mut image_color, &mut image_depth);
+
+            let expected_depth = (0.6f32 * 65535.0).round() as u16;
+            for (x, y, depth) in image_depth.enumerate_pixels() {
+                assert_eq!(depth, expected_depth, "pixel ({}, {})", x, y);
+                assert_eq!(
+                    image_color.pixel_rgba(x, y),
+                    [255; 4],
+                    "pixel ({}, {})",
+                    x,
+                    y
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn two_sided_quad_seen_from_behind() {
+        /// Lights both faces of the triangles from the viewer, flipping the
+        /// normal of back faces.
+        struct TwoSided {
+            rotation: Mat4,
+        }
+
+        impl ShaderProgram for TwoSided {
+            type Attribute = Vec3;
+            type Varying = Vec3;
+            type Fragment = Vec4;
+
+            fn vertex(&self, position: &Vec3) -> (Vec4, Vec3) {
+                let normal = self.rotation.transform_vector3(-Vec3::Z);
+                (self.rotation * position.extend(1.0), normal)
+            }
+
+            fn fragment(&self, _position: Vec4, _normal: &Vec3) -> Vec4 {
+                unreachable!("the face is read from the fragment input")
+            }
+
+            fn fragment_input(&self, input: &FragmentInput<Vec3>) -> Vec4 {
+                let normal = if input.front_facing {
+                    *input.varying
+                } else {
+                    -*input.varying
+                };
+                // The viewer is towards the near plane, at -Z in NDC
+                let lit = normal.dot(-Vec3::Z).max(0.0);
+                Vec3::splat(lit).extend(if input.front_facing { 1.0 } else { 0.5 })
+            }
+        }
+
+        let buffer: Vec<Vec3> = full_screen(0.0, RED)
+            .iter()
+            .map(|vertex| vertex.pos.truncate())
+            .collect();
+        let render = |rotation: Mat4, cull_face: CullFace| {
+            let pipeline = Pipeline::with_options(PipelineOptions {
+                cull_face,
+                ..PipelineOptions::default()
+            });
+            let mut image_color = Image::new(4, 4);
+            let mut image_depth = Image::from_pixel_depth(4, 4, 1.0);
+            let shader = TwoSided { rotation };
+            let stats = pipeline.triangles(&shader, &buffer, &mut image_color, &mut image_depth);
+            (image_color, stats)
+        };
+
+        let (front, _) = render(Mat4::IDENTITY, CullFace::None);
+        let behind = Mat4::from_rotation_y(std::f32::consts::PI);
+        let (back, stats) = render(behind, CullFace::None);
+        assert_eq!(stats.pixels_written, 16);
+        for (x, y, rgba) in front.enumerate_pixels_rgba() {
+            assert_eq!(rgba, [255, 255, 255, 255], "pixel ({}, {})", x, y);
+            assert_eq!(
+                back.pixel_rgba(x, y),
+                [255, 255, 255, 128],
+                "pixel ({}, {})",
+                x,
+                y
+            );
+        }
+
+        // Back faces are the ones `CullFace::Back` culls
+        let (_, stats) = render(behind, CullFace::Back);
+        assert_eq!(stats.triangles_culled, 2);
+        let (_, stats) = render(Mat4::IDENTITY, CullFace::Back);
+        assert_eq!(stats.triangles_culled, 0);
+    }
+
+    #[test]
+    fn ids_of_overlapping_quads_are_nearest() {
+        // The far quad covers pixels 0 to 5 and is drawn last, the near quad
+        // pixels 3 to 7
+        let far = rect(Vec2::new(-1.1, -1.2), Vec2::new(0.3, 1.1), 0.5, RED);
+        let near = rect(Vec2::new(-0.3, -1.2), Vec2::splat(1.1), -0.5, RED);
+
+        let mut pipeline = Pipeline::with_options(PipelineOptions::default());
+        let shader = UnlitColor::identity();
+        let mut image_color = Image::new(8, 2);
+        let mut image_depth = Image::from_pixel_depth(8, 2, 1.0);
+        let mut image_ids = Image::new(8, 2);
+        let mut images_color = [&mut image_color];
+        let mut framebuffer =
+            Framebuffer::new(&mut images_color, &mut image_depth).with_ids(&mut image_ids);
+        pipeline.options_mut().draw_id = 2;
+        pipeline.draw(&shader, &near, &mut framebuffer);
+        pipeline.options_mut().draw_id = 1;
+        pipeline.draw(&shader, &far, &mut framebuffer);
+
+        for (x, y, id) in image_ids.enumerate_pixels() {
+            let expected = if x < 3 { 1 } else { 2 };
+            assert_eq!(id, expected, "pixel ({}, {})", x, y);
+        }
+    }
+
+    /// Returns the triangles of a UV sphere at CENTER with RADIUS, colored
+    /// by their normals.
+    fn sphere(center: Vec3, radius: f32) -> Vec<UnlitColorAttribute> {
+        let (rings, segments) = (12, 16);
+        let point = |ring: u32, segment: u32| {
+            let theta = std::f32::consts::PI * ring as f32 / rings as f32;
+            let phi = std::f32::consts::TAU * segment as f32 / segments as f32;
+            let normal = Vec3::new(
+                theta.sin() * phi.cos(),
+                theta.cos(),
+                theta.sin() * phi.sin(),
+            );
+            let color = (normal * 0.5 + Vec3::splat(0.5)).extend(1.0);
+            UnlitColorAttribute::new(center + normal * radius, color)
+        };
+
+        let mut triangles = Vec::new();
+        for ring in 0..rings {
+            for segment in 0..segments {
+                let (a, b) = (point(ring, segment), point(ring, segment + 1));
+                let (c, d) = (point(ring + 1, segment + 1), point(ring + 1, segment));
+                triangles.extend_from_slice(&[a, b, c, a, c, d]);
+            }
+        }
+        triangles
+    }
+
+    #[test]
+    fn depth_prepass_shades_like_single_pass() {
+        let mut buffer = sphere(Vec3::new(-0.4, 0.1, -3.0), 1.0);
+        buffer.extend(sphere(Vec3::new(0.5, -0.2, -3.5), 1.2));
+        let projection = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_3, 1.0, 0.1, 10.0);
+        let shader = UnlitColor::new(projection);
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+
+        let mut single_color = Image::new(32, 32);
+        let mut single_depth = Image::from_pixel_depth(32, 32, 1.0);
+        let single = pipeline.triangles(&shader, &buffer, &mut single_color, &mut single_depth);
+
+        let mut image_color = Image::new(32, 32);
+        let mut image_depth = Image::from_pixel_depth(32, 32, 1.0);
+        let prepass = pipeline.depth_prepass(&shader, &buffer, &mut image_depth);
+        let mut images_color = [&mut image_color];
+        let shading = pipeline.shade_equal(
+            &shader,
+            &buffer,
+            &mut Framebuffer::new(&mut images_color, &mut image_depth),
+        );
+
+        assert_eq!(prepass, single);
+        assert_eq!(image_depth, single_depth);
+        assert_eq!(image_color, single_color);
+
+        // Every visible fragment passes the depth test of the second pass,
+        // and only those do
+        let mut covered = 0;
+        for (x, y, depth) in image_depth.enumerate_pixels_depth() {
+            if depth < 1.0 {
+                assert_ne!(image_color.pixel_rgba(x, y), [0; 4], "pixel ({}, {})", x, y);
+                covered += 1;
+            }
+        }
+        assert!(covered > 32 * 8);
+        assert_eq!(shading.triangles_culled, single.triangles_culled);
+        assert!(shading.pixels_written >= covered);
+        assert!(shading.pixels_written < single.pixels_written);
+    }
+
+    #[test]
+    fn depth_peel_composites_intersecting_quads() {
+        // Red is nearer on the left, blue on the right, crossing at x = 0
+        let mut red = full_screen(0.0, Vec4::new(1.0, 0.0, 0.0, 0.5));
+        let mut blue = full_screen(0.0, Vec4::new(0.0, 0.0, 1.0, 0.5));
+        for vertex in &mut red {
+            vertex.pos.z = vertex.pos.x * 0.5;
+        }
+        for vertex in &mut blue {
+            vertex.pos.z = -vertex.pos.x * 0.5;
+        }
+
+        let over = |dst: Vec3, src: Vec4| src.truncate() * src.w + dst * (1.0 - src.w);
+        let (red_color, blue_color) = (red[0].color, blue[0].color);
+        let left = over(over(Vec3::ONE, blue_color), red_color) * 255.0;
+        let right = over(over(Vec3::ONE, red_color), blue_color) * 255.0;
+
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let shader = UnlitColor::identity();
+        let image_depth = Image::from_pixel_depth(8, 2, 1.0);
+        for &(first, second) in &[(&red, &blue), (&blue, &red)] {
+            let buffer: Vec<_> = first.iter().chain(second).copied().collect();
+            let mut image_color = Image::from_pixel_rgba(8, 2, [255; 4]);
+            pipeline.depth_peel(&shader, &buffer, &mut image_color, &image_depth, 4);
+
+            for (x, y, rgba) in image_color.enumerate_pixels_rgba() {
+                let expected = match x {
+                    0..=3 => left,
+                    4 => continue,
+                    _ => right,
+                };
+                let rgb = Vec3::new(rgba[0] as f32, rgba[1] as f32, rgba[2] as f32);
+                assert!(
+                    rgb.abs_diff_eq(expected, 2.0),
+                    "pixel ({}, {}): {} != {}",
+                    x,
+                    y,
+                    rgb,
+                    expected
+                );
+                assert_eq!(rgba[3], 255);
+            }
+        }
+    }
+
+    #[test]
+    fn fog_on_ground_plane_matches_analytic() {
+        let ground = [
+            Vec3::new(-60.0, -1.0, -0.5),
+            Vec3::new(60.0, -1.0, -0.5),
+            Vec3::new(60.0, -1.0, -60.0),
+            Vec3::new(-60.0, -1.0, -0.5),
+            Vec3::new(60.0, -1.0, -60.0),
+            Vec3::new(-60.0, -1.0, -60.0),
+        ];
+        let buffer = UnlitColorAttribute::solid(&ground, Vec4::W);
+        let projection = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 100.0);
+
+        let fog = Fog {
+            color: Vec4::ONE,
+            mode: FogMode::Exp { density: 0.2 },
+        };
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            fog: Some(fog),
+            ..PipelineOptions::default()
+        });
+        let mut image_color = ImageF32::from_pixel(16, 16, Vec4::ZERO);
+        let mut image_depth = Image::from_pixel_depth(16, 16, 1.0);
+        pipeline.triangles(
+            &UnlitColor::new(projection),
+            &buffer,
+            &mut image_color,
+            &mut image_depth,
+        );
+
+        // The ground is black, so the red channel is the fog. It grows
+        // towards the horizon in the middle of the screen
+        let mut previous = 0.0;
+        for row in (8..16).rev() {
+            let screen_y = 15 - row;
+            let ndc_y = screen_y as f32 / 8.0 - 1.0;
+            // With a 90 degree field of view, the ground is at distance
+            // -1 / ndc_y along the view direction
+            let distance = -1.0 / ndc_y;
+            let expected = 1.0 - fog.visibility(distance);
+
+            for x in 0..16 {
+                let fogged = image_color.pixel(x, row).x;
+                assert!(
+                    (fogged - expected).abs() < 1e-3,
+                    "pixel ({}, {}): {} != {}",
+                    x,
+                    row,
+                    fogged,
+                    expected
+                );
+            }
+            let fogged = image_color.pixel(0, row).x;
+            assert!(fogged > previous, "row {}: {} <= {}", row, fogged, previous);
+            previous = fogged;
+        }
+    }
+
+    #[test]
+    fn supersample_averages_in_framebuffer_space() {
+        // Covers the left column of samples of the 2x2 block
+        let left = rect(Vec2::new(-1.1, -1.2), Vec2::new(-0.5, 1.1), 0.0, Vec4::ONE);
+        let render = |options: PipelineOptions, clear_depth: f32| {
+            let pipeline = Pipeline::with_options(options);
+            let mut image_color = Image::from_pixel_rgba(1, 1, [0, 0, 0, 255]);
+            let mut image_depth = Image::from_pixel_depth(1, 1, clear_depth);
+            Supersample::new(2).triangles(
+                &pipeline,
+                &UnlitColor::identity(),
+                &left,
+                &mut image_color,
+                &mut image_depth,
+            );
+            (image_color.pixel_rgba(0, 0), image_depth.pixel_depth(0, 0))
+        };
+
+        let (color, depth) = render(PipelineOptions::default(), 1.0);
+        assert_eq!(color, [128, 128, 128, 255]);
+        assert_eq!(depth, 0.5);
+
+        // Half of linear white is 0.735 in sRGB
+        let srgb = PipelineOptions {
+            srgb_framebuffer: true,
+            ..PipelineOptions::default()
+        };
+        let (color, _) = render(srgb, 1.0);
+        assert_eq!(color, [188, 188, 188, 255]);
+
+        let greater = PipelineOptions {
+            depth_func: CompareFunc::Greater,
+            ..PipelineOptions::default()
+        };
+        let (color, depth) = render(greater, 0.0);
+        assert_eq!(color, [128, 128, 128, 255]);
+        assert_eq!(depth, 0.5);
+    }
+
+    #[test]
+    fn perspective_derivatives_are_quad_differences() {
+        /// Outputs the varying and its derivatives along X.
+        struct VaryingAndDerivative;
+
+        impl ShaderProgram for VaryingAndDerivative {
+            type Attribute = Vec4;
+            type Varying = Vec2;
+            type Fragment = (Vec4, Vec4);
+
+            fn vertex(&self, position: &Vec4) -> (Vec4, Vec2) {
+                // Farther to the right, with 1 / W going from 1 to 0.5 across
+                // the screen, and the varying divided by W affine in screen
+                // space, so that both triangles interpolate the same function
+                let w = 1.0 / (0.75 - 0.25 * position.x);
+                let varying = Vec2::new(position.x * 4.0, position.y * 4.0 + position.x * 2.0) * w;
+                ((position.truncate() * w).extend(w), varying)
+            }
+
+            fn fragment(&self, _position: Vec4, _varying: &Vec2) -> (Vec4, Vec4) {
+                unreachable!("derivatives are read from the fragment input")
+            }
+
+            fn fragment_input(&self, input: &FragmentInput<Vec2>) -> (Vec4, Vec4) {
+                (
+                    input.varying.extend(0.0).extend(0.0),
+                    input.dvar_dx.extend(0.0).extend(0.0),
+                )
+            }
+        }
+
+        let buffer: Vec<Vec4> = full_screen(0.0, Vec4::ZERO)
+            .iter()
+            .map(|vertex| vertex.pos)
+            .collect();
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            perspective_correct: true,
+            ..PipelineOptions::default()
+        });
+        let mut image_varying: ImageF32 = Image::from_pixel(8, 8, Vec4::ZERO);
+        let mut image_dx: ImageF32 = Image::from_pixel(8, 8, Vec4::ZERO);
+        let mut image_depth = Image::from_pixel_depth(8, 8, 1.0);
+        let mut images_color = [&mut image_varying, &mut image_dx];
+        pipeline.draw(
+            &VaryingAndDerivative,
+            &buffer,
+            &mut Framebuffer::new(&mut images_color, &mut image_depth),
+        );
+
+        // The varying is not linear on screen, so derivatives differ
+        // between quads, but equal the differences along the lower row of
+        // each, which is the lower image row of the two
+        for y in 0..8 {
+            let quad_y = y | 1;
+            for qx in (0..8).step_by(2) {
+                let expected =
+                    image_varying.pixel(qx + 1, quad_y) - image_varying.pixel(qx, quad_y);
+                for x in qx..qx + 2 {
+                    let dx = image_dx.pixel(x, y);
+                    assert!(
+                        dx.abs_diff_eq(expected, 1e-4),
+                        "pixel ({}, {}): {} != {}",
+                        x,
+                        y,
+                        dx,
+                        expected
+                    );
+                }
+            }
+        }
+        assert!(image_dx.pixel(6, 0).x > image_dx.pixel(0, 0).x * 1.5);
+    }
+}
@@ -0,0 +1,396 @@
+#[cfg(feature = "obj")]
+mod obj;
+
+use std::collections::HashMap;
+
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+#[cfg(feature = "obj")]
+pub use self::obj::{load_obj, parse_obj, ObjError};
+
+/// Vertex of a triangle mesh, as loaded by `load_obj`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Vertex {
+    pub pos: Vec3,
+    pub norm: Vec3,
+    pub uv: Vec2,
+}
+
+/// Vertex of a mesh deformed by a skeleton, see `skin`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct SkinnedVertex {
+    pub pos: Vec3,
+    pub norm: Vec3,
+    pub uv: Vec2,
+    /// Indices of up to four joints into the matrix palette.
+    pub joints: [u16; 4],
+    /// Weights of JOINTS, which should add up to 1.0. Unused joints have
+    /// zero weight.
+    pub weights: Vec4,
+}
+
+/// Deforms VERTEX by the weighted blend of the matrices of its joints in
+/// PALETTE (linear blend skinning), returning the skinned position and unit
+/// normal. The matrices transform from the bind pose to the current pose,
+/// i.e. they usually are the joint world transforms times their inverse
+/// bind matrices.
+///
+/// Normals are transformed by the inverse transpose of the blended matrix,
+/// so they stay perpendicular to the surface under non-uniform scale.
+pub fn skin(vertex: &SkinnedVertex, palette: &[Mat4]) -> (Vec4, Vec3) {
+    let weights: [f32; 4] = vertex.weights.into();
+    let matrix = vertex
+        .joints
+        .iter()
+        .zip(&weights)
+        .filter(|(_, &weight)| weight != 0.0)
+        .fold(Mat4::ZERO, |sum, (&joint, &weight)| {
+            sum + palette[joint as usize] * weight
+        });
+
+    let pos = matrix * vertex.pos.extend(1.0);
+
+    // The cofactor matrix is the inverse transpose scaled by the
+    // determinant, which normalization cancels up to its sign
+    let (x, y, z) = (
+        matrix.x_axis.truncate(),
+        matrix.y_axis.truncate(),
+        matrix.z_axis.truncate(),
+    );
+    let (cof_x, cof_y, cof_z) = (y.cross(z), z.cross(x), x.cross(y));
+    let norm = cof_x * vertex.norm.x + cof_y * vertex.norm.y + cof_z * vertex.norm.z;
+    let norm = norm * x.dot(cof_x).signum();
+
+    (pos, norm.normalize_or_zero())
+}
+
+/// Merges bitwise equal VERTICES, three per triangle, into a compact vertex
+/// array and three indices per triangle, keeping the order and winding of
+/// the triangles. Positive and negative zero are considered equal.
+///
+/// The compression ratio is `vertices.len() / returned vertices.len()`.
+pub fn dedupe(vertices: &[Vertex]) -> (Vec<Vertex>, Vec<u32>) {
+    let mut unique = Vec::new();
+    let mut indices = Vec::with_capacity(vertices.len());
+    let mut index_of = HashMap::new();
+
+    for vertex in vertices {
+        let index = *index_of.entry(vertex_key(vertex)).or_insert_with(|| {
+            unique.push(*vertex);
+            unique.len() as u32 - 1
+        });
+        indices.push(index);
+    }
+
+    (unique, indices)
+}
+
+/// Returns the normals of the vertices at POSITIONS, averaged from the
+/// normals of the triangles sharing them, three INDICES per triangle.
+/// Larger triangles have more weight. Vertices only used by degenerate
+/// triangles, or by none, get a zero normal.
+pub fn compute_normals(positions: &[Vec3], indices: &[u32]) -> Vec<Vec3> {
+    let mut normals = vec![Vec3::ZERO; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let normal = weighted_normal(positions, triangle);
+        for &index in triangle {
+            normals[index as usize] += normal;
+        }
+    }
+
+    for normal in &mut normals {
+        *normal = normal.normalize_or_zero();
+    }
+
+    normals
+}
+
+/// Like `compute_normals`, but a triangle only shares normals with the
+/// triangles around a vertex that are at most CREASE_ANGLE radians away
+/// from it, so that sharper edges stay hard. Vertices on such edges are
+/// split, so this returns new positions, normals and indices. The order of
+/// the triangles is kept.
+pub fn compute_normals_with_crease(
+    positions: &[Vec3],
+    indices: &[u32],
+    crease_angle: f32,
+) -> (Vec<Vec3>, Vec<Vec3>, Vec<u32>) {
+    let cos_crease = crease_angle.cos();
+    let weighted: Vec<Vec3> = indices
+        .chunks_exact(3)
+        .map(|triangle| weighted_normal(positions, triangle))
+        .collect();
+
+    let mut triangles_of = vec![Vec::new(); positions.len()];
+    for (t, triangle) in indices.chunks_exact(3).enumerate() {
+        for &index in triangle {
+            triangles_of[index as usize].push(t);
+        }
+    }
+
+    let mut new_positions = Vec::new();
+    let mut new_normals = Vec::new();
+    let mut new_indices = Vec::with_capacity(indices.len());
+    let mut index_of = HashMap::new();
+
+    for (t, triangle) in indices.chunks_exact(3).enumerate() {
+        let unit = weighted[t].normalize_or_zero();
+        for &index in triangle {
+            // Degenerate triangles have no direction to crease against and
+            // take the smooth normal of the vertex
+            let normal = triangles_of[index as usize]
+                .iter()
+                .map(|&other| weighted[other])
+                .filter(|other| {
+                    unit == Vec3::ZERO || unit.dot(other.normalize_or_zero()) >= cos_crease
+                })
+                .fold(Vec3::ZERO, |sum, other| sum + other)
+                .normalize_or_zero();
+
+            let key = (index, <[f32; 3]>::from(normal).map(|c| (c + 0.0).to_bits()));
+            let new_index = *index_of.entry(key).or_insert_with(|| {
+                new_positions.push(positions[index as usize]);
+                new_normals.push(normal);
+                new_positions.len() as u32 - 1
+            });
+            new_indices.push(new_index);
+        }
+    }
+
+    (new_positions, new_normals, new_indices)
+}
+
+/// Returns the tangents of the vertices, pointing along increasing U in the
+/// plane of their normal, for normal mapping. The tangents are averaged
+/// from the triangles sharing a vertex, three INDICES per triangle, like in
+/// `compute_normals`.
+///
+/// W holds the handedness, 1.0 or -1.0, such that the bitangent, pointing
+/// along increasing V, is `normal.cross(tangent.truncate()) * tangent.w`.
+/// It is -1.0 where the UVs are mirrored. Vertices shared by triangles with
+/// opposite handedness, e.g. on the seam of a mirrored UV island, should be
+/// split first, or their tangents cancel out and fall back to an arbitrary
+/// direction perpendicular to the normal.
+pub fn compute_tangents(
+    positions: &[Vec3],
+    normals: &[Vec3],
+    uvs: &[Vec2],
+    indices: &[u32],
+) -> Vec<Vec4> {
+    // Lengyel's method: solve for the directions of increasing U and V in
+    // the plane of each triangle and accumulate them at its vertices
+    let mut tangents = vec![Vec3::ZERO; positions.len()];
+    let mut bitangents = vec![Vec3::ZERO; positions.len()];
+    for triangle in indices.chunks_exact(3) {
+        let [a, b, c] = [triangle[0], triangle[1], triangle[2]].map(|i| i as usize);
+        let (edge_ab, edge_ac) = (positions[b] - positions[a], positions[c] - positions[a]);
+        let (duv_ab, duv_ac) = (uvs[b] - uvs[a], uvs[c] - uvs[a]);
+
+        let r = 1.0 / (duv_ab.x * duv_ac.y - duv_ac.x * duv_ab.y);
+        let tangent = (edge_ab * duv_ac.y - edge_ac * duv_ab.y) * r;
+        let bitangent = (edge_ac * duv_ab.x - edge_ab * duv_ac.x) * r;
+
+        // Triangles with degenerate UVs have no tangent space
+        if !tangent.is_finite() || !bitangent.is_finite() {
+            continue;
+        }
+
+        for &index in triangle {
+            tangents[index as usize] += tangent;
+            bitangents[index as usize] += bitangent;
+        }
+    }
+
+    normals
+        .iter()
+        .zip(tangents.iter().zip(&bitangents))
+        .map(|(&normal, (&tangent, &bitangent))| {
+            // Gram-Schmidt orthogonalize against the normal
+            let tangent = (tangent - normal * normal.dot(tangent)).normalize_or_zero();
+            if tangent == Vec3::ZERO {
+                return any_tangent(normal).extend(1.0);
+            }
+
+            let handedness = if normal.cross(tangent).dot(bitangent) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            tangent.extend(handedness)
+        })
+        .collect()
+}
+
+/// Returns a unit vector perpendicular to NORMAL, or X if it is zero.
+fn any_tangent(normal: Vec3) -> Vec3 {
+    let axis = if normal.x.abs() < 0.9 {
+        Vec3::X
+    } else {
+        Vec3::Y
+    };
+    let tangent = (axis - normal * normal.dot(axis)).normalize_or_zero();
+    if tangent == Vec3::ZERO {
+        Vec3::X
+    } else {
+        tangent
+    }
+}
+
+/// Returns the normal of TRIANGLE with the length of twice its area, zero
+/// if it is degenerate.
+fn weighted_normal(positions: &[Vec3], triangle: &[u32]) -> Vec3 {
+    let a = positions[triangle[0] as usize];
+    let b = positions[triangle[1] as usize];
+    let c = positions[triangle[2] as usize];
+    let normal = (b - a).cross(c - a);
+
+    // Non-finite positions would spread NaN to all neighboring vertices
+    if normal.is_finite() {
+        normal
+    } else {
+        Vec3::ZERO
+    }
+}
+
+fn vertex_key(vertex: &Vertex) -> [u32; 8] {
+    // Adding zero turns -0.0 into 0.0, so that they hash the same
+    let Vertex { pos, norm, uv } = *vertex;
+    [pos.x, pos.y, pos.z, norm.x, norm.y, norm.z, uv.x, uv.y]
+        .map(|component| (component + 0.0).to_bits())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mirrored_uvs_flip_tangent_handedness() {
+        // Two unit quads side by side, facing +Z, with the vertices on the
+        // seam split. The right one mirrors the U of the left one
+        let positions = [
+            Vec3::new(0.0, 0.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+            Vec3::new(0.0, 1.0, 0.0),
+            Vec3::new(1.0, 0.0, 0.0),
+            Vec3::new(2.0, 0.0, 0.0),
+            Vec3::new(2.0, 1.0, 0.0),
+            Vec3::new(1.0, 1.0, 0.0),
+        ];
+        let uvs: Vec<Vec2> = positions
+            .iter()
+            .enumerate()
+            .map(|(i, pos)| {
+                let u = if i < 4 { pos.x } else { 2.0 - pos.x };
+                Vec2::new(u, pos.y)
+            })
+            .collect();
+        let normals = [Vec3::Z; 8];
+        let indices = [0, 1, 2, 0, 2, 3, 4, 5, 6, 4, 6, 7];
+
+        let tangents = compute_tangents(&positions, &normals, &uvs, &indices);
+        for (i, tangent) in tangents.iter().enumerate() {
+            let expected = if i < 4 {
+                Vec4::new(1.0, 0.0, 0.0, 1.0)
+            } else {
+                Vec4::new(-1.0, 0.0, 0.0, -1.0)
+            };
+            assert!(
+                tangent.abs_diff_eq(expected, 1e-6),
+                "vertex {}: {}",
+                i,
+                tangent
+            );
+
+            // The bitangent points along increasing V on both halves
+            let bitangent = normals[i].cross(tangent.truncate()) * tangent.w;
+            assert!(bitangent.abs_diff_eq(Vec3::Y, 1e-6), "vertex {}", i);
+        }
+    }
+
+    #[test]
+    fn skin_bends_two_bone_cylinder() {
+        // The upper bone of a cylinder along +Y bends 90 degrees to -X
+        // around the joint at Y = 1
+        let joint = Vec3::Y;
+        let palette = [
+            Mat4::IDENTITY,
+            Mat4::from_translation(joint)
+                * Mat4::from_rotation_z(std::f32::consts::FRAC_PI_2)
+                * Mat4::from_translation(-joint),
+        ];
+        let vertex = |pos: Vec3, norm: Vec3, weights: Vec4| SkinnedVertex {
+            pos,
+            norm,
+            uv: Vec2::ZERO,
+            joints: [0, 1, 0, 0],
+            weights,
+        };
+
+        let cases = [
+            // Lower bone only, unchanged
+            (
+                vertex(
+                    Vec3::new(0.1, 0.5, 0.0),
+                    Vec3::X,
+                    Vec4::new(1.0, 0.0, 0.0, 0.0),
+                ),
+                Vec3::new(0.1, 0.5, 0.0),
+                Vec3::X,
+            ),
+            (
+                vertex(
+                    Vec3::new(0.0, 0.5, -0.1),
+                    -Vec3::Z,
+                    Vec4::new(1.0, 0.0, 0.0, 0.0),
+                ),
+                Vec3::new(0.0, 0.5, -0.1),
+                -Vec3::Z,
+            ),
+            // Upper bone only, rotated
+            (
+                vertex(
+                    Vec3::new(0.1, 2.0, 0.0),
+                    Vec3::X,
+                    Vec4::new(0.0, 1.0, 0.0, 0.0),
+                ),
+                Vec3::new(-1.0, 1.1, 0.0),
+                Vec3::Y,
+            ),
+            (
+                vertex(
+                    Vec3::new(0.0, 2.0, 0.1),
+                    Vec3::Z,
+                    Vec4::new(0.0, 1.0, 0.0, 0.0),
+                ),
+                Vec3::new(-1.0, 1.0, 0.1),
+                Vec3::Z,
+            ),
+            // Halfway at the joint, the normal turns 45 degrees
+            (
+                vertex(
+                    Vec3::new(0.1, 1.0, 0.0),
+                    Vec3::X,
+                    Vec4::new(0.5, 0.5, 0.0, 0.0),
+                ),
+                Vec3::new(0.05, 1.05, 0.0),
+                Vec3::new(1.0, 1.0, 0.0).normalize(),
+            ),
+        ];
+        for (vertex, pos, norm) in &cases {
+            let (skinned_pos, skinned_norm) = skin(vertex, &palette);
+            assert!(
+                skinned_pos.abs_diff_eq(pos.extend(1.0), 1e-6),
+                "{} skinned to {}",
+                vertex.pos,
+                skinned_pos
+            );
+            assert!(
+                skinned_norm.abs_diff_eq(*norm, 1e-6),
+                "{} skinned to {}",
+                vertex.norm,
+                skinned_norm
+            );
+        }
+    }
+}
@@ -0,0 +1,149 @@
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use glam::{Vec2, Vec3};
+use wavefront_obj::obj::{self, Primitive};
+
+use crate::mesh::{compute_normals, dedupe, Vertex};
+
+/// Error returned by `load_obj` and `parse_obj`.
+#[derive(Debug)]
+pub enum ObjError {
+    /// The file could not be read.
+    Io(io::Error),
+    /// The file is not valid Wavefront OBJ.
+    Parse { line: usize, message: String },
+    /// A face of OBJECT references a position, UV or normal INDEX, counted
+    /// from 1 as in the file, that the object does not define.
+    InvalidIndex { object: String, index: usize },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(err) => write!(f, "failed to read obj: {}", err),
+            ObjError::Parse { line, message } => {
+                write!(f, "failed to parse obj on line {}: {}", line, message)
+            }
+            ObjError::InvalidIndex { object, index } => {
+                write!(f, "object {:?} references missing vertex {}", object, index)
+            }
+        }
+    }
+}
+
+impl Error for ObjError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ObjError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ObjError {
+    fn from(err: io::Error) -> ObjError {
+        ObjError::Io(err)
+    }
+}
+
+/// Reads the Wavefront OBJ file at PATH, see `parse_obj`.
+pub fn load_obj<P: AsRef<Path>>(path: P) -> Result<(Vec<Vertex>, Vec<u32>), ObjError> {
+    let source = fs::read_to_string(path)?;
+    parse_obj(&source)
+}
+
+/// Parses the triangles of all objects in SOURCE, a Wavefront OBJ, into
+/// vertices and three indices per triangle, sharing vertices between
+/// triangles with `dedupe`. Polygons are triangulated, points and lines are
+/// skipped.
+///
+/// Triangles without normals get smooth normals from `compute_normals`,
+/// shared between the triangles without normals in the same object.
+/// Vertices without a UV get `(0, 0)`.
+pub fn parse_obj(source: &str) -> Result<(Vec<Vertex>, Vec<u32>), ObjError> {
+    let objset = obj::parse(source).map_err(|err| ObjError::Parse {
+        line: err.line_number,
+        message: err.message,
+    })?;
+
+    let mut vertices = Vec::new();
+    for object in &objset.objects {
+        let invalid_index = |index| ObjError::InvalidIndex {
+            object: object.name.clone(),
+            index: index + 1,
+        };
+        let positions: Vec<Vec3> = object
+            .vertices
+            .iter()
+            .map(|pos| Vec3::new(pos.x as f32, pos.y as f32, pos.z as f32))
+            .collect();
+
+        // Position indices of the triangles without normals and where their
+        // vertices are in VERTICES
+        let mut smooth_indices = Vec::new();
+        let mut smooth_vertices = Vec::new();
+
+        let shapes = object.geometry.iter().flat_map(|geom| &geom.shapes);
+        for shape in shapes {
+            let (a, b, c) = match shape.primitive {
+                Primitive::Triangle(a, b, c) => (a, b, c),
+                _ => continue,
+            };
+
+            let mut triangle = [Vertex {
+                pos: Vec3::ZERO,
+                norm: Vec3::ZERO,
+                uv: Vec2::ZERO,
+            }; 3];
+            let mut has_normals = true;
+
+            for (vertex, &(pos_index, uv_index, norm_index)) in triangle.iter_mut().zip(&[a, b, c])
+            {
+                vertex.pos = *positions
+                    .get(pos_index)
+                    .ok_or_else(|| invalid_index(pos_index))?;
+
+                if let Some(uv_index) = uv_index {
+                    let uv = object
+                        .tex_vertices
+                        .get(uv_index)
+                        .ok_or_else(|| invalid_index(uv_index))?;
+                    vertex.uv = Vec2::new(uv.u as f32, uv.v as f32);
+                }
+
+                match norm_index {
+                    Some(norm_index) => {
+                        let norm = object
+                            .normals
+                            .get(norm_index)
+                            .ok_or_else(|| invalid_index(norm_index))?;
+                        vertex.norm = Vec3::new(norm.x as f32, norm.y as f32, norm.z as f32);
+                    }
+                    None => has_normals = false,
+                }
+            }
+
+            // Normals of a triangle come either all from the file, or are
+            // all computed, so that it is lit consistently
+            if !has_normals {
+                smooth_indices.extend([a.0 as u32, b.0 as u32, c.0 as u32]);
+                smooth_vertices.extend(vertices.len()..vertices.len() + 3);
+            }
+
+            vertices.extend_from_slice(&triangle);
+        }
+
+        if !smooth_indices.is_empty() {
+            let normals = compute_normals(&positions, &smooth_indices);
+            for (&vertex, &pos_index) in smooth_vertices.iter().zip(&smooth_indices) {
+                vertices[vertex].norm = normals[pos_index as usize];
+            }
+        }
+    }
+
+    Ok(dedupe(&vertices))
+}
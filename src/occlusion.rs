@@ -0,0 +1,248 @@
+use glam::{Mat4, Vec3};
+
+use crate::bounds::Aabb;
+use crate::image::{DepthPixel, RenderTarget};
+use crate::{DepthConvention, PipelineOptions};
+
+/// Maximum stored depth of a depth attachment over blocks of growing size,
+/// halving the resolution at each level, to test many objects against the
+/// depth of occluders rendered beforehand, see `test_aabb`.
+///
+/// Occluders are usually rendered into a small depth attachment, e.g.
+/// 128x72 pixels, with the same projection as the scene.
+#[derive(Debug, Clone)]
+pub struct DepthPyramid {
+    levels: Vec<Level>,
+    depth_convention: DepthConvention,
+}
+
+/// Depths of one level of `DepthPyramid`, row by row in screen space, i.e.
+/// growing upwards.
+#[derive(Debug, Clone)]
+struct Level {
+    width: u32,
+    height: u32,
+    depth: Vec<f32>,
+}
+
+impl Level {
+    fn depth(&self, x: u32, y: u32) -> f32 {
+        self.depth[(y * self.width + x) as usize]
+    }
+}
+
+impl DepthPyramid {
+    /// Builds the pyramid of IMAGE_DEPTH, rendered by a pipeline with
+    /// OPTIONS.
+    pub fn new<D>(image_depth: &D, options: &PipelineOptions) -> DepthPyramid
+    where
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (width, height) = image_depth.dimensions();
+
+        let mut depth = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            let row = options.origin.row(y, height);
+            depth.extend((0..width).map(|x| image_depth.pixel(x, row).to_depth()));
+        }
+
+        let mut levels = vec![Level {
+            width,
+            height,
+            depth,
+        }];
+
+        // Each texel covers the 2x2 texels of the level below, or fewer on
+        // the last row and column of levels with odd dimensions
+        loop {
+            let below = levels.last().unwrap();
+            if below.width <= 1 && below.height <= 1 {
+                break;
+            }
+
+            let (width, height) = (below.width.div_ceil(2), below.height.div_ceil(2));
+            let mut depth = Vec::with_capacity((width * height) as usize);
+            for y in 0..height {
+                for x in 0..width {
+                    let (x0, y0) = (2 * x, 2 * y);
+                    let (x1, y1) = (
+                        (x0 + 1).min(below.width - 1),
+                        (y0 + 1).min(below.height - 1),
+                    );
+                    depth.push(
+                        below
+                            .depth(x0, y0)
+                            .max(below.depth(x1, y0))
+                            .max(below.depth(x0, y1))
+                            .max(below.depth(x1, y1)),
+                    );
+                }
+            }
+
+            levels.push(Level {
+                width,
+                height,
+                depth,
+            });
+        }
+
+        DepthPyramid {
+            levels,
+            depth_convention: options.depth_convention,
+        }
+    }
+
+    /// Returns false if an object within AABB is provably hidden behind the
+    /// depth stored in the pyramid, where TRANSFORM is the transform from
+    /// the space of AABB to clip space applied by the vertex shader.
+    ///
+    /// The test is conservative: it compares the nearest depth of the box
+    /// with the farthest depth stored over a rectangle of pixels covering
+    /// its projection, so objects can be reported visible when they are
+    /// hidden, but never the other way around. Boxes crossing the near
+    /// plane are always visible. Empty boxes and boxes entirely outside of
+    /// the screen or beyond the far plane cover no pixels and are hidden.
+    pub fn test_aabb(&self, transform: Mat4, aabb: &Aabb) -> bool {
+        if aabb.is_empty() {
+            return false;
+        }
+
+        let mut ndc_min = Vec3::splat(f32::INFINITY);
+        let mut ndc_max = Vec3::splat(f32::NEG_INFINITY);
+        for &corner in &aabb.corners() {
+            let clip = transform * corner.extend(1.0);
+            let near = match self.depth_convention {
+                DepthConvention::NegativeOneToOne => clip.z + clip.w,
+                DepthConvention::ZeroToOne => clip.z,
+            };
+            // Also true for NaN, which can't be proven hidden either
+            if !(clip.w > 0.0 && near >= 0.0) {
+                return true;
+            }
+
+            let ndc = clip.truncate() / clip.w;
+            ndc_min = ndc_min.min(ndc);
+            ndc_max = ndc_max.max(ndc);
+        }
+
+        if !ndc_min.is_finite() || !ndc_max.is_finite() {
+            return true;
+        }
+        let nearest = self.depth_convention.ndc_to_depth(ndc_min.z);
+        if nearest > 1.0 {
+            return false;
+        }
+
+        // Pixels are sampled at integer screen space positions. Widen the
+        // rectangle to the neighboring samples, covering the rounding of
+        // the rasterizer
+        let base = &self.levels[0];
+        let (width, height) = (base.width as f32, base.height as f32);
+        let minx = ((ndc_min.x + 1.0) * width / 2.0).floor();
+        let miny = ((ndc_min.y + 1.0) * height / 2.0).floor();
+        let maxx = ((ndc_max.x + 1.0) * width / 2.0).ceil();
+        let maxy = ((ndc_max.y + 1.0) * height / 2.0).ceil();
+        if maxx < 0.0 || maxy < 0.0 || minx > width - 1.0 || miny > height - 1.0 {
+            return false;
+        }
+
+        let minx = minx.max(0.0) as u32;
+        let miny = miny.max(0.0) as u32;
+        let maxx = maxx.min(width - 1.0) as u32;
+        let maxy = maxy.min(height - 1.0) as u32;
+
+        // Use the finest level where the rectangle spans at most 2x2
+        // texels, which bounds the texels read per test
+        let mut level = 0;
+        while level + 1 < self.levels.len()
+            && ((maxx >> level) - (minx >> level) > 1 || (maxy >> level) - (miny >> level) > 1)
+        {
+            level += 1;
+        }
+
+        let texels = &self.levels[level];
+        let mut farthest = f32::NEG_INFINITY;
+        for y in (miny >> level)..=(maxy >> level) {
+            for x in (minx >> level)..=(maxx >> level) {
+                farthest = farthest.max(texels.depth(x, y));
+            }
+        }
+
+        nearest <= farthest
+    }
+}
+
+/// Returns false if an object within AABB is provably hidden behind the
+/// depth stored in IMAGE_DEPTH, rendered by a pipeline with OPTIONS. See
+/// `DepthPyramid::test_aabb`, which is faster for testing many objects
+/// against the same depth.
+pub fn test_aabb<D>(
+    image_depth: &D,
+    options: &PipelineOptions,
+    transform: Mat4,
+    aabb: &Aabb,
+) -> bool
+where
+    D: RenderTarget,
+    D::Pixel: DepthPixel,
+{
+    DepthPyramid::new(image_depth, options).test_aabb(transform, aabb)
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+    use crate::image::Image;
+    use crate::shaders::{UnlitColor, UnlitColorAttribute};
+    use crate::Pipeline;
+
+    fn cube(center: Vec3) -> Aabb {
+        Aabb {
+            min: center - Vec3::splat(0.5),
+            max: center + Vec3::splat(0.5),
+        }
+    }
+
+    #[test]
+    fn box_behind_wall_is_occluded() {
+        // With a 90 degree field of view, the wall at z = -2 covers the
+        // screen left of NDC x = 0.25
+        let wall = [
+            Vec3::new(-3.0, -3.0, -2.0),
+            Vec3::new(0.5, -3.0, -2.0),
+            Vec3::new(0.5, 3.0, -2.0),
+            Vec3::new(-3.0, -3.0, -2.0),
+            Vec3::new(0.5, 3.0, -2.0),
+            Vec3::new(-3.0, 3.0, -2.0),
+        ];
+        let view_proj = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+
+        let options = PipelineOptions::default();
+        let pipeline = Pipeline::with_options(options);
+        let mut image_color = Image::new(32, 32);
+        let mut image_depth = Image::from_pixel_depth(32, 32, 1.0);
+        pipeline.triangles(
+            &UnlitColor::new(view_proj),
+            &UnlitColorAttribute::solid(&wall, Vec4::ONE),
+            &mut image_color,
+            &mut image_depth,
+        );
+        let pyramid = DepthPyramid::new(&image_depth, &options);
+
+        let hidden = cube(Vec3::new(-1.0, 0.0, -4.0));
+        assert!(!pyramid.test_aabb(view_proj, &hidden));
+        assert!(!test_aabb(&image_depth, &options, view_proj, &hidden));
+
+        // Peeks past the right edge of the wall
+        let peeking = cube(Vec3::new(1.2, 0.0, -4.0));
+        assert!(pyramid.test_aabb(view_proj, &peeking));
+        assert!(test_aabb(&image_depth, &options, view_proj, &peeking));
+
+        // In front of the wall
+        let front = cube(Vec3::new(-1.0, 0.0, -1.5));
+        assert!(pyramid.test_aabb(view_proj, &front));
+    }
+}
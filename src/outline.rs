@@ -0,0 +1,188 @@
+use glam::Vec4;
+
+use crate::image::{ColorTarget, DepthPixel, RenderTarget};
+use crate::shader::ShaderProgram;
+use crate::{
+    CompareFunc, Framebuffer, Pipeline, PipelineOptions, PipelineStats, StencilOp, StencilState,
+};
+
+/// Outline drawn around an object by `draw`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct OutlineParams {
+    /// Color of the outline, written like fragment colors, i.e. blended if
+    /// `PipelineOptions::blend` is set.
+    pub color: Vec4,
+    /// Width of the outline in pixels, regardless of the size and distance
+    /// of the object. Fractional widths round the corners of the outline
+    /// differently.
+    pub thickness_px: f32,
+    /// Stencil value marking the pixels of the object. The stencil
+    /// attachment should be cleared to another value beforehand.
+    pub stencil_reference: u8,
+}
+
+/// Draws the triangles in BUFFER with PIPELINE and SHADER, and then an
+/// outline of the pixels they covered to the first color attachment of
+/// FRAMEBUFFER, e.g. to highlight selected objects in editors.
+///
+/// The object marks the pixels it writes with `stencil_reference` in the
+/// stencil attachment, which FRAMEBUFFER must have. The outline covers the
+/// unmarked pixels within `thickness_px` of a marked one, so unlike drawing
+/// a scaled copy of the object, it doesn't depend on the mesh being closed
+/// or scaling uniformly. It is drawn over everything else, but only around
+/// the visible parts of the object. Objects drawn with the same reference
+/// before are outlined together.
+///
+/// Outline pixels are counted in `PipelineStats::pixels_written`.
+pub fn draw<S, C, D, T>(
+    pipeline: &Pipeline,
+    shader: &S,
+    buffer: &[S::Attribute],
+    framebuffer: &mut Framebuffer<C, D, T>,
+    params: &OutlineParams,
+) -> PipelineStats
+where
+    S: ShaderProgram,
+    C: ColorTarget,
+    D: RenderTarget,
+    D::Pixel: DepthPixel,
+    T: RenderTarget<Pixel = u32>,
+{
+    let mark = Pipeline::with_options(PipelineOptions {
+        stencil: Some(StencilState {
+            func: CompareFunc::Always,
+            reference: params.stencil_reference,
+            pass_op: StencilOp::Replace,
+            ..StencilState::default()
+        }),
+        ..*pipeline.options()
+    });
+    let mut stats = mark.draw(shader, buffer, framebuffer);
+
+    let (width, height) = framebuffer.dimensions();
+    let image_stencil = framebuffer
+        .stencil
+        .as_deref()
+        .expect("outline requires a stencil attachment");
+
+    let mut marked = Vec::with_capacity((width * height) as usize);
+    let (mut minx, mut miny, mut maxx, mut maxy) = (i64::MAX, i64::MAX, i64::MIN, i64::MIN);
+    for y in 0..height {
+        for x in 0..width {
+            let is_marked = image_stencil.pixel(x, y) as u8 == params.stencil_reference;
+            if is_marked {
+                minx = minx.min(i64::from(x));
+                miny = miny.min(i64::from(y));
+                maxx = maxx.max(i64::from(x));
+                maxy = maxy.max(i64::from(y));
+            }
+            marked.push(is_marked);
+        }
+    }
+
+    let image_color = match framebuffer.color.first_mut() {
+        Some(image_color) => image_color,
+        None => return stats,
+    };
+    if minx > maxx || params.thickness_px.is_nan() || params.thickness_px < 1.0 {
+        return stats;
+    }
+
+    // Offsets of the pixels within the thickness of a pixel, by distance
+    // between their centers
+    let (width, height) = (i64::from(width), i64::from(height));
+    let radius = (params.thickness_px.floor() as i64).min(width.max(height));
+    let offsets: Vec<(i64, i64)> = (-radius..=radius)
+        .flat_map(|dy| (-radius..=radius).map(move |dx| (dx, dy)))
+        .filter(|&(dx, dy)| ((dx * dx + dy * dy) as f32).sqrt() <= params.thickness_px)
+        .collect();
+
+    let is_marked = |x: i64, y: i64| {
+        (0..width).contains(&x) && (0..height).contains(&y) && marked[(y * width + x) as usize]
+    };
+
+    let encoding = pipeline.color_encoding();
+    for y in (miny - radius).max(0)..=(maxy + radius).min(height - 1) {
+        for x in (minx - radius).max(0)..=(maxx + radius).min(width - 1) {
+            if is_marked(x, y) || !offsets.iter().any(|&(dx, dy)| is_marked(x + dx, y + dy)) {
+                continue;
+            }
+
+            image_color.write_color(x as u32, y as u32, params.color, &encoding);
+            stats.pixels_written += 1;
+        }
+    }
+
+    stats
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::image::Image;
+    use crate::shaders::{UnlitColor, UnlitColorAttribute};
+
+    /// The outline is drawn in white around the red triangle, shown as `#`
+    /// and `o`.
+    #[test]
+    fn outline_golden() {
+        // Right triangle with the right angle at the bottom left, from
+        // pixel 3 to 8 on both axes
+        let positions = [
+            Vec3::new(-0.55, -0.55, 0.0),
+            Vec3::new(0.4, -0.55, 0.0),
+            Vec3::new(-0.55, 0.4, 0.0),
+        ];
+        let buffer = UnlitColorAttribute::solid(&positions, Vec4::new(1.0, 0.0, 0.0, 1.0));
+        let params = OutlineParams {
+            color: Vec4::ONE,
+            thickness_px: 1.5,
+            stencil_reference: 1,
+        };
+
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let mut image_color = Image::new(12, 12);
+        let mut image_depth = Image::from_pixel_depth(12, 12, 1.0);
+        let mut image_stencil = Image::from_pixel_stencil(12, 12, 0);
+        let mut images_color = [&mut image_color];
+        let mut framebuffer =
+            Framebuffer::new(&mut images_color, &mut image_depth).with_stencil(&mut image_stencil);
+        draw(
+            &pipeline,
+            &UnlitColor::identity(),
+            &buffer,
+            &mut framebuffer,
+            &params,
+        );
+
+        let mut actual = String::new();
+        for y in 0..12 {
+            for x in 0..12 {
+                actual.push(match image_color.pixel_rgba(x, y) {
+                    [255, 255, 255, 255] => '#',
+                    [255, 0, 0, 255] => 'o',
+                    [0, 0, 0, 0] => '.',
+                    rgba => panic!("unexpected color {:?}", rgba),
+                });
+            }
+            actual.push('\n');
+        }
+        let expected = "\
+............
+............
+..###.......
+..#o##......
+..#oo##.....
+..#ooo##....
+..#oooo##...
+..#ooooo##..
+..#oooooo#..
+..########..
+............
+............
+";
+        assert_eq!(actual, expected, "\n{}", actual);
+    }
+}
@@ -0,0 +1,362 @@
+use glam::Vec4;
+use rayon::prelude::*;
+
+use crate::image::{ColorPixel, DepthPixel, Image, Pixel, RenderTarget};
+use crate::shader::ShaderProgram;
+use crate::tile::{Bins, CoarseDepth};
+use crate::{Framebuffer, Pipeline, PipelineStats};
+
+/// Minimum number of vertices shaded by a rayon job.
+const VERTEX_BATCH: usize = 1024;
+
+impl Pipeline {
+    /// Like `draw`, but rasterizes the tiles on the rayon thread pool.
+    ///
+    /// Vertex shading runs in parallel first, then triangles are culled and
+    /// binned on the calling thread. Every tile renders its triangles in
+    /// submission order into its own copy of the attachments, which are
+    /// written back afterwards, so the output is identical to `draw`. Set
+    /// `PipelineOptions::single_threaded` to opt out.
+    pub fn draw_parallel<S, C, D, T>(
+        &self,
+        shader: &S,
+        buffer: &[S::Attribute],
+        framebuffer: &mut Framebuffer<C, D, T>,
+    ) -> PipelineStats
+    where
+        S: ShaderProgram + Sync,
+        S::Attribute: Sync,
+        S::Varying: Send + Sync,
+        C: RenderTarget,
+        C::Pixel: ColorPixel,
+        Image<C::Pixel>: Send,
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+        Image<D::Pixel>: Send,
+        T: RenderTarget<Pixel = u32>,
+    {
+        if self.options.single_threaded {
+            return self.draw(shader, buffer, framebuffer);
+        }
+
+        let (width, height) = framebuffer.dimensions();
+        self.check_framebuffer::<S, C, D, T>(framebuffer);
+
+        // Vertices are shaded in parallel, but triangles are set up and
+        // binned in submission order
+        let vertices: Vec<(Vec4, S::Varying)> = buffer[..buffer.len() / 3 * 3]
+            .par_iter()
+            .with_min_len(VERTEX_BATCH)
+            .map(|attribute| shader.vertex(attribute))
+            .collect();
+
+        let mut stats = PipelineStats::default();
+        let mut bins = Bins::new(width, height);
+        let mut vertices = vertices.into_iter();
+        let mut primitive = 0;
+        while let (Some((a, va)), Some((b, vb)), Some((c, vc))) =
+            (vertices.next(), vertices.next(), vertices.next())
+        {
+            self.setup_clipped(
+                (a, b, c),
+                (va, vb, vc),
+                width,
+                height,
+                &mut stats,
+                |screen, vars| bins.push(primitive, screen, vars),
+            );
+            primitive += 1;
+        }
+
+        let mut tiles: Vec<Tile<C::Pixel, D::Pixel>> = bins
+            .tiles()
+            .map(|(bounds, triangles)| {
+                let (minx, miny, maxx, maxy) = bounds;
+                let (tile_width, tile_height) = (maxx - minx + 1, maxy - miny + 1);
+
+                // Tiles are in screen space, but attachments may be stored
+                // flipped
+                let origin = self.options.origin;
+                let image_y = origin.row(miny, height).min(origin.row(maxy, height));
+                let rect = (minx, image_y, tile_width, tile_height);
+                let copy_words = |image: &Option<&mut T>| {
+                    image
+                        .as_deref()
+                        .map(|image| TileTarget::copy_from(image, rect))
+                };
+
+                Tile {
+                    bounds,
+                    triangles,
+                    stats: PipelineStats::default(),
+                    color: framebuffer
+                        .color
+                        .iter()
+                        .map(|image_color| TileTarget::copy_from(&**image_color, rect))
+                        .collect(),
+                    depth: TileTarget::copy_from(&*framebuffer.depth, rect),
+                    stencil: copy_words(&framebuffer.stencil),
+                    ids: copy_words(&framebuffer.ids),
+                    primitive_ids: copy_words(&framebuffer.primitive_ids),
+                    aux: copy_words(&framebuffer.aux),
+                    peel_depth: framebuffer
+                        .peel_depth
+                        .map(|image_peel| TileTarget::copy_from(image_peel, rect)),
+                }
+            })
+            .collect();
+
+        tiles.par_iter_mut().for_each(|tile| {
+            let mut color: Vec<_> = tile.color.iter_mut().collect();
+            let mut tile_framebuffer = Framebuffer {
+                color: &mut color,
+                depth: &mut tile.depth,
+                stencil: tile.stencil.as_mut(),
+                ids: tile.ids.as_mut(),
+                primitive_ids: tile.primitive_ids.as_mut(),
+                aux: tile.aux.as_mut(),
+                peel_depth: tile.peel_depth.as_ref(),
+            };
+
+            let mut coarse_depth = CoarseDepth::new(tile.bounds, self.options.origin);
+            for &i in tile.triangles {
+                let (primitive, screen, vars) = bins.triangle(i);
+                self.triangle(
+                    shader,
+                    &mut tile_framebuffer,
+                    &mut coarse_depth,
+                    &mut tile.stats,
+                    primitive,
+                    screen,
+                    vars,
+                );
+            }
+        });
+
+        for tile in &tiles {
+            for (image_color, tile_color) in framebuffer.color.iter_mut().zip(&tile.color) {
+                tile_color.copy_to(&mut **image_color);
+            }
+            tile.depth.copy_to(framebuffer.depth);
+            let words = [
+                (framebuffer.stencil.as_deref_mut(), &tile.stencil),
+                (framebuffer.ids.as_deref_mut(), &tile.ids),
+                (
+                    framebuffer.primitive_ids.as_deref_mut(),
+                    &tile.primitive_ids,
+                ),
+                (framebuffer.aux.as_deref_mut(), &tile.aux),
+            ];
+            for (image, tile_image) in words {
+                if let (Some(image), Some(tile_image)) = (image, tile_image) {
+                    tile_image.copy_to(image);
+                }
+            }
+
+            stats += tile.stats;
+        }
+
+        stats
+    }
+}
+
+/// Screen space tile with the triangles overlapping it and private copies of
+/// the attachments it covers.
+struct Tile<'a, P: ColorPixel, Q: DepthPixel> {
+    /// Inclusive min and max corners of the tile, see `Bins::tiles`.
+    bounds: (u32, u32, u32, u32),
+    /// Indices of the triangles overlapping the tile, see `Bins::triangle`.
+    triangles: &'a [usize],
+    /// Fragment counters of the tile.
+    stats: PipelineStats,
+    color: Vec<TileTarget<P>>,
+    depth: TileTarget<Q>,
+    stencil: Option<TileTarget<u32>>,
+    ids: Option<TileTarget<u32>>,
+    primitive_ids: Option<TileTarget<u32>>,
+    aux: Option<TileTarget<u32>>,
+    /// Read only, not written back.
+    peel_depth: Option<TileTarget<Q>>,
+}
+
+/// Copy of a rectangle of an attachment, addressed with the coordinates of
+/// the whole attachment, so that fragments are written exactly as they would
+/// be to the attachment itself, e.g. with the same dither pattern.
+struct TileTarget<P: Pixel> {
+    image: Image<P>,
+    x: u32,
+    y: u32,
+    dimensions: (u32, u32),
+}
+
+impl<P: Pixel> TileTarget<P> {
+    fn copy_from<T>(target: &T, (x, y, width, height): (u32, u32, u32, u32)) -> TileTarget<P>
+    where
+        T: RenderTarget<Pixel = P> + ?Sized,
+    {
+        let mut image = Image::from_pixel(width, height, P::CLEAR);
+        for ty in 0..height {
+            for tx in 0..width {
+                image.set_pixel(tx, ty, target.pixel(x + tx, y + ty));
+            }
+        }
+
+        TileTarget {
+            image,
+            x,
+            y,
+            dimensions: target.dimensions(),
+        }
+    }
+
+    fn copy_to<T>(&self, target: &mut T)
+    where
+        T: RenderTarget<Pixel = P> + ?Sized,
+    {
+        let (width, height) = self.image.dimensions();
+        for ty in 0..height {
+            for tx in 0..width {
+                target.set_pixel(self.x + tx, self.y + ty, self.image.pixel(tx, ty));
+            }
+        }
+    }
+}
+
+impl<P: Pixel> RenderTarget for TileTarget<P> {
+    type Pixel = P;
+
+    fn dimensions(&self) -> (u32, u32) {
+        self.dimensions
+    }
+
+    fn pixel(&self, x: u32, y: u32) -> P {
+        self.image.pixel(x - self.x, y - self.y)
+    }
+
+    fn set_pixel(&mut self, x: u32, y: u32, pixel: P) {
+        self.image.set_pixel(x - self.x, y - self.y, pixel);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec3;
+
+    use super::*;
+    use crate::image::BlendMode;
+    use crate::shaders::{UnlitColor, UnlitColorAttribute};
+    use crate::{CompareFunc, Origin, PipelineOptions, StencilOp, StencilState};
+
+    /// Overlapping translucent triangles of all sizes at varying depths,
+    /// some crossing the edges of the screen.
+    fn scene() -> Vec<UnlitColorAttribute> {
+        let mut state = 0x2545_f491_u32;
+        let mut random = move || {
+            state = state.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (state >> 8) as f32 / (1 << 24) as f32
+        };
+
+        let mut buffer = Vec::new();
+        for i in 0..200 {
+            let center = Vec3::new(
+                random() * 2.4 - 1.2,
+                random() * 2.4 - 1.2,
+                random() * 1.8 - 0.9,
+            );
+            let size = if i % 10 == 0 { 1.5 } else { 0.2 };
+            for _ in 0..3 {
+                let offset = Vec3::new(random() - 0.5, random() - 0.5, random() * 0.2 - 0.1);
+                let color = Vec4::new(random(), random(), random(), random() * 0.8 + 0.2);
+                buffer.push(UnlitColorAttribute::new(center + offset * size, color));
+            }
+        }
+        buffer
+    }
+
+    /// Attachments of a framebuffer spanning several tiles, with partial
+    /// tiles on the right and top.
+    #[derive(Debug, PartialEq)]
+    struct Attachments {
+        color: Image,
+        depth: Image,
+        stencil: Image,
+        ids: Image,
+        primitive_ids: Image,
+    }
+
+    impl Attachments {
+        fn new() -> Attachments {
+            let (width, height) = (150, 100);
+            Attachments {
+                color: Image::from_pixel_rgba(width, height, [30, 60, 90, 255]),
+                depth: Image::from_pixel_depth(width, height, 1.0),
+                stencil: Image::from_pixel_stencil(width, height, 0),
+                ids: Image::new(width, height),
+                primitive_ids: Image::new(width, height),
+            }
+        }
+
+        fn draw<F>(&mut self, draw: F) -> PipelineStats
+        where
+            F: FnOnce(&mut Framebuffer) -> PipelineStats,
+        {
+            let mut images_color = [&mut self.color];
+            let mut framebuffer = Framebuffer::new(&mut images_color, &mut self.depth)
+                .with_stencil(&mut self.stencil)
+                .with_ids(&mut self.ids)
+                .with_primitive_ids(&mut self.primitive_ids);
+            draw(&mut framebuffer)
+        }
+    }
+
+    #[test]
+    fn draw_parallel_matches_draw() {
+        let buffer = scene();
+        let shader = UnlitColor::identity();
+        for &origin in &[Origin::TopLeft, Origin::BottomLeft] {
+            for &effects in &[false, true] {
+                let mut pipeline = Pipeline::with_options(PipelineOptions {
+                    origin,
+                    dither: effects,
+                    blend: if effects {
+                        Some(BlendMode::Alpha)
+                    } else {
+                        None
+                    },
+                    srgb_framebuffer: effects,
+                    depth_func: CompareFunc::LessEqual,
+                    stencil: Some(StencilState {
+                        pass_op: StencilOp::IncrWrap,
+                        ..StencilState::default()
+                    }),
+                    ..PipelineOptions::default()
+                });
+
+                let mut serial = Attachments::new();
+                let mut parallel = Attachments::new();
+                // Draw twice, so that the second draw blends over and tests
+                // against what the first one wrote
+                for draw_id in 1..=2 {
+                    pipeline.options_mut().draw_id = draw_id;
+                    let serial_stats =
+                        serial.draw(|framebuffer| pipeline.draw(&shader, &buffer, framebuffer));
+                    let parallel_stats = parallel
+                        .draw(|framebuffer| pipeline.draw_parallel(&shader, &buffer, framebuffer));
+                    assert_eq!(
+                        parallel_stats, serial_stats,
+                        "origin {:?}, effects {}",
+                        origin, effects
+                    );
+                }
+
+                assert!(serial.ids.enumerate_pixels().any(|(_, _, id)| id == 2));
+                assert!(
+                    parallel == serial,
+                    "origin {:?}, effects {}",
+                    origin,
+                    effects
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use glam::{Mat4, Vec2, Vec3};
+
+/// Half line from ORIGIN along DIRECTION, which has unit length.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Ray {
+    pub origin: Vec3,
+    pub direction: Vec3,
+}
+
+/// Intersection of a `Ray` with a triangle, see `intersect_triangles`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct Hit {
+    /// Distance from the origin of the ray.
+    pub t: f32,
+    /// Index of the triangle, i.e. its first vertex divided by three.
+    pub triangle_index: u32,
+    /// Weights of the vertices of the triangle at the hit, in the space of
+    /// the positions, e.g. to interpolate UVs under the cursor.
+    pub barycentric: Vec3,
+    /// Whether the triangle is counter-clockwise as seen from the origin of
+    /// the ray, like front faces of the pipeline.
+    pub front_facing: bool,
+}
+
+/// Returns the ray through PIXEL of images with VIEWPORT dimensions, in the
+/// space INV_VIEW_PROJ maps clip space to, e.g. world space for the inverse
+/// of a view-projection matrix.
+///
+/// PIXEL is in image coordinates with row 0 at the top, like images
+/// rendered with the default `Origin`. Integer coordinates are where the
+/// pipeline samples pixels, so fractional cursor positions pick between
+/// them. The ray starts at NDC depth -1, the near plane of
+/// `DepthConvention::NegativeOneToOne` or just in front of the near plane
+/// of `DepthConvention::ZeroToOne`.
+pub fn ray_from_screen(pixel: Vec2, viewport: (u32, u32), inv_view_proj: Mat4) -> Ray {
+    let (width, height) = (viewport.0 as f32, viewport.1 as f32);
+    let ndc_x = 2.0 * pixel.x / width - 1.0;
+    let ndc_y = 2.0 * (height - 1.0 - pixel.y) / height - 1.0;
+
+    let near = inv_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, -1.0));
+    let far = inv_view_proj.project_point3(Vec3::new(ndc_x, ndc_y, 1.0));
+
+    Ray {
+        origin: near,
+        direction: (far - near).normalize(),
+    }
+}
+
+/// Returns the closest hit of RAY with the triangles of POSITIONS, three
+/// vertices each like the attributes passed to the pipeline, or None if it
+/// misses all of them. Both faces are hit, see `Hit::front_facing`.
+///
+/// Hits on edges shared by triangles are reported for the first of them.
+pub fn intersect_triangles(ray: &Ray, positions: &[Vec3]) -> Option<Hit> {
+    intersect_triangles_by(ray, positions, |&position| position)
+}
+
+/// Like `intersect_triangles`, but reads the positions of ATTRIBUTES with
+/// POSITION, e.g. the attributes of a draw call.
+pub fn intersect_triangles_by<A, F>(ray: &Ray, attributes: &[A], position: F) -> Option<Hit>
+where
+    F: Fn(&A) -> Vec3,
+{
+    let mut closest: Option<Hit> = None;
+    for (i, triangle) in attributes.chunks_exact(3).enumerate() {
+        let (a, b, c) = (
+            position(&triangle[0]),
+            position(&triangle[1]),
+            position(&triangle[2]),
+        );
+        if let Some(mut hit) = intersect_triangle(ray, a, b, c) {
+            if closest.is_none_or(|closest| hit.t < closest.t) {
+                hit.triangle_index = i as u32;
+                closest = Some(hit);
+            }
+        }
+    }
+
+    closest
+}
+
+/// Intersects RAY with triangle A, B, C with the Möller–Trumbore algorithm.
+fn intersect_triangle(ray: &Ray, a: Vec3, b: Vec3, c: Vec3) -> Option<Hit> {
+    let ab = b - a;
+    let ac = c - a;
+    let p = ray.direction.cross(ac);
+    let det = ab.dot(p);
+
+    // Parallel rays and degenerate triangles. The tests below are written
+    // to fail for NaN too
+    if det == 0.0 || !det.is_finite() {
+        return None;
+    }
+
+    let inv_det = 1.0 / det;
+    let ao = ray.origin - a;
+    let u = ao.dot(p) * inv_det;
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+
+    let q = ao.cross(ab);
+    let v = ray.direction.dot(q) * inv_det;
+    if !(v >= 0.0 && u + v <= 1.0) {
+        return None;
+    }
+
+    let t = ac.dot(q) * inv_det;
+    if !(t >= 0.0 && t.is_finite()) {
+        return None;
+    }
+
+    Some(Hit {
+        t,
+        triangle_index: 0,
+        barycentric: Vec3::new(1.0 - u - v, u, v),
+        // The determinant is the triple product of the edges with the
+        // reversed direction, positive for triangles facing the origin
+        front_facing: det > 0.0,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec4;
+
+    use super::*;
+    use crate::image::Image;
+    use crate::shaders::{UnlitColor, UnlitColorAttribute};
+    use crate::{Framebuffer, Pipeline, PipelineOptions};
+
+    #[test]
+    fn picks_rendered_triangle() {
+        // With a 90 degree field of view, the view plane at z = -2 spans
+        // [-2..2] on both axes
+        let positions = [
+            // Background, drawn first
+            Vec3::new(-8.0, -8.0, -4.0),
+            Vec3::new(8.0, -8.0, -4.0),
+            Vec3::new(0.0, 8.0, -4.0),
+            Vec3::new(-2.0, -2.0, -2.0),
+            Vec3::new(2.0, -2.0, -2.0),
+            Vec3::new(0.0, 2.0, -2.0),
+        ];
+        let view_proj = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_2, 1.0, 0.1, 10.0);
+
+        let pipeline = Pipeline::with_options(PipelineOptions::default());
+        let buffer = UnlitColorAttribute::solid(&positions, Vec4::ONE);
+        let mut image_color = Image::new(16, 16);
+        let mut image_depth = Image::from_pixel_depth(16, 16, 1.0);
+        let mut image_primitive_ids = Image::from_pixel(16, 16, u32::MAX);
+        let mut images_color = [&mut image_color];
+        let mut framebuffer = Framebuffer::new(&mut images_color, &mut image_depth)
+            .with_primitive_ids(&mut image_primitive_ids);
+        pipeline.draw(&UnlitColor::new(view_proj), &buffer, &mut framebuffer);
+
+        // Pixel 8, 8 is at NDC (0, -0.125), or (0, -0.25, -2) on the front
+        // triangle
+        let ray = ray_from_screen(Vec2::new(8.0, 8.0), (16, 16), view_proj.inverse());
+        let hit = intersect_triangles(&ray, &positions).unwrap();
+        assert_eq!(hit.triangle_index, image_primitive_ids.pixel(8, 8));
+        assert_eq!(hit.triangle_index, 1);
+        assert!(hit.front_facing);
+
+        let expected = Vec3::new(0.28125, 0.28125, 0.4375);
+        assert!(
+            hit.barycentric.abs_diff_eq(expected, 1e-4),
+            "{} != {}",
+            hit.barycentric,
+            expected
+        );
+        let point = ray.origin + ray.direction * hit.t;
+        assert!(point.abs_diff_eq(Vec3::new(0.0, -0.25, -2.0), 1e-4));
+    }
+}
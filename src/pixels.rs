@@ -0,0 +1,84 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::image::Image;
+
+/// Error of copying an image to a frame it doesn't fit.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum FrameError {
+    /// The frame doesn't hold exactly the expected number of bytes.
+    SizeMismatch { expected: usize, actual: usize },
+    /// The image placed at the offset extends past the edges of the frame.
+    OutOfBounds,
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::SizeMismatch { expected, actual } => {
+                write!(f, "frame has {} bytes, expected {}", actual, expected)
+            }
+            FrameError::OutOfBounds => write!(f, "image doesn't fit in the frame"),
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+/// Copies IMAGE to FRAME, e.g. `pixels::Pixels::frame_mut`, which holds RGBA
+/// bytes row by row from the top, and must be exactly as large as IMAGE.
+pub fn copy_to_pixels(image: &Image, frame: &mut [u8]) -> Result<(), FrameError> {
+    let expected = image.width() as usize * image.height() as usize * 4;
+    if frame.len() != expected {
+        return Err(FrameError::SizeMismatch {
+            expected,
+            actual: frame.len(),
+        });
+    }
+
+    let pixels = image.rows().flatten();
+    for (dst, &src) in frame.chunks_exact_mut(4).zip(pixels) {
+        dst.copy_from_slice(&src.to_le_bytes());
+    }
+
+    Ok(())
+}
+
+/// Like `copy_to_pixels`, but copies IMAGE to the rectangle of FRAME,
+/// FRAME_WIDTH pixels wide, with its top left corner at OFFSET, e.g. to
+/// letterbox it. The rest of the frame is kept.
+pub fn blit_to_pixels(
+    image: &Image,
+    frame: &mut [u8],
+    frame_width: u32,
+    offset: (u32, u32),
+) -> Result<(), FrameError> {
+    // The frame must be made of whole rows
+    let stride = frame_width as usize * 4;
+    let frame_height = frame.len().checked_div(stride).unwrap_or(0);
+    if frame.len() != frame_height * stride {
+        return Err(FrameError::SizeMismatch {
+            expected: frame_height * stride,
+            actual: frame.len(),
+        });
+    }
+
+    let (x, y) = (offset.0 as usize, offset.1 as usize);
+    if x + image.width() as usize > frame_width as usize
+        || y + image.height() as usize > frame_height
+    {
+        return Err(FrameError::OutOfBounds);
+    }
+    if image.width() == 0 {
+        return Ok(());
+    }
+
+    for (row, frame_row) in image.rows().zip(frame.chunks_exact_mut(stride).skip(y)) {
+        let dst = &mut frame_row[x * 4..(x + row.len()) * 4];
+        for (dst, &src) in dst.chunks_exact_mut(4).zip(row) {
+            dst.copy_from_slice(&src.to_le_bytes());
+        }
+    }
+
+    Ok(())
+}
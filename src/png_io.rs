@@ -0,0 +1,70 @@
+use std::fs;
+use std::io::{self, BufReader, BufWriter};
+use std::path::Path;
+
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+
+use crate::image::Image;
+
+impl Image {
+    /// Saves the image as 8-bit RGBA PNG.
+    pub fn save_png<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (width, height) = self.dimensions();
+
+        let writer = BufWriter::new(fs::File::create(path)?);
+        let mut encoder = Encoder::new(writer, width, height);
+        encoder.set_color(ColorType::RGBA);
+        encoder.set_depth(BitDepth::Eight);
+
+        let data: Vec<u8> = self.as_ref().iter().flat_map(|p| p.to_le_bytes()).collect();
+        let mut writer = encoder.write_header().map_err(invalid_data)?;
+        writer.write_image_data(&data).map_err(invalid_data)
+    }
+
+    /// Loads an 8-bit RGB or RGBA PNG. RGB images get opaque alpha. Other
+    /// color types and bit depths are reported as `InvalidData` errors.
+    pub fn load_png<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let reader = BufReader::new(fs::File::open(path)?);
+        let mut decoder = Decoder::new(reader);
+        decoder.set_transformations(Transformations::IDENTITY);
+
+        let (info, mut reader) = decoder.read_info().map_err(invalid_data)?;
+        if info.bit_depth != BitDepth::Eight {
+            let message = format!("unsupported PNG bit depth {:?}", info.bit_depth);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        let depth = match info.color_type {
+            ColorType::RGB => 3,
+            ColorType::RGBA => 4,
+            color_type => {
+                let message = format!("unsupported PNG color type {:?}", color_type);
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+        };
+
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data).map_err(invalid_data)?;
+
+        let buffer = data
+            .chunks_exact(depth)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], if depth == 4 { p[3] } else { 255 }]))
+            .collect();
+
+        Ok(Image::from_raw(buffer, info.width, info.height).expect("buffer matches dimensions"))
+    }
+
+    /// Like `load_png`, but converts the image to premultiplied alpha, see
+    /// `Image::premultiply_alpha`.
+    pub fn load_png_premultiplied<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let mut image = Image::load_png(path)?;
+        image.premultiply_alpha();
+        Ok(image)
+    }
+}
+
+fn invalid_data<E>(error: E) -> io::Error
+where
+    E: Into<Box<dyn std::error::Error + Send + Sync>>,
+{
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
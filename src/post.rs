@@ -0,0 +1,151 @@
+use glam::{Vec2, Vec4};
+#[cfg(feature = "rayon")]
+use rayon::prelude::*;
+
+use crate::image::{ColorEncoding, ColorPixel, Image, Pixel};
+
+/// Runs F for every pixel of SRC and writes the returned color to the same
+/// pixel of DST, e.g. for tone mapping or a vignette.
+///
+/// F receives the UV of the pixel center, with the convention of `Sampler`,
+/// so that it can sample SRC around it, and the color of the pixel. Colors
+/// are written to DST without sRGB encoding or dither.
+pub fn process<S, D, F>(src: &Image<S>, dst: &mut Image<D>, f: F)
+where
+    S: ColorPixel,
+    D: ColorPixel,
+    F: Fn(Vec2, Vec4) -> Vec4,
+{
+    check_dimensions(src, None, dst);
+
+    let dimensions = src.dimensions();
+    for (y, (src_row, dst_row)) in src.rows().zip(dst.rows_mut()).enumerate() {
+        process_row::<S, D, _>(
+            y as u32,
+            dimensions,
+            src_row,
+            None,
+            dst_row,
+            |uv, color, _| f(uv, color),
+        );
+    }
+}
+
+/// Like `process`, but F also receives the depth of the pixel stored in
+/// IMAGE_DEPTH, e.g. for fog or depth of field.
+pub fn process_with_depth<S, D, F>(src: &Image<S>, image_depth: &Image, dst: &mut Image<D>, f: F)
+where
+    S: ColorPixel,
+    D: ColorPixel,
+    F: Fn(Vec2, Vec4, f32) -> Vec4,
+{
+    check_dimensions(src, Some(image_depth), dst);
+
+    let dimensions = src.dimensions();
+    let rows = src.rows().zip(image_depth.rows()).zip(dst.rows_mut());
+    for (y, ((src_row, depth_row), dst_row)) in rows.enumerate() {
+        process_row::<S, D, _>(y as u32, dimensions, src_row, Some(depth_row), dst_row, &f);
+    }
+}
+
+/// Like `process`, but processes the rows on the rayon thread pool.
+#[cfg(feature = "rayon")]
+pub fn process_parallel<S, D, F>(src: &Image<S>, dst: &mut Image<D>, f: F)
+where
+    S: ColorPixel,
+    S::Storage: Sync,
+    D: ColorPixel,
+    D::Storage: Send,
+    F: Fn(Vec2, Vec4) -> Vec4 + Sync,
+{
+    check_dimensions(src, None, dst);
+
+    let dimensions = src.dimensions();
+    let src_rows: Vec<_> = src.rows().collect();
+    let dst_rows: Vec<_> = dst.rows_mut().collect();
+    src_rows
+        .into_par_iter()
+        .zip(dst_rows)
+        .enumerate()
+        .for_each(|(y, (src_row, dst_row))| {
+            process_row::<S, D, _>(
+                y as u32,
+                dimensions,
+                src_row,
+                None,
+                dst_row,
+                |uv, color, _| f(uv, color),
+            );
+        });
+}
+
+/// Like `process_with_depth`, but processes the rows on the rayon thread
+/// pool.
+#[cfg(feature = "rayon")]
+pub fn process_with_depth_parallel<S, D, F>(
+    src: &Image<S>,
+    image_depth: &Image,
+    dst: &mut Image<D>,
+    f: F,
+) where
+    S: ColorPixel,
+    S::Storage: Sync,
+    D: ColorPixel,
+    D::Storage: Send,
+    F: Fn(Vec2, Vec4, f32) -> Vec4 + Sync,
+{
+    check_dimensions(src, Some(image_depth), dst);
+
+    let dimensions = src.dimensions();
+    let src_rows: Vec<_> = src.rows().zip(image_depth.rows()).collect();
+    let dst_rows: Vec<_> = dst.rows_mut().collect();
+    src_rows.into_par_iter().zip(dst_rows).enumerate().for_each(
+        |(y, ((src_row, depth_row), dst_row))| {
+            process_row::<S, D, _>(y as u32, dimensions, src_row, Some(depth_row), dst_row, &f);
+        },
+    );
+}
+
+fn check_dimensions<S: Pixel, D: Pixel>(
+    src: &Image<S>,
+    image_depth: Option<&Image>,
+    dst: &Image<D>,
+) {
+    assert!(
+        src.dimensions() == dst.dimensions(),
+        "images must have equal dims"
+    );
+    if let Some(image_depth) = image_depth {
+        assert!(
+            src.dimensions() == image_depth.dimensions(),
+            "images must have equal dims"
+        );
+    }
+}
+
+/// Runs F for the pixels of row Y of images with DIMENSIONS. Without a
+/// depth row, F receives the far plane depth.
+fn process_row<S, D, F>(
+    y: u32,
+    (width, height): (u32, u32),
+    src_row: &[S::Storage],
+    depth_row: Option<&[u32]>,
+    dst_row: &mut [D::Storage],
+    f: F,
+) where
+    S: ColorPixel,
+    D: ColorPixel,
+    F: Fn(Vec2, Vec4, f32) -> Vec4,
+{
+    let encoding = ColorEncoding::default();
+    let v = (y as f32 + 0.5) / height as f32;
+
+    for (x, (src, dst)) in src_row.iter().zip(dst_row.iter_mut()).enumerate() {
+        let x = x as u32;
+        let uv = Vec2::new((x as f32 + 0.5) / width as f32, v);
+        let depth = depth_row.map_or(1.0, |depth_row| f32::from_bits(depth_row[x as usize]));
+
+        let color = f(uv, S::decode(*src).to_color(), depth);
+        *dst = D::from_color(color, x, y, &encoding).encode();
+    }
+}
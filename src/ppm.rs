@@ -0,0 +1,241 @@
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use crate::image::Image;
+
+impl Image {
+    /// Saves the image as binary PPM (P6). PPM has no alpha channel, so
+    /// alpha is dropped, see `save_pam` to keep it.
+    pub fn save_ppm<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (width, height) = self.dimensions();
+
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        write!(writer, "P6\n{} {}\n255\n", width, height)?;
+        for y in 0..height {
+            for x in 0..width {
+                let [r, g, b, _] = self.pixel_rgba(x, y);
+                writer.write_all(&[r, g, b])?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    /// Saves the image as PAM (P7) with RGBA tuples, keeping alpha.
+    pub fn save_pam<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let (width, height) = self.dimensions();
+
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        write!(
+            writer,
+            "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n",
+            width, height,
+        )?;
+        for y in 0..height {
+            for x in 0..width {
+                writer.write_all(&self.pixel_rgba(x, y))?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    /// Saves the depth image as 16-bit binary PGM (P5), mapping depth MIN to
+    /// black and MAX to white. Depths outside the range are clamped.
+    pub fn save_depth_pgm<P: AsRef<Path>>(&self, path: P, min: f32, max: f32) -> io::Result<()> {
+        let (width, height) = self.dimensions();
+
+        let mut writer = BufWriter::new(fs::File::create(path)?);
+        write!(writer, "P5\n{} {}\n65535\n", width, height)?;
+        for y in 0..height {
+            for x in 0..width {
+                let t = (self.pixel_depth(x, y) - min) / (max - min);
+                let value = (t.clamp(0.0, 1.0) * 65535.0).round() as u16;
+                writer.write_all(&value.to_be_bytes())?;
+            }
+        }
+
+        writer.flush()
+    }
+
+    /// Loads an 8-bit binary PPM (P6) or an RGB or RGBA PAM (P7). Images
+    /// without alpha get opaque alpha.
+    pub fn load_ppm<P: AsRef<Path>>(path: P) -> io::Result<Image> {
+        let data = fs::read(path)?;
+        let mut header = Header {
+            data: &data,
+            position: 0,
+        };
+
+        let (width, height, depth, maxval) = match header.token()? {
+            b"P6" => {
+                let width = header.number()?;
+                let height = header.number()?;
+                let maxval = header.number()?;
+                (width, height, 3, maxval)
+            }
+            b"P7" => header.pam()?,
+            _ => return Err(invalid_data("not a binary PPM or PAM file")),
+        };
+        // A single whitespace byte separates the header from the raster
+        header.position += 1;
+
+        if maxval != 255 {
+            return Err(invalid_data("only 8-bit images are supported"));
+        }
+        if depth != 3 && depth != 4 {
+            return Err(invalid_data("only RGB and RGBA images are supported"));
+        }
+
+        // The dimensions come from the file, so they can overflow
+        let len = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(depth))
+            .ok_or_else(|| invalid_data("image too large"))?;
+        let end = header
+            .position
+            .checked_add(len)
+            .ok_or_else(|| invalid_data("image too large"))?;
+        let raster = data
+            .get(header.position..end)
+            .ok_or_else(|| invalid_data("truncated raster"))?;
+
+        let buffer = raster
+            .chunks_exact(depth)
+            .map(|p| u32::from_le_bytes([p[0], p[1], p[2], if depth == 4 { p[3] } else { 255 }]))
+            .collect();
+
+        Ok(Image::from_raw(buffer, width, height).expect("buffer matches dimensions"))
+    }
+}
+
+struct Header<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Header<'a> {
+    /// Returns the next whitespace separated token, skipping comments.
+    fn token(&mut self) -> io::Result<&'a [u8]> {
+        loop {
+            match self.data.get(self.position) {
+                Some(b'#') => {
+                    while !matches!(self.data.get(self.position), Some(b'\n') | None) {
+                        self.position += 1;
+                    }
+                }
+                Some(c) if c.is_ascii_whitespace() => self.position += 1,
+                Some(_) => break,
+                None => return Err(invalid_data("truncated header")),
+            }
+        }
+
+        let start = self.position;
+        while matches!(self.data.get(self.position), Some(c) if !c.is_ascii_whitespace()) {
+            self.position += 1;
+        }
+
+        Ok(&self.data[start..self.position])
+    }
+
+    fn number(&mut self) -> io::Result<u32> {
+        let token = self.token()?;
+        std::str::from_utf8(token)
+            .ok()
+            .and_then(|t| t.parse().ok())
+            .ok_or_else(|| invalid_data("invalid number in header"))
+    }
+
+    /// Parses the PAM header after the magic number, returning width,
+    /// height, depth and maxval.
+    fn pam(&mut self) -> io::Result<(u32, u32, usize, u32)> {
+        let (mut width, mut height, mut depth, mut maxval) = (None, None, None, None);
+        loop {
+            match self.token()? {
+                b"WIDTH" => width = Some(self.number()?),
+                b"HEIGHT" => height = Some(self.number()?),
+                b"DEPTH" => depth = Some(self.number()? as usize),
+                b"MAXVAL" => maxval = Some(self.number()?),
+                b"TUPLTYPE" => {
+                    self.token()?;
+                }
+                b"ENDHDR" => break,
+                _ => return Err(invalid_data("unknown PAM header field")),
+            }
+        }
+
+        match (width, height, depth, maxval) {
+            (Some(width), Some(height), Some(depth), Some(maxval)) => {
+                Ok((width, height, depth, maxval))
+            }
+            _ => Err(invalid_data("incomplete PAM header")),
+        }
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::path::PathBuf;
+    use std::process;
+
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        env::temp_dir().join(format!("rusterizer-{}-{}", process::id(), name))
+    }
+
+    fn test_image() -> Image {
+        let mut image = Image::new(5, 3);
+        for (i, (x, y)) in (0..3).flat_map(|y| (0..5).map(move |x| (x, y))).enumerate() {
+            let v = (i * 17) as u8;
+            image.set_pixel_rgba(x, y, [v, 255 - v, v / 2, v.wrapping_mul(3)]);
+        }
+
+        image
+    }
+
+    #[test]
+    fn pam_roundtrip() {
+        let image = test_image();
+        let path = temp_path("roundtrip.pam");
+        image.save_pam(&path).unwrap();
+        let loaded = Image::load_ppm(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap(), image);
+    }
+
+    #[test]
+    fn ppm_roundtrip_drops_alpha() {
+        let image = test_image();
+        let path = temp_path("roundtrip.ppm");
+        image.save_ppm(&path).unwrap();
+        let loaded = Image::load_ppm(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        for y in 0..3 {
+            for x in 0..5 {
+                let [r, g, b, _] = image.pixel_rgba(x, y);
+                assert_eq!(loaded.pixel_rgba(x, y), [r, g, b, 255]);
+            }
+        }
+    }
+
+    #[test]
+    fn overflowing_dimensions_are_invalid() {
+        // 2^31 x 2^31 RGBA pixels wrap to 0 bytes in 64 bits
+        let path = temp_path("overflow.pam");
+        let header = "P7\nWIDTH 2147483648\nHEIGHT 2147483648\nDEPTH 4\nMAXVAL 255\nENDHDR\n";
+        fs::write(&path, header).unwrap();
+        let loaded = Image::load_ppm(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(loaded.unwrap_err().kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,60 @@
+use std::num::NonZeroU32;
+
+use raw_window_handle::{HasDisplayHandle, HasWindowHandle};
+use softbuffer::{Context, SoftBufferError, Surface};
+
+use crate::image::{rgba_to_bgra, Image};
+
+/// Presents images in a window through a softbuffer surface.
+///
+/// D and W are usually the same shared window handle, e.g. an
+/// `Rc<winit::window::Window>`.
+pub struct Presenter<D, W> {
+    surface: Surface<D, W>,
+    dimensions: (u32, u32),
+}
+
+impl<D: HasDisplayHandle, W: HasWindowHandle> Presenter<D, W> {
+    /// Creates a surface for WINDOW on DISPLAY.
+    pub fn new(display: D, window: W) -> Result<Presenter<D, W>, SoftBufferError> {
+        let context = Context::new(display)?;
+        let surface = Surface::new(&context, window)?;
+
+        Ok(Presenter {
+            surface,
+            dimensions: (0, 0),
+        })
+    }
+
+    /// Copies IMAGE to the window and presents it. The surface is resized to
+    /// the image first if their dimensions differ, so the image should be
+    /// rendered at the inner size of the window.
+    ///
+    /// Does nothing for images with zero width or height, e.g. while the
+    /// window is minimized.
+    pub fn present(&mut self, image: &Image) -> Result<(), SoftBufferError> {
+        let (width, height) = match (
+            NonZeroU32::new(image.width()),
+            NonZeroU32::new(image.height()),
+        ) {
+            (Some(width), Some(height)) => (width, height),
+            _ => return Ok(()),
+        };
+
+        if self.dimensions != image.dimensions() {
+            self.surface.resize(width, height)?;
+            self.dimensions = image.dimensions();
+        }
+
+        // The surface buffer is written in place, so presenting doesn't
+        // allocate
+        let mut buffer = self.surface.buffer_mut()?;
+        let pixels = image.rows().flatten();
+        for (dst, &src) in buffer.iter_mut().zip(pixels) {
+            // Softbuffer expects 0RGB words, the top byte must be zero
+            *dst = rgba_to_bgra(src) & 0x00ff_ffff;
+        }
+
+        buffer.present()
+    }
+}
@@ -0,0 +1,218 @@
+use glam::{Vec3, Vec4};
+
+use crate::{CompareFunc, DepthConvention, EdgeFunctions};
+
+/// Pixels of an aligned 2x2 quad relative to its lower left pixel. Bit I of
+/// the masks of a `Quad` refers to pixel I.
+pub const QUAD_PIXELS: [(u32, u32); 4] = [(0, 0), (1, 0), (0, 1), (1, 1)];
+
+/// Fixed-function state of the pixels of a 2x2 quad, see `QUAD_PIXELS`.
+///
+/// With the `simd` feature, the four pixels are evaluated at once in the
+/// lanes of `Vec4`s, otherwise one at a time. Both compute the same values.
+pub struct Quad {
+    /// Bit I is set if pixel I is inside the triangle and its depth is
+    /// within [0..1].
+    pub coverage: u32,
+    /// Barycentric coordinates of each pixel.
+    pub bc: [Vec3; 4],
+    /// Depth of each pixel, remapped from NDC to [0..1] by the
+    /// `DepthConvention`.
+    pub depth: Vec4,
+}
+
+// Both paths are always compiled, so that tests can compare them
+impl Quad {
+    /// Evaluates the quad whose lower left pixel has the edge function
+    /// values VALUE. Z holds the NDC depth of the vertices in CONVENTION.
+    pub fn new(edges: &EdgeFunctions, value: Vec3, z: Vec3, convention: DepthConvention) -> Quad {
+        if cfg!(feature = "simd") {
+            Quad::new_lanes(edges, value, z, convention)
+        } else {
+            Quad::new_scalar(edges, value, z, convention)
+        }
+    }
+
+    /// Returns the mask of pixels whose depth compares to the STORED depth
+    /// by FUNC.
+    pub fn depth_test(&self, stored: Vec4, func: CompareFunc) -> u32 {
+        if cfg!(feature = "simd") {
+            self.depth_test_lanes(stored, func)
+        } else {
+            self.depth_test_scalar(stored, func)
+        }
+    }
+
+    fn new_scalar(
+        edges: &EdgeFunctions,
+        value: Vec3,
+        z: Vec3,
+        convention: DepthConvention,
+    ) -> Quad {
+        let [offsets_a, offsets_b, offsets_c] = edges.quad_offsets;
+        let mut quad = Quad {
+            coverage: 0,
+            bc: [Vec3::ZERO; 4],
+            depth: Vec4::ZERO,
+        };
+
+        for i in 0..QUAD_PIXELS.len() {
+            let edge = Vec3::new(
+                value.x + offsets_a[i],
+                value.y + offsets_b[i],
+                value.z + offsets_c[i],
+            );
+            let bc = edge * edges.inv_area;
+            let depth = convention.ndc_to_depth(z.x * bc.x + z.y * bc.y + z.z * bc.z);
+            if bc.x >= 0.0 && bc.y >= 0.0 && bc.z >= 0.0 && (0.0..=1.0).contains(&depth) {
+                quad.coverage |= 1 << i;
+            }
+
+            quad.bc[i] = bc;
+            quad.depth[i] = depth;
+        }
+
+        quad
+    }
+
+    fn new_lanes(edges: &EdgeFunctions, value: Vec3, z: Vec3, convention: DepthConvention) -> Quad {
+        let [offsets_a, offsets_b, offsets_c] = edges.quad_offsets;
+        let inv_area = Vec4::splat(edges.inv_area);
+
+        let bc_a = (Vec4::splat(value.x) + offsets_a) * inv_area;
+        let bc_b = (Vec4::splat(value.y) + offsets_b) * inv_area;
+        let bc_c = (Vec4::splat(value.z) + offsets_c) * inv_area;
+
+        let depth = Vec4::splat(z.x) * bc_a + Vec4::splat(z.y) * bc_b + Vec4::splat(z.z) * bc_c;
+        let (scale, bias) = convention.remap();
+        let depth = depth * scale + Vec4::splat(bias);
+
+        let inside = bc_a.cmpge(Vec4::ZERO) & bc_b.cmpge(Vec4::ZERO) & bc_c.cmpge(Vec4::ZERO);
+        let coverage = inside & depth.cmpge(Vec4::ZERO) & depth.cmple(Vec4::ONE);
+
+        Quad {
+            coverage: coverage.bitmask(),
+            bc: [
+                Vec3::new(bc_a.x, bc_b.x, bc_c.x),
+                Vec3::new(bc_a.y, bc_b.y, bc_c.y),
+                Vec3::new(bc_a.z, bc_b.z, bc_c.z),
+                Vec3::new(bc_a.w, bc_b.w, bc_c.w),
+            ],
+            depth,
+        }
+    }
+
+    fn depth_test_scalar(&self, stored: Vec4, func: CompareFunc) -> u32 {
+        let mut mask = 0;
+        for i in 0..QUAD_PIXELS.len() {
+            if func.compare(self.depth[i], stored[i]) {
+                mask |= 1 << i;
+            }
+        }
+
+        mask
+    }
+
+    fn depth_test_lanes(&self, stored: Vec4, func: CompareFunc) -> u32 {
+        match func {
+            CompareFunc::Never => 0,
+            CompareFunc::Less => self.depth.cmplt(stored).bitmask(),
+            CompareFunc::Equal => self.depth.cmpeq(stored).bitmask(),
+            CompareFunc::LessEqual => self.depth.cmple(stored).bitmask(),
+            CompareFunc::Greater => self.depth.cmpgt(stored).bitmask(),
+            CompareFunc::NotEqual => self.depth.cmpne(stored).bitmask(),
+            CompareFunc::GreaterEqual => self.depth.cmpge(stored).bitmask(),
+            CompareFunc::Always => 0b1111,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::Vec2;
+
+    use super::*;
+
+    const FUNCS: [CompareFunc; 8] = [
+        CompareFunc::Never,
+        CompareFunc::Less,
+        CompareFunc::Equal,
+        CompareFunc::LessEqual,
+        CompareFunc::Greater,
+        CompareFunc::NotEqual,
+        CompareFunc::GreaterEqual,
+        CompareFunc::Always,
+    ];
+
+    /// Evaluates all quads of the bounding box of triangle A, B, C both
+    /// ways and compares them bit for bit.
+    fn assert_paths_equal(a: Vec3, b: Vec3, c: Vec3, convention: DepthConvention) {
+        let (a2, b2, c2) = (a.truncate(), b.truncate(), c.truncate());
+        let edges = EdgeFunctions::new(a2, b2, c2, Vec2::ZERO).unwrap();
+        let z = Vec3::new(a.z, b.z, c.z);
+
+        let max = a.max(b).max(c);
+        let mut covered = 0;
+        for qy in (0..=max.y as u32 + 1).step_by(2) {
+            for qx in (0..=max.x as u32 + 1).step_by(2) {
+                let value = edges.value + edges.dx * qx as f32 + edges.dy * qy as f32;
+                let scalar = Quad::new_scalar(&edges, value, z, convention);
+                let lanes = Quad::new_lanes(&edges, value, z, convention);
+
+                assert_eq!(scalar.coverage, lanes.coverage, "quad ({}, {})", qx, qy);
+                for i in 0..QUAD_PIXELS.len() {
+                    assert_eq!(scalar.depth[i].to_bits(), lanes.depth[i].to_bits());
+                    for axis in 0..3 {
+                        assert_eq!(scalar.bc[i][axis].to_bits(), lanes.bc[i][axis].to_bits());
+                    }
+                }
+
+                // Stored depth equal to the fragments, and just around them
+                for &offset in &[0.0, 1e-7, -1e-7, 0.25] {
+                    let stored = scalar.depth + Vec4::splat(offset);
+                    for &func in &FUNCS {
+                        assert_eq!(
+                            scalar.depth_test_scalar(stored, func),
+                            lanes.depth_test_lanes(stored, func),
+                        );
+                    }
+                }
+
+                covered += scalar.coverage.count_ones();
+            }
+        }
+
+        assert!(covered > 0);
+    }
+
+    #[test]
+    fn lanes_match_scalar() {
+        for &convention in &[
+            DepthConvention::NegativeOneToOne,
+            DepthConvention::ZeroToOne,
+        ] {
+            // Pixel centers exactly on the edges and vertices, where the
+            // tie-break decides coverage
+            assert_paths_equal(
+                Vec3::new(0.0, 0.0, -0.5),
+                Vec3::new(8.0, 0.0, 0.25),
+                Vec3::new(0.0, 8.0, 0.9),
+                convention,
+            );
+            assert_paths_equal(
+                Vec3::new(2.0, 1.0, 0.0),
+                Vec3::new(10.0, 9.0, 0.0),
+                Vec3::new(2.0, 9.0, 0.0),
+                convention,
+            );
+            // The other winding, with fractional vertices and depth leaving
+            // [0..1]
+            assert_paths_equal(
+                Vec3::new(0.3, 0.7, -1.5),
+                Vec3::new(3.1, 12.9, 0.6),
+                Vec3::new(13.6, 4.2, 1.4),
+                convention,
+            );
+        }
+    }
+}
@@ -0,0 +1,155 @@
+use glam::{Mat4, Vec4};
+
+use crate::DepthConvention;
+
+/// Returns the transform mirroring points about PLANE, whose points P have
+/// `plane.truncate().dot(P) + plane.w == 0`, e.g. `Vec4::new(0.0, 1.0, 0.0,
+/// 0.0)` for a floor at Y = 0.
+///
+/// Rendering the scene with the view matrix multiplied by the reflection,
+/// i.e. `view * reflection_matrix(plane)`, draws its mirror image, e.g. into
+/// a texture sampled by the mirror. Mirroring reverses the winding of
+/// triangles, so `CullFace::Front` culls the back faces of the reflection.
+pub fn reflection_matrix(plane: Vec4) -> Mat4 {
+    let plane = plane / plane.truncate().length();
+    let (n, d) = (plane.truncate(), plane.w);
+
+    Mat4::from_cols(
+        Vec4::X - 2.0 * n.x * n.extend(0.0),
+        Vec4::Y - 2.0 * n.y * n.extend(0.0),
+        Vec4::Z - 2.0 * n.z * n.extend(0.0),
+        (-2.0 * d * n).extend(1.0),
+    )
+}
+
+/// Returns the transform of the plane with components PLANE, see
+/// `reflection_matrix`, to the space TRANSFORM maps to, e.g. from world to
+/// view space for `oblique_projection`.
+pub fn transform_plane(transform: Mat4, plane: Vec4) -> Vec4 {
+    // Planes transform by the inverse transpose, so that they still contain
+    // the transformed points
+    transform.inverse().transpose() * plane
+}
+
+/// Returns PROJ with its near plane replaced by the view space CLIP_PLANE,
+/// so that points P behind it, with `clip_plane.truncate().dot(P) +
+/// clip_plane.w < 0`, fall outside of the view volume, e.g. to clip the
+/// geometry on the far side of a mirror when rendering its reflection.
+///
+/// This is the oblique near plane technique by Eric Lengyel: it costs
+/// nothing per vertex, but the far plane tilts to fit the near plane,
+/// which lowers depth precision. The camera must be on the clipped side of
+/// CLIP_PLANE, as with reflected cameras, and PROJ must produce NDC depth
+/// in the [-1..1] range. See `oblique_projection_with_depth_convention` for
+/// other projections.
+pub fn oblique_projection(proj: Mat4, clip_plane: Vec4) -> Mat4 {
+    oblique_projection_with_depth_convention(proj, clip_plane, DepthConvention::NegativeOneToOne)
+}
+
+/// Like `oblique_projection`, but for PROJ producing NDC depth in
+/// CONVENTION.
+pub fn oblique_projection_with_depth_convention(
+    proj: Mat4,
+    clip_plane: Vec4,
+    convention: DepthConvention,
+) -> Mat4 {
+    // The corner of the view volume opposite to the plane, which the far
+    // plane must still contain
+    let corner = proj.inverse() * Vec4::new(clip_plane.x.signum(), clip_plane.y.signum(), 1.0, 1.0);
+
+    // Clip space z is the row of the projection that the near plane is
+    // extracted from, see `Frustum::with_depth_convention`
+    let mut rows = proj.transpose();
+    rows.z_axis = match convention {
+        DepthConvention::NegativeOneToOne => {
+            clip_plane * (2.0 / clip_plane.dot(corner)) - rows.w_axis
+        }
+        DepthConvention::ZeroToOne => clip_plane * (1.0 / clip_plane.dot(corner)),
+    };
+
+    rows.transpose()
+}
+
+#[cfg(test)]
+mod tests {
+    use glam::{Vec2, Vec3};
+
+    use super::*;
+    use crate::image::Image;
+    use crate::shaders::{UnlitColor, UnlitColorAttribute};
+    use crate::{Pipeline, PipelineOptions, MAX_CLIP_PLANES};
+
+    fn quad(min: Vec2, max: Vec2, color: Vec4) -> Vec<UnlitColorAttribute> {
+        let positions = [
+            Vec3::new(min.x, min.y, 0.0),
+            Vec3::new(max.x, min.y, 0.0),
+            Vec3::new(max.x, max.y, 0.0),
+            Vec3::new(min.x, min.y, 0.0),
+            Vec3::new(max.x, max.y, 0.0),
+            Vec3::new(min.x, max.y, 0.0),
+        ];
+        UnlitColorAttribute::solid(&positions, color)
+    }
+
+    /// Renders the reflection of a quad standing half below the floor,
+    /// green above and red below it, and returns the number of green and
+    /// red pixels. The red half stops short of the floor, so that none of
+    /// it lies exactly on the clipping plane.
+    fn render_reflection(
+        clip_planes: [Option<Vec4>; MAX_CLIP_PLANES],
+        proj: Mat4,
+        view: Mat4,
+    ) -> (usize, usize) {
+        let mut buffer = quad(Vec2::new(-0.5, 0.0), Vec2::new(0.5, 1.0), Vec4::Y + Vec4::W);
+        buffer.extend(quad(
+            Vec2::new(-0.5, -1.0),
+            Vec2::new(0.5, -0.02),
+            Vec4::X + Vec4::W,
+        ));
+
+        let pipeline = Pipeline::with_options(PipelineOptions {
+            clip_planes,
+            ..PipelineOptions::default()
+        });
+        let mut image_color = Image::new(32, 32);
+        let mut image_depth = Image::from_pixel_depth(32, 32, 1.0);
+        pipeline.triangles(
+            &UnlitColor::new(proj * view),
+            &buffer,
+            &mut image_color,
+            &mut image_depth,
+        );
+
+        let count = |color| {
+            image_color
+                .enumerate_pixels_rgba()
+                .filter(|&(_, _, rgba)| rgba == color)
+                .count()
+        };
+        (count([0, 255, 0, 255]), count([255, 0, 0, 255]))
+    }
+
+    #[test]
+    fn reflection_clips_geometry_behind_mirror() {
+        let floor = Vec4::Y;
+        let proj = Mat4::perspective_rh_gl(std::f32::consts::FRAC_PI_3, 1.0, 0.1, 20.0);
+        let view = Mat4::look_at_rh(Vec3::new(0.0, 2.0, 4.0), Vec3::ZERO, Vec3::Y);
+        let reflected_view = view * reflection_matrix(floor);
+
+        // Without clipping, the part below the floor shows up in the
+        // reflection
+        let (green, red) = render_reflection([None; MAX_CLIP_PLANES], proj, reflected_view);
+        assert!(green > 0 && red > 0);
+
+        let clip_plane = transform_plane(proj * reflected_view, floor);
+        let clip_planes = [Some(clip_plane), None, None, None];
+        let (clipped_green, red) = render_reflection(clip_planes, proj, reflected_view);
+        assert_eq!((clipped_green, red), (green, 0));
+
+        let oblique = oblique_projection(proj, transform_plane(reflected_view, floor));
+        let (oblique_green, red) =
+            render_reflection([None; MAX_CLIP_PLANES], oblique, reflected_view);
+        assert_eq!(red, 0);
+        assert!(oblique_green > green / 2);
+    }
+}
@@ -1,17 +1,301 @@
-use glam::{Vec2, Vec3, Vec4};
+use std::marker::PhantomData;
+use std::ops::{Deref, DerefMut};
+
+use glam::{Mat3, Mat4, Quat, Vec2, Vec3, Vec4};
+
+use crate::image::{ColorEncoding, ColorTarget};
 
 pub trait Smooth {
     fn interpolate(a: &Self, b: &Self, c: &Self, bc: Vec3) -> Self;
+
+    /// Interpolates with perspective-corrected barycentric coordinates
+    /// `bc_persp`, used when the pipeline has `perspective_correct` enabled.
+    /// The screen-space coordinates `bc_screen` are passed along for types
+    /// that opt out of the correction, like `NoPerspective`.
+    ///
+    /// Composite varyings containing `Flat` or `NoPerspective` fields must
+    /// forward this to their fields, otherwise the fields are interpolated
+    /// with `bc_persp`.
+    fn interpolate_perspective(
+        a: &Self,
+        b: &Self,
+        c: &Self,
+        _bc_screen: Vec3,
+        bc_persp: Vec3,
+    ) -> Self
+    where
+        Self: Sized,
+    {
+        Self::interpolate(a, b, c, bc_persp)
+    }
 }
 
 pub trait ShaderProgram {
     type Attribute;
-    type Varying: Default + Smooth;
-    // type Fragment;
+    type Varying: Smooth;
+    type Fragment: FragmentOutputs;
+
+    /// Returns the clip space position of the vertex with ATTRIBUTE, and the
+    /// varying to interpolate across its triangles.
+    fn vertex(&self, attribute: &Self::Attribute) -> (Vec4, Self::Varying);
 
-    fn vertex(&self, attribute: &Self::Attribute, varying: &mut Self::Varying) -> Vec4;
+    fn fragment(&self, position: Vec4, varying: &Self::Varying) -> Self::Fragment;
 
-    fn fragment(&self, position: Vec4, varying: &Self::Varying) -> Vec4;
+    /// Like `fragment`, but receives the full `FragmentInput`, including
+    /// screen space derivatives of the varying and the pixel and barycentric
+    /// coordinates of the fragment. Override this instead of `fragment` to
+    /// use them, e.g. for `Sampler::sample_grad`.
+    fn fragment_input(&self, input: &FragmentInput<Self::Varying>) -> Self::Fragment {
+        self.fragment(input.position, input.varying)
+    }
+}
+
+/// `ShaderProgram` made of a vertex and a fragment closure, for shaders too
+/// small to deserve a struct. Uniforms are captured by the closures.
+pub struct FnShader<A, V, VS, FS> {
+    vertex: VS,
+    fragment: FS,
+    marker: PhantomData<fn(&A) -> V>,
+}
+
+impl<A, V, VS, FS> FnShader<A, V, VS, FS>
+where
+    V: Smooth,
+    VS: Fn(&A) -> (Vec4, V),
+    FS: Fn(Vec4, &V) -> Vec4,
+{
+    pub fn new(vertex: VS, fragment: FS) -> FnShader<A, V, VS, FS> {
+        FnShader {
+            vertex,
+            fragment,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<A, V, VS, FS> ShaderProgram for FnShader<A, V, VS, FS>
+where
+    V: Smooth,
+    VS: Fn(&A) -> (Vec4, V),
+    FS: Fn(Vec4, &V) -> Vec4,
+{
+    type Attribute = A;
+    type Varying = V;
+    type Fragment = Vec4;
+
+    fn vertex(&self, attribute: &A) -> (Vec4, V) {
+        (self.vertex)(attribute)
+    }
+
+    fn fragment(&self, position: Vec4, varying: &V) -> Vec4 {
+        (self.fragment)(position, varying)
+    }
+}
+
+/// Input of the fragment shader.
+///
+/// The derivatives are computed by interpolating the varying at the
+/// differences of barycentric coordinates between pixels of an aligned 2x2
+/// quad, so they are exact for varyings that are linear in screen space.
+/// Derivatives of `Flat` fields are not meaningful.
+#[derive(Debug, Clone, Copy)]
+pub struct FragmentInput<'a, V> {
+    /// Fragment position, see `ShaderProgram::fragment`.
+    pub position: Vec4,
+    /// Interpolated varying.
+    pub varying: &'a V,
+    /// Change of the varying one pixel right in screen space, computed per
+    /// 2x2 pixel quad.
+    pub dvar_dx: &'a V,
+    /// Change of the varying one pixel up in screen space, computed per 2x2
+    /// pixel quad.
+    pub dvar_dy: &'a V,
+    /// Pixel of the attachments the fragment is written to, with row 0 as
+    /// set by `PipelineOptions::origin`.
+    pub pixel: (u32, u32),
+    /// Screen space barycentric coordinates of the fragment, the weights of
+    /// the vertices of the triangle in submission order. Not corrected for
+    /// perspective, so their isolines are straight on screen, e.g. for
+    /// wireframes.
+    pub barycentric: Vec3,
+    /// Whether the triangle is counter-clockwise on screen, i.e. front
+    /// facing for `CullFace`, e.g. to flip the normals of back faces of
+    /// two-sided materials.
+    pub front_facing: bool,
+    /// Index of the triangle in the draw call, in submission order and
+    /// counting culled triangles, i.e. the index of its first vertex divided
+    /// by 3.
+    pub primitive: u32,
+}
+
+/// Values produced by the fragment shader, written to the color attachments
+/// of the pipeline. A single `Vec4` writes one RGBA attachment, tuples and
+/// arrays write their elements to consecutive attachments.
+pub trait FragmentOutputs {
+    /// Number of color attachments written.
+    const COUNT: usize;
+
+    /// Writes the outputs to pixel X, Y of `targets`, which holds exactly
+    /// `COUNT` images. Colors are quantized with ENCODING where the target
+    /// requires it.
+    fn write<C: ColorTarget>(
+        &self,
+        targets: &mut [&mut C],
+        x: u32,
+        y: u32,
+        encoding: &ColorEncoding,
+    );
+
+    /// Alpha of the first output, used by the alpha test. Outputs without
+    /// alpha are opaque.
+    fn alpha(&self) -> f32 {
+        1.0
+    }
+
+    /// Replaces the color of the first output with F applied to it, used by
+    /// fixed-function stages like fog. Outputs without color are kept.
+    fn map_color<F: FnOnce(Vec4) -> Vec4>(&mut self, _f: F) {}
+
+    /// Word written to `Framebuffer::aux`, see `WithAux`. Outputs without
+    /// one leave the auxiliary attachment unchanged.
+    fn aux(&self) -> Option<u32> {
+        None
+    }
+}
+
+/// Fragment OUTPUTS with a word for `Framebuffer::aux`, e.g. an object ID
+/// or a quantized velocity. It is written only where OUTPUTS are, i.e. for
+/// fragments passing the depth, stencil and alpha tests.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct WithAux<F> {
+    pub outputs: F,
+    pub aux: u32,
+}
+
+impl<F: FragmentOutputs> FragmentOutputs for WithAux<F> {
+    const COUNT: usize = F::COUNT;
+
+    fn write<C: ColorTarget>(
+        &self,
+        targets: &mut [&mut C],
+        x: u32,
+        y: u32,
+        encoding: &ColorEncoding,
+    ) {
+        self.outputs.write(targets, x, y, encoding);
+    }
+
+    fn alpha(&self) -> f32 {
+        self.outputs.alpha()
+    }
+
+    fn map_color<G: FnOnce(Vec4) -> Vec4>(&mut self, f: G) {
+        self.outputs.map_color(f);
+    }
+
+    fn aux(&self) -> Option<u32> {
+        Some(self.aux)
+    }
+}
+
+impl FragmentOutputs for Vec4 {
+    const COUNT: usize = 1;
+
+    fn write<C: ColorTarget>(
+        &self,
+        targets: &mut [&mut C],
+        x: u32,
+        y: u32,
+        encoding: &ColorEncoding,
+    ) {
+        targets[0].write_color(x, y, *self, encoding);
+    }
+
+    fn alpha(&self) -> f32 {
+        self.w
+    }
+
+    fn map_color<F: FnOnce(Vec4) -> Vec4>(&mut self, f: F) {
+        *self = f(*self);
+    }
+}
+
+/// Raw storage word, e.g. for object or material IDs.
+impl FragmentOutputs for u32 {
+    const COUNT: usize = 1;
+
+    fn write<C: ColorTarget>(
+        &self,
+        targets: &mut [&mut C],
+        x: u32,
+        y: u32,
+        _encoding: &ColorEncoding,
+    ) {
+        targets[0].write_u32(x, y, *self);
+    }
+}
+
+impl<T: FragmentOutputs, const N: usize> FragmentOutputs for [T; N] {
+    const COUNT: usize = T::COUNT * N;
+
+    fn write<C: ColorTarget>(
+        &self,
+        targets: &mut [&mut C],
+        x: u32,
+        y: u32,
+        encoding: &ColorEncoding,
+    ) {
+        for (output, chunk) in self.iter().zip(targets.chunks_mut(T::COUNT)) {
+            output.write(chunk, x, y, encoding);
+        }
+    }
+
+    fn alpha(&self) -> f32 {
+        self.first().map_or(1.0, T::alpha)
+    }
+
+    fn map_color<F: FnOnce(Vec4) -> Vec4>(&mut self, f: F) {
+        if let Some(first) = self.first_mut() {
+            first.map_color(f);
+        }
+    }
+}
+
+macro_rules! impl_fragment_outputs_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: FragmentOutputs),+> FragmentOutputs for ($($name,)+) {
+            const COUNT: usize = 0 $(+ $name::COUNT)+;
+
+            fn write<Target: ColorTarget>(&self, targets: &mut [&mut Target], x: u32, y: u32, encoding: &ColorEncoding) {
+                let mut start = 0;
+                $(
+                    let end = start + $name::COUNT;
+                    self.$index.write(&mut targets[start..end], x, y, encoding);
+                    start = end;
+                )+
+                let _ = start;
+            }
+
+            fn alpha(&self) -> f32 {
+                self.0.alpha()
+            }
+
+            fn map_color<F: FnOnce(Vec4) -> Vec4>(&mut self, f: F) {
+                self.0.map_color(f);
+            }
+        }
+    };
+}
+
+impl_fragment_outputs_tuple!(A 0);
+impl_fragment_outputs_tuple!(A 0, B 1);
+impl_fragment_outputs_tuple!(A 0, B 1, C 2);
+impl_fragment_outputs_tuple!(A 0, B 1, C 2, D 3);
+
+/// For shaders without varyings, e.g. depth-only passes. Interpolating is
+/// free, so `Pipeline` doesn't need to special-case them.
+impl Smooth for () {
+    fn interpolate(_a: &(), _b: &(), _c: &(), _bc: Vec3) {}
 }
 
 impl Smooth for f32 {
@@ -49,3 +333,245 @@ impl Smooth for Vec4 {
         )
     }
 }
+
+impl<T: Smooth, const N: usize> Smooth for [T; N] {
+    fn interpolate(a: &[T; N], b: &[T; N], c: &[T; N], bc: Vec3) -> [T; N] {
+        std::array::from_fn(|i| T::interpolate(&a[i], &b[i], &c[i], bc))
+    }
+
+    fn interpolate_perspective(
+        a: &[T; N],
+        b: &[T; N],
+        c: &[T; N],
+        bc_screen: Vec3,
+        bc_persp: Vec3,
+    ) -> [T; N] {
+        std::array::from_fn(|i| {
+            T::interpolate_perspective(&a[i], &b[i], &c[i], bc_screen, bc_persp)
+        })
+    }
+}
+
+macro_rules! impl_smooth_tuple {
+    ($($name:ident $index:tt),+) => {
+        impl<$($name: Smooth),+> Smooth for ($($name,)+) {
+            fn interpolate(a: &Self, b: &Self, c: &Self, bc: Vec3) -> Self {
+                ($($name::interpolate(&a.$index, &b.$index, &c.$index, bc),)+)
+            }
+
+            fn interpolate_perspective(a: &Self, b: &Self, c: &Self, bc_screen: Vec3, bc_persp: Vec3) -> Self {
+                ($($name::interpolate_perspective(&a.$index, &b.$index, &c.$index, bc_screen, bc_persp),)+)
+            }
+        }
+    };
+}
+
+impl_smooth_tuple!(A 0);
+impl_smooth_tuple!(A 0, B 1);
+impl_smooth_tuple!(A 0, B 1, C 2);
+impl_smooth_tuple!(A 0, B 1, C 2, D 3);
+impl_smooth_tuple!(A 0, B 1, C 2, D 3, E 4);
+impl_smooth_tuple!(A 0, B 1, C 2, D 3, E 4, F 5);
+impl_smooth_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6);
+impl_smooth_tuple!(A 0, B 1, C 2, D 3, E 4, F 5, G 6, H 7);
+
+impl Smooth for Mat3 {
+    fn interpolate(a: &Mat3, b: &Mat3, c: &Mat3, bc: Vec3) -> Mat3 {
+        Mat3::from_cols(
+            Vec3::interpolate(&a.x_axis, &b.x_axis, &c.x_axis, bc),
+            Vec3::interpolate(&a.y_axis, &b.y_axis, &c.y_axis, bc),
+            Vec3::interpolate(&a.z_axis, &b.z_axis, &c.z_axis, bc),
+        )
+    }
+}
+
+impl Smooth for Mat4 {
+    fn interpolate(a: &Mat4, b: &Mat4, c: &Mat4, bc: Vec3) -> Mat4 {
+        Mat4::from_cols(
+            Vec4::interpolate(&a.x_axis, &b.x_axis, &c.x_axis, bc),
+            Vec4::interpolate(&a.y_axis, &b.y_axis, &c.y_axis, bc),
+            Vec4::interpolate(&a.z_axis, &b.z_axis, &c.z_axis, bc),
+            Vec4::interpolate(&a.w_axis, &b.w_axis, &c.w_axis, bc),
+        )
+    }
+}
+
+/// Blends the components and normalizes the result. This approximates
+/// spherical interpolation well only when the rotations are close to each
+/// other, and the vertices should agree on the sign of their quaternions, as
+/// Q and -Q are the same rotation but blend to very different ones.
+impl Smooth for Quat {
+    fn interpolate(a: &Quat, b: &Quat, c: &Quat, bc: Vec3) -> Quat {
+        Quat::from_xyzw(
+            f32::interpolate(&a.x, &b.x, &c.x, bc),
+            f32::interpolate(&a.y, &b.y, &c.y, bc),
+            f32::interpolate(&a.z, &b.z, &c.z, bc),
+            f32::interpolate(&a.w, &b.w, &c.w, bc),
+        )
+        .normalize()
+    }
+}
+
+/// Varying wrapper that is not interpolated, but takes the value of the first
+/// vertex of the triangle, like GLSL's `flat` qualifier.
+#[repr(transparent)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Flat<T>(pub T);
+
+impl<T> Deref for Flat<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Flat<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Clone> Smooth for Flat<T> {
+    fn interpolate(a: &Flat<T>, _b: &Flat<T>, _c: &Flat<T>, _bc: Vec3) -> Flat<T> {
+        a.clone()
+    }
+
+    fn interpolate_perspective(
+        a: &Flat<T>,
+        _b: &Flat<T>,
+        _c: &Flat<T>,
+        _bc_screen: Vec3,
+        _bc_persp: Vec3,
+    ) -> Flat<T> {
+        a.clone()
+    }
+}
+
+/// Varying wrapper that is always interpolated linearly in screen space, even
+/// when perspective correction is enabled, like GLSL's `noperspective`
+/// qualifier.
+#[repr(transparent)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct NoPerspective<T>(pub T);
+
+impl<T> Deref for NoPerspective<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for NoPerspective<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+impl<T: Smooth> Smooth for NoPerspective<T> {
+    fn interpolate(
+        a: &NoPerspective<T>,
+        b: &NoPerspective<T>,
+        c: &NoPerspective<T>,
+        bc: Vec3,
+    ) -> NoPerspective<T> {
+        NoPerspective(T::interpolate(&a.0, &b.0, &c.0, bc))
+    }
+
+    fn interpolate_perspective(
+        a: &NoPerspective<T>,
+        b: &NoPerspective<T>,
+        c: &NoPerspective<T>,
+        bc_screen: Vec3,
+        _bc_persp: Vec3,
+    ) -> NoPerspective<T> {
+        NoPerspective(T::interpolate(&a.0, &b.0, &c.0, bc_screen))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn smooth_matrices_and_quaternions() {
+        // Weights summing to one exactly, so identical values are returned
+        // as is
+        let bc = Vec3::new(0.5, 0.25, 0.25);
+        let mat3 = Mat3::from_cols_array(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0, 8.0, 9.0]);
+        let mat4 = Mat4::from_cols_array(&[
+            1.0, -2.0, 3.0, -4.0, 5.0, -6.0, 7.0, -8.0, 9.0, -10.0, 11.0, -12.0, 13.0, -14.0, 15.0,
+            -16.0,
+        ]);
+        let quat = Quat::from_xyzw(0.5, -0.5, 0.5, 0.5);
+        assert_eq!(Mat3::interpolate(&mat3, &mat3, &mat3, bc), mat3);
+        assert_eq!(Mat4::interpolate(&mat4, &mat4, &mat4, bc), mat4);
+        assert_eq!(Quat::interpolate(&quat, &quat, &quat, bc), quat);
+
+        let a = Mat3::from_cols(Vec3::X, Vec3::Y, Vec3::Z);
+        let b = Mat3::from_cols(Vec3::Y, Vec3::Z, Vec3::X);
+        let c = Mat3::from_cols(Vec3::Z, Vec3::X, Vec3::Y);
+        assert_eq!(
+            Mat3::interpolate(&a, &b, &c, bc),
+            Mat3::from_cols(
+                Vec3::new(0.5, 0.25, 0.25),
+                Vec3::new(0.25, 0.5, 0.25),
+                Vec3::new(0.25, 0.25, 0.5),
+            )
+        );
+
+        // Halfway between two rotations about the same axis
+        let half = Quat::interpolate(
+            &Quat::IDENTITY,
+            &Quat::from_rotation_z(1.0),
+            &Quat::IDENTITY,
+            Vec3::new(0.5, 0.5, 0.0),
+        );
+        assert!(half.abs_diff_eq(Quat::from_rotation_z(0.5), 1e-6));
+        assert!((half.length() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn smooth_arrays_and_tuples_are_element_wise() {
+        let mut seed = 0x9e37_79b9_u32;
+        let mut random = move || {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            (seed >> 8) as f32 / (1 << 24) as f32
+        };
+
+        for _ in 0..100 {
+            // Random weights summing to one, and other weights to tell which
+            // ones the fields are interpolated with
+            let (x, y) = (random(), random());
+            let (x, y) = if x + y > 1.0 {
+                (1.0 - x, 1.0 - y)
+            } else {
+                (x, y)
+            };
+            let bc = Vec3::new(x, y, 1.0 - x - y);
+            let bc_persp = Vec3::new(y, 1.0 - x - y, x);
+
+            let arrays = [(); 3].map(|_| [(); 4].map(|_| Vec2::new(random(), random())));
+            let [a, b, c] = &arrays;
+            let expected: Vec<Vec2> = (0..4)
+                .map(|i| Vec2::interpolate(&a[i], &b[i], &c[i], bc))
+                .collect();
+            assert_eq!(<[Vec2; 4]>::interpolate(a, b, c, bc)[..], expected[..]);
+
+            let tuples = [(); 3].map(|_| {
+                (
+                    random(),
+                    Vec2::new(random(), random()),
+                    NoPerspective(random()),
+                )
+            });
+            let [a, b, c] = &tuples;
+            let (first, second, third) =
+                <(f32, Vec2, NoPerspective<f32>)>::interpolate_perspective(a, b, c, bc, bc_persp);
+            assert_eq!(first, f32::interpolate(&a.0, &b.0, &c.0, bc_persp));
+            assert_eq!(second, Vec2::interpolate(&a.1, &b.1, &c.1, bc_persp));
+            assert_eq!(third.0, f32::interpolate(&a.2 .0, &b.2 .0, &c.2 .0, bc));
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use glam::{Mat4, Vec2, Vec3, Vec4};
+
+use crate::image::{Image, Sampler};
+use crate::mesh::Vertex;
+use crate::shader::{ShaderProgram, Smooth};
+
+/// Transforms vertices by `u_mvp` and interpolates their colors, without
+/// lighting.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct UnlitColor {
+    pub u_mvp: Mat4,
+}
+
+/// Vertex of `UnlitColor`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct UnlitColorAttribute {
+    pub pos: Vec4,
+    pub color: Vec4,
+}
+
+impl UnlitColor {
+    pub fn new(mvp: Mat4) -> UnlitColor {
+        UnlitColor { u_mvp: mvp }
+    }
+
+    /// Creates the shader with an identity transform, so that positions are
+    /// given in clip space.
+    pub fn identity() -> UnlitColor {
+        UnlitColor::new(Mat4::IDENTITY)
+    }
+}
+
+impl UnlitColorAttribute {
+    pub fn new(pos: Vec3, color: Vec4) -> UnlitColorAttribute {
+        UnlitColorAttribute {
+            pos: Vec4::new(pos.x, pos.y, pos.z, 1.0),
+            color,
+        }
+    }
+
+    /// Creates vertices at POSITIONS with a single COLOR.
+    pub fn solid(positions: &[Vec3], color: Vec4) -> Vec<UnlitColorAttribute> {
+        positions
+            .iter()
+            .map(|&pos| UnlitColorAttribute::new(pos, color))
+            .collect()
+    }
+}
+
+impl ShaderProgram for UnlitColor {
+    type Attribute = UnlitColorAttribute;
+    type Varying = Vec4;
+    type Fragment = Vec4;
+
+    fn vertex(&self, attribute: &UnlitColorAttribute) -> (Vec4, Vec4) {
+        (self.u_mvp * attribute.pos, attribute.color)
+    }
+
+    fn fragment(&self, _position: Vec4, color: &Vec4) -> Vec4 {
+        *color
+    }
+}
+
+/// Samples a texture and lights it with a directional light, using the
+/// Lambertian (diffuse) term only.
+#[derive(Debug, Clone)]
+pub struct TexturedLambert {
+    u_proj: Mat4,
+    u_view: Mat4,
+    u_model: Mat4,
+    /// Inverse transpose of `u_model`, transforming normals.
+    u_normal: Mat4,
+    u_light_dir: Vec3,
+    u_texture: Image,
+    u_sampler: Sampler,
+}
+
+/// Varying of `TexturedLambert`.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct TexturedLambertVarying {
+    pub uv: Vec2,
+    pub light_intensity: f32,
+}
+
+impl Smooth for TexturedLambertVarying {
+    fn interpolate(
+        a: &TexturedLambertVarying,
+        b: &TexturedLambertVarying,
+        c: &TexturedLambertVarying,
+        bc: Vec3,
+    ) -> TexturedLambertVarying {
+        TexturedLambertVarying {
+            uv: Vec2::interpolate(&a.uv, &b.uv, &c.uv, bc),
+            light_intensity: f32::interpolate(
+                &a.light_intensity,
+                &b.light_intensity,
+                &c.light_intensity,
+                bc,
+            ),
+        }
+    }
+}
+
+impl TexturedLambert {
+    /// Creates the shader with an identity model transform, light shining
+    /// along -Z and nearest texture sampling.
+    pub fn new(proj: Mat4, view: Mat4, texture: Image) -> TexturedLambert {
+        TexturedLambert {
+            u_proj: proj,
+            u_view: view,
+            u_model: Mat4::IDENTITY,
+            u_normal: Mat4::IDENTITY,
+            u_light_dir: Vec3::new(0.0, 0.0, 1.0),
+            u_texture: texture,
+            u_sampler: Sampler::default(),
+        }
+    }
+
+    pub fn set_proj(&mut self, proj: Mat4) {
+        self.u_proj = proj;
+    }
+
+    pub fn set_view(&mut self, view: Mat4) {
+        self.u_view = view;
+    }
+
+    pub fn set_model(&mut self, model: Mat4) {
+        self.u_model = model;
+        self.u_normal = model.inverse().transpose();
+    }
+
+    /// Sets the world space direction towards the light.
+    pub fn set_light_dir(&mut self, light_dir: Vec3) {
+        self.u_light_dir = light_dir.normalize();
+    }
+
+    pub fn set_texture(&mut self, texture: Image) {
+        self.u_texture = texture;
+    }
+
+    pub fn set_sampler(&mut self, sampler: Sampler) {
+        self.u_sampler = sampler;
+    }
+}
+
+impl ShaderProgram for TexturedLambert {
+    type Attribute = Vertex;
+    type Varying = TexturedLambertVarying;
+    type Fragment = Vec4;
+
+    fn vertex(&self, attribute: &Vertex) -> (Vec4, TexturedLambertVarying) {
+        let normal = self.u_normal.transform_vector3(attribute.norm).normalize();
+        let varying = TexturedLambertVarying {
+            uv: attribute.uv,
+            light_intensity: normal.dot(self.u_light_dir).max(0.0),
+        };
+
+        (
+            self.u_proj * self.u_view * self.u_model * attribute.pos.extend(1.0),
+            varying,
+        )
+    }
+
+    fn fragment(&self, _position: Vec4, varying: &TexturedLambertVarying) -> Vec4 {
+        let color = self.u_sampler.sample(&self.u_texture, varying.uv) * varying.light_intensity;
+        Vec4::new(color.x, color.y, color.z, 1.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Pipeline, PipelineOptions};
+
+    /// Renders a triangle over the lower left half of an 8x8 image, with
+    /// red, green and blue vertices, and returns the color at screen space
+    /// pixel X, Y.
+    fn render(shader: &UnlitColor) -> impl Fn(u32, u32) -> [u8; 4] {
+        let buffer = [
+            UnlitColorAttribute::new(Vec3::new(-1.0, -1.0, 0.0), Vec4::new(1.0, 0.0, 0.0, 1.0)),
+            UnlitColorAttribute::new(Vec3::new(1.0, -1.0, 0.0), Vec4::new(0.0, 1.0, 0.0, 1.0)),
+            UnlitColorAttribute::new(Vec3::new(-1.0, 1.0, 0.0), Vec4::new(0.0, 0.0, 1.0, 1.0)),
+        ];
+        let mut image_color = Image::new(8, 8);
+        let mut image_depth = Image::from_pixel_depth(8, 8, 1.0);
+        Pipeline::with_options(PipelineOptions::default()).triangles(
+            shader,
+            &buffer,
+            &mut image_color,
+            &mut image_depth,
+        );
+
+        move |x, y| image_color.pixel_rgba(x, 7 - y)
+    }
+
+    #[test]
+    fn unlit_color_interpolates_colors() {
+        let pixel = render(&UnlitColor::identity());
+        assert_eq!(pixel(0, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(4, 0), [128, 128, 0, 255]);
+        assert_eq!(pixel(0, 4), [128, 0, 128, 255]);
+        assert_eq!(pixel(4, 4), [0, 128, 128, 255]);
+        assert_eq!(pixel(2, 2), [128, 64, 64, 255]);
+        assert_eq!(pixel(5, 5), [0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn unlit_color_transforms_by_mvp() {
+        // Half the screen to the right
+        let shader = UnlitColor::new(Mat4::from_translation(Vec3::X));
+        let pixel = render(&shader);
+        assert_eq!(pixel(3, 0), [0, 0, 0, 0]);
+        assert_eq!(pixel(4, 0), [255, 0, 0, 255]);
+        assert_eq!(pixel(6, 0), [191, 64, 0, 255]);
+    }
+}
@@ -0,0 +1,195 @@
+use glam::{Vec2, Vec4};
+
+use crate::image::{DepthPixel, RenderTarget};
+use crate::{bounding_box, Origin};
+
+/// Width and height of the screen space tiles the rasterizer works on. Even,
+/// so that the 2x2 quads of `Pipeline::triangle` never straddle tiles.
+pub const TILE_SIZE: u32 = 64;
+
+/// Primitive index and screen space triangle with the varyings of its
+/// vertices.
+type Triangle<V> = (u32, (Vec4, Vec4, Vec4), (V, V, V));
+
+/// `Triangle` with borrowed varyings.
+type TriangleRef<'a, V> = (u32, (Vec4, Vec4, Vec4), (&'a V, &'a V, &'a V));
+
+/// Screen space triangles produced by the geometry phase with their
+/// varyings, binned to the tiles their bounding boxes overlap.
+pub struct Bins<V> {
+    width: u32,
+    height: u32,
+    tiles_x: u32,
+    triangles: Vec<Triangle<V>>,
+    /// Indices into `triangles` for each tile, row by row, in submission
+    /// order.
+    bins: Vec<Vec<usize>>,
+}
+
+impl<V> Bins<V> {
+    pub fn new(width: u32, height: u32) -> Bins<V> {
+        let tiles_x = width.div_ceil(TILE_SIZE);
+        let tiles_y = height.div_ceil(TILE_SIZE);
+
+        Bins {
+            width,
+            height,
+            tiles_x,
+            triangles: Vec::new(),
+            bins: vec![Vec::new(); tiles_x as usize * tiles_y as usize],
+        }
+    }
+
+    /// Adds triangle PRIMITIVE to all tiles it overlaps. Triangles entirely
+    /// outside the screen are dropped.
+    pub fn push(&mut self, primitive: u32, (a, b, c): (Vec4, Vec4, Vec4), vars: (V, V, V)) {
+        let a2 = Vec2::new(a.x, a.y);
+        let b2 = Vec2::new(b.x, b.y);
+        let c2 = Vec2::new(c.x, c.y);
+
+        let (minx, miny, maxx, maxy) = match bounding_box(a2, b2, c2, self.width, self.height, 0.0)
+        {
+            Some(bounds) => bounds,
+            None => return,
+        };
+
+        let index = self.triangles.len();
+        self.triangles.push((primitive, (a, b, c), vars));
+
+        for ty in miny / TILE_SIZE..=maxy / TILE_SIZE {
+            for tx in minx / TILE_SIZE..=maxx / TILE_SIZE {
+                self.bins[(ty * self.tiles_x + tx) as usize].push(index);
+            }
+        }
+    }
+
+    /// Returns the primitive index and triangle at INDEX, as stored in the
+    /// tiles.
+    pub fn triangle(&self, index: usize) -> TriangleRef<'_, V> {
+        let (primitive, screen, (va, vb, vc)) = &self.triangles[index];
+        (*primitive, *screen, (va, vb, vc))
+    }
+
+    /// Iterates the tiles overlapped by at least one triangle, returning
+    /// their inclusive screen space min and max corners and triangle
+    /// indices.
+    pub fn tiles(&self) -> impl Iterator<Item = ((u32, u32, u32, u32), &[usize])> + '_ {
+        self.bins
+            .iter()
+            .enumerate()
+            .filter(|(_, bin)| !bin.is_empty())
+            .map(move |(i, bin)| {
+                let (tx, ty) = (i as u32 % self.tiles_x, i as u32 / self.tiles_x);
+                (tile_bounds(tx, ty, self.width, self.height), &bin[..])
+            })
+    }
+}
+
+/// Returns the inclusive screen space min and max corners of tile TX, TY of
+/// a WIDTH x HEIGHT framebuffer.
+pub fn tile_bounds(tx: u32, ty: u32, width: u32, height: u32) -> (u32, u32, u32, u32) {
+    let x = tx * TILE_SIZE;
+    let y = ty * TILE_SIZE;
+    let maxx = (x + TILE_SIZE).min(width) - 1;
+    let maxy = (y + TILE_SIZE).min(height) - 1;
+
+    (x, y, maxx, maxy)
+}
+
+/// Width and height of the blocks of `CoarseDepth`. Divides `TILE_SIZE`, so
+/// blocks never straddle tiles.
+pub const BLOCK_SIZE: u32 = 8;
+
+const TILE_BLOCKS: u32 = TILE_SIZE / BLOCK_SIZE;
+
+/// Maximum stored depth of the `BLOCK_SIZE` square blocks of a tile, used to
+/// reject whole blocks a triangle is behind before testing their pixels.
+///
+/// Blocks are read from the depth attachment when first needed and kept up
+/// to date as the pipeline writes depth, so they must not outlive a draw.
+pub struct CoarseDepth {
+    bounds: (u32, u32, u32, u32),
+    origin: Origin,
+    blocks: [Option<Block>; (TILE_BLOCKS * TILE_BLOCKS) as usize],
+}
+
+#[derive(Clone, Copy)]
+struct Block {
+    max: f32,
+    /// Number of pixels storing `max`.
+    count: u32,
+}
+
+impl CoarseDepth {
+    /// Creates an empty structure for the tile with BOUNDS, see
+    /// `Bins::tiles`, of attachments stored with ORIGIN.
+    pub fn new(bounds: (u32, u32, u32, u32), origin: Origin) -> CoarseDepth {
+        CoarseDepth {
+            bounds,
+            origin,
+            blocks: [None; (TILE_BLOCKS * TILE_BLOCKS) as usize],
+        }
+    }
+
+    /// Returns the inclusive screen space min and max corners of the tile.
+    pub fn bounds(&self) -> (u32, u32, u32, u32) {
+        self.bounds
+    }
+
+    /// Returns the maximum depth stored in IMAGE_DEPTH within the block
+    /// containing screen space pixel X, Y.
+    pub fn max<D>(&mut self, image_depth: &D, x: u32, y: u32) -> f32
+    where
+        D: RenderTarget,
+        D::Pixel: DepthPixel,
+    {
+        let (minx, miny, maxx, maxy) = self.bounds;
+        let (bx, by) = ((x - minx) / BLOCK_SIZE, (y - miny) / BLOCK_SIZE);
+        let origin = self.origin;
+
+        let block = self.blocks[(by * TILE_BLOCKS + bx) as usize].get_or_insert_with(|| {
+            let height = image_depth.dimensions().1;
+            let x0 = minx + bx * BLOCK_SIZE;
+            let y0 = miny + by * BLOCK_SIZE;
+
+            let mut block = Block {
+                max: f32::NEG_INFINITY,
+                count: 0,
+            };
+            for y in y0..=(y0 + BLOCK_SIZE - 1).min(maxy) {
+                for x in x0..=(x0 + BLOCK_SIZE - 1).min(maxx) {
+                    let depth = image_depth.pixel(x, origin.row(y, height)).to_depth();
+                    if depth > block.max {
+                        block = Block {
+                            max: depth,
+                            count: 1,
+                        };
+                    } else if depth == block.max {
+                        block.count += 1;
+                    }
+                }
+            }
+
+            block
+        });
+
+        block.max
+    }
+
+    /// Records that the depth of screen space pixel X, Y, which used to be
+    /// OLD, was lowered.
+    pub fn lower(&mut self, x: u32, y: u32, old: f32) {
+        let (minx, miny, _, _) = self.bounds;
+        let (bx, by) = ((x - minx) / BLOCK_SIZE, (y - miny) / BLOCK_SIZE);
+
+        let entry = &mut self.blocks[(by * TILE_BLOCKS + bx) as usize];
+        if let Some(block) = entry {
+            if old == block.max {
+                block.count -= 1;
+                if block.count == 0 {
+                    *entry = None;
+                }
+            }
+        }
+    }
+}